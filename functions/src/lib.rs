@@ -3,8 +3,16 @@
 
 extern crate alloc;
 
-use alloc::vec::Vec;
-use shared::User;
+use alloc::{string::String, vec::Vec};
+use shared::{
+    aggregate::AggregateState,
+    case_fold,
+    document::{Document, DocumentValue},
+    plan::PlanState,
+    substring_search,
+    top_k::TopK,
+    ProjectedValue, User,
+};
 
 // ======
 // Misc
@@ -18,20 +26,6 @@ pub unsafe extern "C" fn fn_sig_bad(_vec: &[User]) -> Vec<User> {
     unimplemented!()
 }
 
-#[no_mangle]
-#[inline(always)]
-pub unsafe extern "C" fn fn_sig(_vec: &[User], _output_vec: *mut Vec<User>) {
-    // Function used purely for copying the function signature in LLVM
-    unimplemented!()
-}
-
-#[no_mangle]
-#[inline(always)]
-pub extern "C" fn filter_fn_sig(_user: &User) -> bool {
-    // Function used purely for copying the function signature in LLVM
-    unimplemented!()
-}
-
 #[no_mangle]
 #[inline(always)]
 pub extern "C" fn test_return_str() -> &'static str {
@@ -57,21 +51,540 @@ pub extern "C" fn separated_str_as_str(separated: *mut SeparatedStr) -> &'static
     }
 }
 
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn str_as_ptr(s: &str) -> *const u8 {
+    s.as_ptr()
+}
+
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn str_len(s: &str) -> u64 {
+    s.len() as u64
+}
+
+// ======
+// Records
+// ======
+
+/// Exports the signatures and runners the JIT needs for a record type, with every symbol name
+/// prefixed by the record's `Record::SYMBOL_PREFIX`. The runners are only ever used inlined
+/// into compiled code.
+macro_rules! record_functions {
+    ($module:ident, $prefix:literal, $record:ty) => {
+        pub mod $module {
+            use super::*;
+
+            #[export_name = concat!($prefix, "fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn fn_sig(_vec: &[$record], _output_vec: *mut Vec<$record>) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "indices_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn indices_fn_sig(
+                _vec: &[$record],
+                _output_vec: *mut Vec<usize>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "refs_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn refs_fn_sig<'a>(
+                _vec: &'a [$record],
+                _output_vec: *mut Vec<&'a $record>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "projection_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn projection_fn_sig<'a>(
+                _vec: &'a [$record],
+                _output_vec: *mut Vec<ProjectedValue<'a>>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "aggregation_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn aggregation_fn_sig<'a>(
+                _vec: &'a [$record],
+                _state: *mut AggregateState<'a>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "ordered_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn ordered_fn_sig<'a>(
+                _vec: &'a [$record],
+                _state: *mut TopK<'a, $record>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "quantifier_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn quantifier_fn_sig(_vec: &[$record], _output: *mut bool) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "count_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn count_fn_sig(_vec: &[$record], _output: *mut usize) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "find_first_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn find_first_fn_sig<'a>(
+                _vec: &'a [$record],
+                _output: *mut Option<&'a $record>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "plan_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn plan_fn_sig<'a>(
+                _vec: &'a [$record],
+                _state: *mut PlanState<'a, $record>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "bitmap_fn_sig")]
+            #[inline(always)]
+            pub unsafe extern "C" fn bitmap_fn_sig(_vec: &[$record], _bitmap: *mut u64) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "filter_fn_sig")]
+            #[inline(always)]
+            pub extern "C" fn filter_fn_sig(_user: &$record) -> bool {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "project_fn_sig")]
+            #[inline(always)]
+            pub extern "C" fn project_fn_sig<'a>(
+                _user: &'a $record,
+                _output_vec: *mut Vec<ProjectedValue<'a>>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "aggregate_fn_sig")]
+            #[inline(always)]
+            pub extern "C" fn aggregate_fn_sig<'a>(
+                _user: &'a $record,
+                _state: *mut AggregateState<'a>,
+            ) {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "sort_less_fn_sig")]
+            #[inline(always)]
+            pub extern "C" fn sort_less_fn_sig(_a: &$record, _b: &$record) -> bool {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            #[export_name = concat!($prefix, "plan_row_fn_sig")]
+            #[inline(always)]
+            pub extern "C" fn plan_row_fn_sig<'a>(
+                _user: &'a $record,
+                _state: *mut PlanState<'a, $record>,
+            ) -> bool {
+                // Function used purely for copying the function signature in LLVM
+                unimplemented!()
+            }
+
+            // ======
+            // Processing
+            // ======
+
+            #[export_name = concat!($prefix, "run_filter")]
+            #[inline(always)]
+            pub extern "C" fn run_filter(
+                vec: &[$record],
+                output_vec: *mut Vec<$record>, // Expects null
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                unsafe {
+                    *output_vec = Vec::new();
+                    (*output_vec).extend(vec.iter().filter(|user| filter(user)).cloned());
+                }
+            }
+
+            #[export_name = concat!($prefix, "run_filter_indices")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_indices(
+                vec: &[$record],
+                output_vec: *mut Vec<usize>, // Expects null
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                unsafe {
+                    *output_vec = Vec::new();
+                    (*output_vec).extend(
+                        vec.iter()
+                            .enumerate()
+                            .filter(|(_, user)| filter(user))
+                            .map(|(index, _)| index),
+                    );
+                }
+            }
+
+            #[export_name = concat!($prefix, "run_filter_refs")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_refs<'a>(
+                vec: &'a [$record],
+                output_vec: *mut Vec<&'a $record>, // Expects null
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                unsafe {
+                    *output_vec = Vec::new();
+                    (*output_vec).extend(vec.iter().filter(|user| filter(user)));
+                }
+            }
+
+            #[export_name = concat!($prefix, "run_filter_project")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_project<'a>(
+                vec: &'a [$record],
+                output_vec: *mut Vec<ProjectedValue<'a>>, // Expects null
+                filter: extern "C" fn(&$record) -> bool,
+                project: extern "C" fn(&'a $record, *mut Vec<ProjectedValue<'a>>),
+            ) {
+                unsafe {
+                    *output_vec = Vec::new();
+                    for user in vec {
+                        if filter(user) {
+                            project(user, output_vec);
+                        }
+                    }
+                }
+            }
+
+            #[export_name = concat!($prefix, "run_filter_any")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_any(
+                vec: &[$record],
+                output: *mut bool,
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                for user in vec {
+                    if filter(user) {
+                        unsafe { *output = true };
+                        return;
+                    }
+                }
+                unsafe { *output = false };
+            }
+
+            #[export_name = concat!($prefix, "run_filter_all")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_all(
+                vec: &[$record],
+                output: *mut bool,
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                for user in vec {
+                    if !filter(user) {
+                        unsafe { *output = false };
+                        return;
+                    }
+                }
+                unsafe { *output = true };
+            }
+
+            #[export_name = concat!($prefix, "run_filter_count")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_count(
+                vec: &[$record],
+                output: *mut usize,
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                // Adding the result rather than branching on it, like the bitmap
+                let mut count = 0;
+                for user in vec {
+                    count += filter(user) as usize;
+                }
+                unsafe { *output = count };
+            }
+
+            #[export_name = concat!($prefix, "run_filter_find_first")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_find_first<'a>(
+                vec: &'a [$record],
+                output: *mut Option<&'a $record>,
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                for user in vec {
+                    if filter(user) {
+                        unsafe { *output = Some(user) };
+                        return;
+                    }
+                }
+                unsafe { *output = None };
+            }
+
+            #[export_name = concat!($prefix, "run_filter_aggregate")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_aggregate<'a>(
+                vec: &'a [$record],
+                state: *mut AggregateState<'a>, // Expects an initialized state
+                filter: extern "C" fn(&$record) -> bool,
+                aggregate: extern "C" fn(&'a $record, *mut AggregateState<'a>),
+            ) {
+                for user in vec {
+                    if filter(user) {
+                        aggregate(user, state);
+                    }
+                }
+            }
+
+            #[export_name = concat!($prefix, "run_filter_limit")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_limit<'a>(
+                vec: &'a [$record],
+                state: *mut TopK<'a, $record>, // Expects an empty state
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                let state = unsafe { &mut *state };
+                for user in vec {
+                    // Without an order, the first matches are as good as any, so stop once
+                    // there's enough
+                    if state.is_full() {
+                        break;
+                    }
+                    if filter(user) {
+                        state.push_unordered(user);
+                    }
+                }
+            }
+
+            #[export_name = concat!($prefix, "run_filter_top_k")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_top_k<'a>(
+                vec: &'a [$record],
+                state: *mut TopK<'a, $record>, // Expects an empty state
+                filter: extern "C" fn(&$record) -> bool,
+                less: extern "C" fn(&$record, &$record) -> bool,
+            ) {
+                let state = unsafe { &mut *state };
+                for user in vec {
+                    if filter(user) {
+                        state.push(user, |a, b| less(a, b));
+                    }
+                }
+                state.sort(|a, b| less(a, b));
+            }
+
+            #[export_name = concat!($prefix, "run_filter_bitmap")]
+            #[inline(always)]
+            pub extern "C" fn run_filter_bitmap(
+                vec: &[$record],
+                bitmap: *mut u64, // Expects one word for every 64 users
+                filter: extern "C" fn(&$record) -> bool,
+            ) {
+                for (word_index, users) in vec.chunks(64).enumerate() {
+                    // Build the word without branching on the filter's result, bit `n` is user `n`
+                    let mut word = 0;
+                    for (bit, user) in users.iter().enumerate() {
+                        word |= (filter(user) as u64) << bit;
+                    }
+                    unsafe {
+                        *bitmap.add(word_index) = word;
+                    }
+                }
+            }
+
+            /// Run the first stage of a plan over the input. `row` returns false once
+            /// nothing more can get through the stage
+            #[export_name = concat!($prefix, "run_plan_scan")]
+            #[inline(always)]
+            pub extern "C" fn run_plan_scan<'a>(
+                vec: &'a [$record],
+                state: *mut PlanState<'a, $record>,
+                row: extern "C" fn(&'a $record, *mut PlanState<'a, $record>) -> bool,
+            ) {
+                for user in vec {
+                    if !row(user, state) {
+                        break;
+                    }
+                }
+            }
+
+            /// Run a later stage of a plan over the rows collected by the sort at `index`
+            #[export_name = concat!($prefix, "run_plan_sorted")]
+            #[inline(always)]
+            pub extern "C" fn run_plan_sorted<'a>(
+                state: *mut PlanState<'a, $record>,
+                index: usize,
+                less: extern "C" fn(&$record, &$record) -> bool,
+                row: extern "C" fn(&'a $record, *mut PlanState<'a, $record>) -> bool,
+            ) {
+                let state = unsafe { &mut *state };
+                let sorted = &mut state.sorted[index];
+                sorted.sort(|a, b| less(a, b));
+                // The rows are moved out, as `row` gets the state again
+                let rows = core::mem::take(&mut sorted.rows);
+                for user in rows {
+                    if !row(user, state) {
+                        break;
+                    }
+                }
+            }
+
+            // ======
+            // Plans
+            // ======
+
+            #[export_name = concat!($prefix, "plan_take_limit")]
+            #[inline(always)]
+            pub extern "C" fn plan_take_limit(
+                state: *mut PlanState<$record>,
+                index: usize,
+            ) -> bool {
+                unsafe { (*state).take_limit(index) }
+            }
+
+            #[export_name = concat!($prefix, "plan_push_sorted")]
+            #[inline(always)]
+            pub extern "C" fn plan_push_sorted<'a>(
+                state: *mut PlanState<'a, $record>,
+                index: usize,
+                user: &'a $record,
+                less: extern "C" fn(&$record, &$record) -> bool,
+            ) {
+                let state = unsafe { &mut *state };
+                state.sorted[index].push(user, |a, b| less(a, b));
+            }
+
+            #[export_name = concat!($prefix, "plan_push_row")]
+            #[inline(always)]
+            pub extern "C" fn plan_push_row<'a>(
+                state: *mut PlanState<'a, $record>,
+                user: &'a $record,
+            ) {
+                unsafe { (*state).rows.push(user) }
+            }
+
+            #[export_name = concat!($prefix, "plan_state_projected")]
+            #[inline(always)]
+            pub extern "C" fn plan_state_projected<'a>(
+                state: *mut PlanState<'a, $record>,
+            ) -> *mut Vec<ProjectedValue<'a>> {
+                unsafe { &mut (*state).projected }
+            }
+
+            #[export_name = concat!($prefix, "plan_state_aggregate")]
+            #[inline(always)]
+            pub extern "C" fn plan_state_aggregate<'a>(
+                state: *mut PlanState<'a, $record>,
+            ) -> *mut AggregateState<'a> {
+                unsafe { &mut (*state).aggregate }
+            }
+        }
+    };
+}
+
+record_functions!(users, "", User);
+record_functions!(documents, "document_", Document);
+
 // ======
-// Processing
+// Projection
 // ======
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn run_filter(
-    vec: &[User],
-    output_vec: *mut Vec<User>, // Expects null
-    filter: extern "C" fn(&User) -> bool,
+pub extern "C" fn project_push_str<'a>(output_vec: *mut Vec<ProjectedValue<'a>>, s: &'a str) {
+    unsafe { (*output_vec).push(ProjectedValue::Str(s)) }
+}
+
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn project_push_int(output_vec: *mut Vec<ProjectedValue>, n: u64) {
+    unsafe { (*output_vec).push(ProjectedValue::Int(n)) }
+}
+
+// Nullable fields push their value only if `present`, so the IR doesn't have to branch
+
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn project_push_nullable_str<'a>(
+    output_vec: *mut Vec<ProjectedValue<'a>>,
+    present: bool,
+    s: &'a str,
 ) {
-    unsafe {
-        *output_vec = Vec::new();
-        (*output_vec).extend(vec.iter().filter(|user| filter(user)).cloned());
-    }
+    let value = if present {
+        ProjectedValue::Str(s)
+    } else {
+        ProjectedValue::Null
+    };
+    unsafe { (*output_vec).push(value) }
+}
+
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn project_push_nullable_int(
+    output_vec: *mut Vec<ProjectedValue>,
+    present: bool,
+    n: u64,
+) {
+    let value = if present {
+        ProjectedValue::Int(n)
+    } else {
+        ProjectedValue::Null
+    };
+    unsafe { (*output_vec).push(value) }
+}
+
+// ======
+// Aggregation
+// ======
+
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn aggregate_state_totals(state: *mut AggregateState) -> *mut u64 {
+    unsafe { (*state).totals.as_mut_ptr() }
+}
+
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn aggregate_state_group<'a>(
+    state: *mut AggregateState<'a>,
+    key: &'a str,
+) -> *mut u64 {
+    unsafe { (*state).group_slots(key).as_mut_ptr() }
+}
+
+// ======
+// Ordering
+// ======
+
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn str_compare(a: &str, b: &str) -> i32 {
+    a.cmp(b) as i32
 }
 
 // ======
@@ -84,6 +597,17 @@ pub extern "C" fn filter_str_contains(s: &str, substr: &str) -> bool {
     s.contains(substr)
 }
 
+/// `substr` must be the needle `skip_table` was built from, see `shared::substring_search`
+#[no_mangle]
+#[inline(always)]
+pub extern "C" fn filter_str_contains_with_table(
+    s: &str,
+    substr: &str,
+    skip_table: &substring_search::SkipTable,
+) -> bool {
+    substring_search::contains_with_table(s.as_bytes(), substr.as_bytes(), skip_table)
+}
+
 #[no_mangle]
 #[inline(always)]
 pub extern "C" fn filter_str_equals(s: &str, other: &str) -> bool {
@@ -102,84 +626,104 @@ pub extern "C" fn filter_str_ends_with(s: &str, suffix: &str) -> bool {
     s.ends_with(suffix)
 }
 
-// #[no_mangle]
-// #[inline(always)]
-// pub extern "C" fn filter_str_matches(s: &str, regex: &str) -> bool {
-//     regex::Regex::new(regex).unwrap().is_match(s)
-// }
-
-// ======
-// User
-// ======
+// The ignore case filters expect the second argument to already be case folded, so that the
+// JIT can fold constants once at compile time.
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_email(user: &User) -> &str {
-    &user.email
+pub extern "C" fn filter_str_contains_ignore_case(s: &str, folded_substr: &str) -> bool {
+    case_fold::folded_contains(s, folded_substr)
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_gender(user: &User) -> &str {
-    &user.gender
+pub extern "C" fn filter_str_equals_ignore_case(s: &str, folded_other: &str) -> bool {
+    case_fold::folded_equals(s, folded_other)
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_phone_number(user: &User) -> &str {
-    &user.phone_number
+pub extern "C" fn filter_str_starts_with_ignore_case(s: &str, folded_prefix: &str) -> bool {
+    case_fold::folded_starts_with(s, folded_prefix)
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_location_street(user: &User) -> &str {
-    &user.location.street
+pub extern "C" fn filter_str_ends_with_ignore_case(s: &str, folded_suffix: &str) -> bool {
+    case_fold::folded_ends_with(s, folded_suffix)
 }
 
+// A field read by several ignore case filters is folded once into a buffer on the filter
+// function's stack instead, and compared with the plain filters. The JIT initializes the
+// buffer on entry and drops it before returning.
+
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_location_city(user: &User) -> &str {
-    &user.location.city
+pub unsafe extern "C" fn str_buffer_init(buffer: *mut String) {
+    buffer.write(String::new())
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_location_state(user: &User) -> &str {
-    &user.location.state
+pub unsafe extern "C" fn str_buffer_drop(buffer: *mut String) {
+    core::ptr::drop_in_place(buffer)
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_username(user: &User) -> &str {
-    &user.username
+pub extern "C" fn str_fold_into<'a>(s: &str, buffer: &'a mut String) -> &'a str {
+    buffer.clear();
+    buffer.extend(s.chars().map(case_fold::fold_char));
+    buffer
 }
 
+// Regex matching can't use the regex crate here, instead the runner compiles each pattern
+// into a DFA and emits it directly as IR (see `build_regex_filter` in the runner).
+
+// ======
+// User
+// ======
+
+// The getters the JIT reads fields through, generated from `User` by `#[derive(JitRecord)]`
+shared::user_getters!();
+
+// ======
+// Documents
+// ======
+
+// Document fields are looked up one key of their path at a time, and the value at the end is
+// checked and read by the getters of the field's type. `None` is a missing value, and the
+// root value is the document itself (see `shared::document`).
+
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_password(user: &User) -> &str {
-    &user.password
+pub extern "C" fn document_lookup<'a>(
+    value: Option<&'a DocumentValue>,
+    key: &str,
+) -> Option<&'a DocumentValue> {
+    value?.get(key)
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_first_name(user: &User) -> &str {
-    &user.first_name
+pub extern "C" fn document_value_is_str(value: Option<&DocumentValue>) -> bool {
+    matches!(value, Some(DocumentValue::Str(_)))
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_last_name(user: &User) -> &str {
-    &user.last_name
+pub extern "C" fn document_value_str(value: Option<&DocumentValue>) -> &str {
+    value.and_then(DocumentValue::as_str).unwrap_or("")
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_title(user: &User) -> &str {
-    &user.title
+pub extern "C" fn document_value_is_int(value: Option<&DocumentValue>) -> bool {
+    matches!(value, Some(DocumentValue::Int(_)))
 }
 
 #[no_mangle]
 #[inline(always)]
-pub extern "C" fn user_get_field_picture(user: &User) -> &str {
-    &user.picture
+pub extern "C" fn document_value_int(value: Option<&DocumentValue>) -> u64 {
+    value.and_then(DocumentValue::as_int).unwrap_or(0)
 }
@@ -0,0 +1,858 @@
+//! `#[derive(JitRecord)]`, which generates everything that lists the fields of a record type
+//! from its struct definition, so none of it can drift apart from the struct:
+//!
+//! - The field enum, implementing `shared::schema::RecordField` (field names, types and the
+//!   names of their getters)
+//! - `shared::schema::Record` for the struct, which the interpreter reads fields through
+//! - A macro that defines the `no_std` getters the JIT calls, which the `functions` crate
+//!   invokes to export them (e.g. `shared::user_getters!();`)
+//!
+//! ```ignore
+//! #[derive(Clone, JitRecord)]
+//! #[jit_record(nested)]
+//! pub struct Location {
+//!     pub street: String,
+//!     pub postcode: u32,
+//! }
+//!
+//! #[derive(Clone, JitRecord)]
+//! #[jit_record(field = UserField, symbol_prefix = "")]
+//! #[jit_record_field_attr(cfg_attr(feature = "std", derive(Serialize, Deserialize)))]
+//! pub struct User {
+//!     pub email: String,
+//!     pub birthdate: u64,
+//!     #[jit_record(flatten)]
+//!     pub location: Location,
+//! }
+//! ```
+//!
+//! Fields can be `String`, `u32` or `u64`, or an `Option` of one of them for nullable fields,
+//! which also get a getter for whether the value is there. `Vec<String>` fields are lists,
+//! their getter (`*_len`) returns the length and a second one (`*_element`) an element. The
+//! fields of a `#[jit_record(flatten)]` struct are flattened into the record
+//! (`location_street`, which queries can also write as `location.street`), and fields marked
+//! with `#[jit_record(skip)]` can't be queried. Every `#[jit_record_field_attr(..)]` is copied
+//! onto the field enum.
+//!
+//! A flattened struct derives `JitRecord` with `#[jit_record(nested)]`, which only checks its
+//! fields and defines a `macro_rules!` listing them, as a derive can't see the definitions of
+//! other types. The record's derive expands through that macro, so the nested struct has to
+//! be defined before the record, in the same module.
+//!
+//! By default, for a struct `LogEvent` the field enum is `LogEventField`, the functions crate
+//! symbols are prefixed with `log_event_`, the getters are named `log_event_get_field_*`, the
+//! presence getters `log_event_has_field_*` and the macro is `log_event_getters!`. Each can be
+//! changed with the `field`, `symbol_prefix`, `getter_prefix`, `presence_prefix` and `getters`
+//! options.
+//!
+//! The generated code refers to the `shared` crate by name, so it can only be used there.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    braced,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Data, DeriveInput, Field, Fields, GenericArgument, Ident, LitStr, Meta, PathArguments, Token,
+    Type,
+};
+
+#[proc_macro_derive(JitRecord, attributes(jit_record, jit_record_field_attr))]
+pub fn derive_jit_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match derive(&input, &[]) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Where the derive continues once the macros of nested structs have added their fields:
+/// `{ <the record's struct> } Location { street: String, .. } ..`
+#[doc(hidden)]
+#[proc_macro]
+pub fn jit_record_flattened(input: TokenStream) -> TokenStream {
+    let FlattenedInput { input, nested } = parse_macro_input!(input as FlattenedInput);
+    match derive(&input, &nested) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The derive, with the fields of the nested structs found so far
+fn derive(input: &DeriveInput, nested: &[NestedStruct]) -> syn::Result<TokenStream2> {
+    let options = Options::parse(input)?;
+    if options.nested {
+        return expand_nested(input);
+    }
+
+    // Ask the first nested struct that's still missing for its fields, which calls back into
+    // `jit_record_flattened!`
+    for field in named_fields(input)? {
+        if !FieldOptions::parse(field)?.flatten {
+            continue;
+        }
+        let name = type_name(&field.ty).ok_or_else(|| {
+            syn::Error::new_spanned(
+                &field.ty,
+                "`#[jit_record(flatten)]` needs a struct named without a path or generics",
+            )
+        })?;
+        if nested.iter().all(|nested| nested.name != name) {
+            let fields_macro = nested_fields_macro(&name);
+            return Ok(quote! {
+                #fields_macro! { { #input } #(#nested)* }
+            });
+        }
+    }
+
+    expand(input, options, nested)
+}
+
+#[derive(Clone, Copy)]
+enum FieldType {
+    Str,
+    U32,
+    U64,
+    /// `Vec<String>`
+    StrList,
+}
+
+impl FieldType {
+    /// The type of a field, and whether it's nullable (an `Option` of one of the types)
+    fn from_type(ty: &Type) -> syn::Result<(FieldType, bool)> {
+        if generic_inner(ty, "Vec").and_then(type_name).as_deref() == Some("String") {
+            return Ok((FieldType::StrList, false));
+        }
+
+        let (inner, nullable) = match generic_inner(ty, "Option") {
+            Some(inner) => (inner, true),
+            None => (ty, false),
+        };
+        let field_type = match type_name(inner).as_deref() {
+            Some("String") => FieldType::Str,
+            Some("u32") => FieldType::U32,
+            Some("u64") => FieldType::U64,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    "record fields must be `String`, `u32`, `u64`, an `Option` of one of them \
+                     or `Vec<String>`, other fields need `#[jit_record(flatten)]` or \
+                     `#[jit_record(skip)]`",
+                ))
+            }
+        };
+        Ok((field_type, nullable))
+    }
+
+    fn schema_type(self) -> TokenStream2 {
+        match self {
+            FieldType::Str => quote!(::shared::schema::FieldType::Str),
+            FieldType::U32 => quote!(::shared::schema::FieldType::U32),
+            FieldType::U64 => quote!(::shared::schema::FieldType::U64),
+            FieldType::StrList => quote!(::shared::schema::FieldType::StrList),
+        }
+    }
+
+    /// What the getter returns, the number of elements for lists
+    fn getter_return_type(self) -> TokenStream2 {
+        match self {
+            FieldType::Str => quote!(&str),
+            FieldType::U32 => quote!(u32),
+            FieldType::U64 | FieldType::StrList => quote!(u64),
+        }
+    }
+}
+
+/// The name of a type without a path or generics, e.g. `String`
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.get_ident().map(Ident::to_string),
+        _ => None,
+    }
+}
+
+/// `T` if `ty` is `Name<T>`
+fn generic_inner<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = match path.path.segments.iter().collect::<Vec<_>>()[..] {
+        [segment] if path.qself.is_none() && segment.ident == name => segment,
+        _ => return None,
+    };
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.iter().collect::<Vec<_>>()[..] {
+        [GenericArgument::Type(inner)] => Some(inner),
+        _ => None,
+    }
+}
+
+/// A queryable field of the record, with nested structs already flattened
+struct RecordField {
+    variant: Ident,
+    /// Name used in queries, e.g. `location_street`
+    name: String,
+    /// Name with a dot between the struct and its field, e.g. `location.street`
+    dotted_name: Option<String>,
+    /// Struct fields from the record down to the value
+    path: Vec<Ident>,
+    ty: FieldType,
+    /// Stored as an `Option`
+    nullable: bool,
+}
+
+impl RecordField {
+    fn new(path: Vec<Ident>, (ty, nullable): (FieldType, bool)) -> Self {
+        let names = path
+            .iter()
+            .map(|ident| ident.to_string().trim_start_matches("r#").to_string())
+            .collect::<Vec<_>>();
+        RecordField {
+            variant: format_ident!("{}", to_pascal_case(&names.join("_"))),
+            name: names.join("_"),
+            dotted_name: (names.len() > 1).then(|| names.join(".")),
+            path,
+            ty,
+            nullable,
+        }
+    }
+
+    /// The field itself, starting from `base`
+    fn access(&self, base: TokenStream2) -> TokenStream2 {
+        let path = &self.path;
+        quote!(#base.#(#path).*)
+    }
+
+    /// The value of the field as its getter returns it, with missing values read as an empty
+    /// string or 0 and lists as their length
+    fn read(&self, base: TokenStream2) -> TokenStream2 {
+        let access = self.access(base);
+        match (self.ty, self.nullable) {
+            (FieldType::StrList, _) => quote!(#access.len() as u64),
+            (FieldType::Str, false) => quote!(&#access),
+            (FieldType::Str, true) => quote!(#access.as_deref().unwrap_or("")),
+            (_, false) => quote!(#access),
+            (_, true) => quote!(#access.unwrap_or(0)),
+        }
+    }
+}
+
+struct Options {
+    field: Ident,
+    symbol_prefix: String,
+    getter_prefix: String,
+    presence_prefix: String,
+    getters: Ident,
+    field_attrs: Vec<Meta>,
+    /// Only flattened into other records, see [`expand_nested`]
+    nested: bool,
+}
+
+impl Options {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let snake_name = to_snake_case(&input.ident.to_string());
+        let mut options = Options {
+            field: format_ident!("{}Field", input.ident),
+            symbol_prefix: format!("{}_", snake_name),
+            getter_prefix: format!("{}_get_field_", snake_name),
+            presence_prefix: format!("{}_has_field_", snake_name),
+            getters: format_ident!("{}_getters", snake_name),
+            field_attrs: Vec::new(),
+            nested: false,
+        };
+
+        for attr in &input.attrs {
+            if attr.path().is_ident("jit_record_field_attr") {
+                options.field_attrs.push(attr.parse_args()?);
+            } else if attr.path().is_ident("jit_record") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("field") {
+                        options.field = meta.value()?.parse()?;
+                    } else if meta.path.is_ident("symbol_prefix") {
+                        options.symbol_prefix = meta.value()?.parse::<LitStr>()?.value();
+                    } else if meta.path.is_ident("getter_prefix") {
+                        options.getter_prefix = meta.value()?.parse::<LitStr>()?.value();
+                    } else if meta.path.is_ident("presence_prefix") {
+                        options.presence_prefix = meta.value()?.parse::<LitStr>()?.value();
+                    } else if meta.path.is_ident("getters") {
+                        options.getters = meta.value()?.parse()?;
+                    } else if meta.path.is_ident("nested") {
+                        options.nested = true;
+                    } else {
+                        return Err(meta.error("unknown jit_record option"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// One field of a nested struct, `name: Type`
+struct NestedField {
+    name: Ident,
+    ty: Type,
+}
+
+impl Parse for NestedField {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        Ok(NestedField {
+            name,
+            ty: input.parse()?,
+        })
+    }
+}
+
+/// The queryable fields of a nested struct, `Location { street: String, .. }`
+struct NestedStruct {
+    name: String,
+    ident: Ident,
+    fields: Vec<NestedField>,
+}
+
+impl Parse for NestedStruct {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ident: Ident = input.parse()?;
+        let content;
+        braced!(content in input);
+        let fields = Punctuated::<NestedField, Token![,]>::parse_terminated(&content)?;
+        Ok(NestedStruct {
+            name: ident.to_string(),
+            ident,
+            fields: fields.into_iter().collect(),
+        })
+    }
+}
+
+impl quote::ToTokens for NestedStruct {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        let ident = &self.ident;
+        let names = self.fields.iter().map(|field| &field.name);
+        let types = self.fields.iter().map(|field| &field.ty);
+        tokens.extend(quote!(#ident { #(#names: #types),* }));
+    }
+}
+
+struct FlattenedInput {
+    input: DeriveInput,
+    nested: Vec<NestedStruct>,
+}
+
+impl Parse for FlattenedInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let content;
+        braced!(content in input);
+        let derive_input = content.parse()?;
+        let mut nested = Vec::new();
+        while !input.is_empty() {
+            nested.push(input.parse()?);
+        }
+        Ok(FlattenedInput {
+            input: derive_input,
+            nested,
+        })
+    }
+}
+
+/// The macro a nested struct's derive defines to list its fields
+fn nested_fields_macro(name: &str) -> Ident {
+    format_ident!("__jit_record_fields_{}", name)
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    skip: bool,
+    flatten: bool,
+}
+
+impl FieldOptions {
+    fn parse(field: &Field) -> syn::Result<Self> {
+        let mut options = FieldOptions::default();
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("jit_record"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    options.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    options.flatten = true;
+                } else {
+                    return Err(meta.error("unknown jit_record field option"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(options)
+    }
+}
+
+fn named_fields(input: &DeriveInput) -> syn::Result<&Punctuated<Field, Token![,]>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "JitRecord can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "JitRecord needs a struct with named fields",
+        ));
+    };
+    Ok(&fields.named)
+}
+
+/// Flatten the struct's fields into the queryable fields, in declaration order. Every
+/// flattened struct has to be in `nested`.
+fn record_fields(input: &DeriveInput, nested: &[NestedStruct]) -> syn::Result<Vec<RecordField>> {
+    let mut record_fields = Vec::new();
+    for field in named_fields(input)? {
+        let ident = field.ident.clone().unwrap();
+        let options = FieldOptions::parse(field)?;
+        if options.skip {
+            continue;
+        }
+
+        if options.flatten {
+            let name = type_name(&field.ty);
+            let nested = nested
+                .iter()
+                .find(|nested| Some(&nested.name) == name.as_ref())
+                .expect("nested structs are resolved before expanding");
+            for nested_field in &nested.fields {
+                let ty = FieldType::from_type(&nested_field.ty)?;
+                let path = vec![ident.clone(), nested_field.name.clone()];
+                record_fields.push(RecordField::new(path, ty));
+            }
+        } else {
+            let ty = FieldType::from_type(&field.ty)?;
+            record_fields.push(RecordField::new(vec![ident], ty));
+        }
+    }
+
+    if record_fields.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "a record needs at least one queryable field",
+        ));
+    }
+    Ok(record_fields)
+}
+
+/// A nested struct only checks its fields, and defines the macro that passes them on to the
+/// derive of the records flattening it
+fn expand_nested(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let mut fields = Vec::new();
+    for field in named_fields(input)? {
+        let options = FieldOptions::parse(field)?;
+        if options.flatten {
+            return Err(syn::Error::new_spanned(
+                field,
+                "nested structs can't flatten other structs",
+            ));
+        }
+        if options.skip {
+            continue;
+        }
+        FieldType::from_type(&field.ty)?;
+        fields.push(NestedField {
+            name: field.ident.clone().unwrap(),
+            ty: field.ty.clone(),
+        });
+    }
+
+    let nested = NestedStruct {
+        name: input.ident.to_string(),
+        ident: input.ident.clone(),
+        fields,
+    };
+    let fields_macro = nested_fields_macro(&nested.name);
+    Ok(quote! {
+        #[doc(hidden)]
+        #[allow(unused_macros)]
+        macro_rules! #fields_macro {
+            ($($input:tt)*) => {
+                ::record_derive::jit_record_flattened! { $($input)* #nested }
+            };
+        }
+    })
+}
+
+fn expand(
+    input: &DeriveInput,
+    options: Options,
+    nested: &[NestedStruct],
+) -> syn::Result<TokenStream2> {
+    let fields = record_fields(input, nested)?;
+
+    let record = &input.ident;
+    let vis = &input.vis;
+    let field_enum = &options.field;
+    let field_attrs = &options.field_attrs;
+    let symbol_prefix = &options.symbol_prefix;
+    let getters_macro = &options.getters;
+    let doc = format!(
+        "The queryable fields of [`{}`], generated by `#[derive(JitRecord)]`",
+        record
+    );
+
+    let variants = fields
+        .iter()
+        .map(|field| &field.variant)
+        .collect::<Vec<_>>();
+    let names = fields.iter().map(|field| &field.name).collect::<Vec<_>>();
+    // Nested fields can also be written with a dot, e.g. `location.street`
+    let name_patterns = fields.iter().map(|field| {
+        let name = &field.name;
+        match &field.dotted_name {
+            Some(dotted_name) => quote!(#name | #dotted_name),
+            None => quote!(#name),
+        }
+    });
+    let schema_types = fields.iter().map(|field| field.ty.schema_type());
+    let getter_names = fields
+        .iter()
+        .map(|field| match field.ty {
+            FieldType::StrList => format!("{}{}_len", options.getter_prefix, field.name),
+            _ => format!("{}{}", options.getter_prefix, field.name),
+        })
+        .collect::<Vec<_>>();
+    let (list_variants, element_getter_names): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .filter(|field| matches!(field.ty, FieldType::StrList))
+        .map(|field| {
+            let name = format!("{}{}_element", options.getter_prefix, field.name);
+            (&field.variant, name)
+        })
+        .unzip();
+    let list_accesses = fields
+        .iter()
+        .filter(|field| matches!(field.ty, FieldType::StrList))
+        .map(|field| field.access(quote!(self)));
+    let (nullable_variants, presence_getter_names): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .filter(|field| field.nullable)
+        .map(|field| {
+            let name = format!("{}{}", options.presence_prefix, field.name);
+            (&field.variant, name)
+        })
+        .unzip();
+    let nullable_accesses = fields
+        .iter()
+        .filter(|field| field.nullable)
+        .map(|field| field.access(quote!(self)));
+
+    let (str_variants, str_reads): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .filter(|field| matches!(field.ty, FieldType::Str))
+        .map(|field| (&field.variant, field.read(quote!(self))))
+        .unzip();
+    let (int_variants, int_reads): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .filter(|field| matches!(field.ty, FieldType::U32 | FieldType::U64))
+        .map(|field| (&field.variant, field.read(quote!(self))))
+        .unzip();
+
+    let getters = fields
+        .iter()
+        .zip(&getter_names)
+        .map(|(field, getter_name)| {
+            let getter = format_ident!("{}", getter_name);
+            let return_type = field.ty.getter_return_type();
+            let read = field.read(quote!(record));
+            quote! {
+                #[no_mangle]
+                #[inline(always)]
+                pub extern "C" fn #getter(record: &$crate::#record) -> #return_type {
+                    #read
+                }
+            }
+        });
+    let presence_getters = fields
+        .iter()
+        .filter(|field| field.nullable)
+        .zip(&presence_getter_names)
+        .map(|(field, getter_name)| {
+            let getter = format_ident!("{}", getter_name);
+            let access = field.access(quote!(record));
+            quote! {
+                #[no_mangle]
+                #[inline(always)]
+                pub extern "C" fn #getter(record: &$crate::#record) -> bool {
+                    #access.is_some()
+                }
+            }
+        });
+    let element_getters = fields
+        .iter()
+        .filter(|field| matches!(field.ty, FieldType::StrList))
+        .zip(&element_getter_names)
+        .map(|(field, getter_name)| {
+            let getter = format_ident!("{}", getter_name);
+            let access = field.access(quote!(record));
+            quote! {
+                #[no_mangle]
+                #[inline(always)]
+                pub extern "C" fn #getter(record: &$crate::#record, index: u64) -> &str {
+                    &#access[index as usize]
+                }
+            }
+        });
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #(#[#field_attrs])*
+        #vis enum #field_enum {
+            #(#variants,)*
+        }
+
+        impl ::shared::schema::RecordField for #field_enum {
+            type Record = #record;
+
+            const ALL: &'static [Self] = &[#(#field_enum::#variants),*];
+
+            fn name(self) -> &'static str {
+                match self {
+                    #(#field_enum::#variants => #names,)*
+                }
+            }
+
+            fn field_type(self) -> ::shared::schema::FieldType {
+                match self {
+                    #(#field_enum::#variants => #schema_types,)*
+                }
+            }
+
+            fn getter(self) -> &'static str {
+                match self {
+                    #(#field_enum::#variants => #getter_names,)*
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn presence_getter(self) -> Option<&'static str> {
+                match self {
+                    #(#field_enum::#nullable_variants => Some(#presence_getter_names),)*
+                    _ => None,
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn element_getter(self) -> Option<&'static str> {
+                match self {
+                    #(#field_enum::#list_variants => Some(#element_getter_names),)*
+                    _ => None,
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                let field = match name {
+                    #(#name_patterns => #field_enum::#variants,)*
+                    _ => return None,
+                };
+                Some(field)
+            }
+        }
+
+        impl ::shared::schema::Record for #record {
+            type Field = #field_enum;
+
+            const SYMBOL_PREFIX: &'static str = #symbol_prefix;
+
+            #[allow(unreachable_patterns)]
+            fn str_field(&self, field: #field_enum) -> &str {
+                match field {
+                    #(#field_enum::#str_variants => #str_reads,)*
+                    _ => panic!("{:?} is not a string field", field),
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn int_field(&self, field: #field_enum) -> u64 {
+                match field {
+                    #(#field_enum::#int_variants => u64::from(#int_reads),)*
+                    _ => panic!("{:?} is not a numeric field", field),
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn has_value(&self, field: #field_enum) -> bool {
+                match field {
+                    #(#field_enum::#nullable_variants => #nullable_accesses.is_some(),)*
+                    _ => true,
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn str_list_field(&self, field: #field_enum) -> &[::alloc::string::String] {
+                match field {
+                    #(#field_enum::#list_variants => &#list_accesses,)*
+                    _ => panic!("{:?} is not a list field", field),
+                }
+            }
+        }
+
+        /// Define the getters the JIT calls to read each field of the record (along with
+        /// whether nullable fields have a value and the elements of lists), for the
+        /// `functions` crate to export
+        #[macro_export]
+        macro_rules! #getters_macro {
+            () => {
+                #(#getters)*
+                #(#presence_getters)*
+                #(#element_getters)*
+            };
+        }
+    })
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, ch) in name.chars().enumerate() {
+        if ch.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(ch.to_lowercase());
+        } else {
+            snake.push(ch);
+        }
+    }
+    snake
+}
+
+fn to_pascal_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    fn derive_error(input: DeriveInput) -> String {
+        derive(&input, &[]).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn flattened_structs_are_asked_for_their_fields() {
+        let input: DeriveInput = parse_quote! {
+            struct User {
+                email: String,
+                #[jit_record(flatten)]
+                location: Location,
+            }
+        };
+        let expected = quote! {
+            __jit_record_fields_Location! { { #input } }
+        };
+        assert_eq!(
+            derive(&input, &[]).unwrap().to_string(),
+            expected.to_string()
+        );
+
+        // Once the nested struct answered, the record expands
+        let location: NestedStruct = parse_quote!(Location { city: String });
+        let tokens = derive(&input, &[location]).unwrap().to_string();
+        assert!(tokens.contains("enum UserField { Email , LocationCity , }"));
+        assert!(tokens.contains("\"location_city\" | \"location.city\""));
+        assert!(tokens.contains("user_get_field_location_city"));
+    }
+
+    #[test]
+    fn nested_structs_define_a_macro_listing_their_fields() {
+        let input: DeriveInput = parse_quote! {
+            #[jit_record(nested)]
+            struct Location {
+                city: String,
+                #[jit_record(skip)]
+                id: [u8; 16],
+                postcode: Option<u32>,
+            }
+        };
+        let expected = quote! {
+            #[doc(hidden)]
+            #[allow(unused_macros)]
+            macro_rules! __jit_record_fields_Location {
+                ($($input:tt)*) => {
+                    ::record_derive::jit_record_flattened! {
+                        $($input)* Location { city: String, postcode: Option<u32> }
+                    }
+                };
+            }
+        };
+        assert_eq!(
+            derive(&input, &[]).unwrap().to_string(),
+            expected.to_string()
+        );
+    }
+
+    #[test]
+    fn flattened_input_round_trips() {
+        let input: FlattenedInput = parse_quote! {
+            { struct User { #[jit_record(flatten)] location: Location } }
+            Location { city: String, postcode: u32 }
+        };
+        assert_eq!(input.input.ident, "User");
+        assert_eq!(input.nested.len(), 1);
+        assert_eq!(input.nested[0].name, "Location");
+        let names = input.nested[0]
+            .fields
+            .iter()
+            .map(|field| field.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["city", "postcode"]);
+    }
+
+    #[test]
+    fn unsupported_fields_are_errors() {
+        let message = derive_error(parse_quote! {
+            struct User { score: f64 }
+        });
+        assert!(message.starts_with("record fields must be"), "{}", message);
+
+        let message = derive_error(parse_quote! {
+            #[jit_record(nested)]
+            struct Location { #[jit_record(flatten)] country: Country }
+        });
+        assert_eq!(message, "nested structs can't flatten other structs");
+
+        let message = derive_error(parse_quote! {
+            struct User { #[jit_record(flatten)] location: geo::Location }
+        });
+        assert_eq!(
+            message,
+            "`#[jit_record(flatten)]` needs a struct named without a path or generics"
+        );
+
+        let message = derive_error(parse_quote! {
+            struct User { #[jit_record(skip)] id: u64 }
+        });
+        assert_eq!(message, "a record needs at least one queryable field");
+
+        let message = derive_error(parse_quote! {
+            struct User { #[jit_record(nested(city: String))] location: Location }
+        });
+        assert_eq!(message, "unknown jit_record field option");
+    }
+}
@@ -1,5 +1,11 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use runner::{build_complex_filter, interpreted, jit::build_module, read_data};
+use runner::{
+    build_complex_filter, interpreted,
+    jit::{build_module, build_module_with_options, build_plan_module},
+    order::SortKey,
+    plan::Plan,
+    query, read_data, Field, FilterOptions,
+};
 
 fn criterion_benchmark(c: &mut Criterion) {
     let users = read_data();
@@ -11,6 +17,99 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
 
     c.bench_function("JIT", |b| b.iter(|| unsafe { jit_fn.execute(&users) }));
+
+    c.bench_function("Interpreted indices", |b| {
+        b.iter(|| interpreted::filter_indices_with_filters(&users, &filters))
+    });
+
+    c.bench_function("JIT indices", |b| {
+        b.iter(|| unsafe { jit_fn.execute_indices(&users) })
+    });
+
+    c.bench_function("JIT refs", |b| {
+        b.iter(|| unsafe { jit_fn.execute_refs(&users) })
+    });
+
+    c.bench_function("JIT bitmap", |b| {
+        b.iter(|| unsafe { jit_fn.execute_bitmap(&users) })
+    });
+
+    c.bench_function("JIT count", |b| b.iter(|| unsafe { jit_fn.count(&users) }));
+
+    c.bench_function("JIT any", |b| b.iter(|| unsafe { jit_fn.any(&users) }));
+
+    let order_by = [SortKey::descending(Field::Birthdate)];
+    let ordered_options = FilterOptions {
+        order_by: &order_by,
+        limit: Some(100),
+        ..Default::default()
+    };
+    let ordered_jit_fn = unsafe { build_module_with_options(&filters, &ordered_options).unwrap() };
+
+    c.bench_function("Interpreted top 100", |b| {
+        b.iter(|| interpreted::filter_ordered_with_options(&users, &filters, &ordered_options))
+    });
+
+    c.bench_function("JIT top 100", |b| {
+        b.iter(|| unsafe { ordered_jit_fn.execute_ordered(&users) })
+    });
+
+    let plan = Plan::scan()
+        .filter(filters.clone())
+        .sort(order_by.to_vec())
+        .limit(100)
+        .project(vec![Field::Email, Field::Birthdate]);
+    let plan_jit_fn = unsafe { build_plan_module(&plan, &FilterOptions::default()).unwrap() };
+
+    c.bench_function("Interpreted plan", |b| {
+        b.iter(|| interpreted::execute_plan(&users, &plan, &FilterOptions::default()))
+    });
+
+    c.bench_function("JIT plan", |b| {
+        b.iter(|| unsafe { plan_jit_fn.execute(&users) })
+    });
+
+    let options = FilterOptions {
+        reorder_sample: Some(&users),
+        ..Default::default()
+    };
+    let reordered_jit_fn = unsafe { build_module_with_options(&filters, &options).unwrap() };
+
+    c.bench_function("Interpreted (reordered)", |b| {
+        b.iter(|| interpreted::filter_vec_with_options(&users, &filters, &options))
+    });
+
+    c.bench_function("JIT (reordered)", |b| {
+        b.iter(|| unsafe { reordered_jit_fn.execute(&users) })
+    });
+
+    // Only constant equals/starts_with/ends_with, to compare the inline codegen for them
+    // against calling the generic string functions
+    let str_compare_filters = query::parse_filter(
+        r#"(title = "mr" or title = "mrs" or gender = "female")
+            and (email ends_with "@example.com" or username starts_with "crazy"
+                or location_city = "Winchester" or first_name starts_with "Ann")"#,
+    )
+    .unwrap();
+    let generic_jit_fn = unsafe {
+        build_module_with_options(
+            &str_compare_filters,
+            &FilterOptions {
+                inline_str_compares: false,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+    let inline_jit_fn = unsafe { build_module(&str_compare_filters) };
+
+    c.bench_function("JIT string compares (generic)", |b| {
+        b.iter(|| unsafe { generic_jit_fn.execute(&users) })
+    });
+
+    c.bench_function("JIT string compares (inline)", |b| {
+        b.iter(|| unsafe { inline_jit_fn.execute(&users) })
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
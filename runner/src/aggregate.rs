@@ -0,0 +1,183 @@
+//! Aggregate queries over the users that match a filter, e.g. the number of matching users
+//! per state, or the earliest birthdate among them.
+//!
+//! The JIT fuses the filter and the aggregation into one loop, instead of filtering first and
+//! aggregating the output afterwards (the "volcano model" from the slides). Both engines fill
+//! the same [`AggregateState`], with the slot layout described by [`AggregateQuery`].
+
+use std::fmt;
+
+use shared::{
+    aggregate::AggregateState,
+    schema::{Record, RecordField},
+};
+
+use crate::Field;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aggregate<F = Field> {
+    /// Number of matching users
+    Count,
+    /// Smallest value of a numeric field
+    Min(F),
+    /// Largest value of a numeric field
+    Max(F),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AggregateError<F = Field> {
+    /// Groups can only be keyed by string fields
+    InvalidGroupBy(F),
+    /// Min and max only work on numeric fields
+    InvalidAggregate(Aggregate<F>),
+    /// Aggregates don't define what a missing value does, so they can't use nullable fields
+    NullableField(F),
+    /// Groups can't be keyed by lists
+    ListField(F),
+}
+
+impl<F: fmt::Debug> fmt::Display for AggregateError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregateError::InvalidGroupBy(field) => {
+                write!(f, "can't group by the numeric {:?} field", field)
+            }
+            AggregateError::InvalidAggregate(aggregate) => {
+                write!(f, "{:?} needs a numeric field", aggregate)
+            }
+            AggregateError::NullableField(field) => {
+                write!(f, "can't aggregate over the nullable {:?} field", field)
+            }
+            AggregateError::ListField(field) => {
+                write!(f, "can't group by the list {:?} field", field)
+            }
+        }
+    }
+}
+
+impl<F: fmt::Debug> std::error::Error for AggregateError<F> {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AggregateQuery<F = Field> {
+    group_by: Option<F>,
+    aggregates: Vec<Aggregate<F>>,
+}
+
+impl<F: RecordField> AggregateQuery<F> {
+    pub fn new(
+        group_by: Option<F>,
+        aggregates: Vec<Aggregate<F>>,
+    ) -> Result<Self, AggregateError<F>> {
+        if let Some(field) = group_by {
+            if field.is_numeric() {
+                return Err(AggregateError::InvalidGroupBy(field));
+            }
+            if field.is_nullable() {
+                return Err(AggregateError::NullableField(field));
+            }
+            if field.is_list() {
+                return Err(AggregateError::ListField(field));
+            }
+        }
+        for &aggregate in &aggregates {
+            match aggregate {
+                Aggregate::Min(field) | Aggregate::Max(field) if !field.is_numeric() => {
+                    return Err(AggregateError::InvalidAggregate(aggregate));
+                }
+                Aggregate::Min(field) | Aggregate::Max(field) if field.is_nullable() => {
+                    return Err(AggregateError::NullableField(field));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(AggregateQuery {
+            group_by,
+            aggregates,
+        })
+    }
+
+    pub fn group_by(&self) -> Option<F> {
+        self.group_by
+    }
+
+    pub fn aggregates(&self) -> &[Aggregate<F>] {
+        &self.aggregates
+    }
+
+    /// The slot each aggregate is accumulated in. `Count` reads the row count in slot 0, the
+    /// others get a slot each, in order, starting from slot 1.
+    pub(crate) fn slots(&self) -> impl Iterator<Item = (Aggregate<F>, usize)> + '_ {
+        let mut next_slot = 1;
+        self.aggregates
+            .iter()
+            .map(move |&aggregate| match aggregate {
+                Aggregate::Count => (aggregate, 0),
+                Aggregate::Min(_) | Aggregate::Max(_) => {
+                    next_slot += 1;
+                    (aggregate, next_slot - 1)
+                }
+            })
+    }
+
+    pub(crate) fn new_state<'a>(&self) -> AggregateState<'a> {
+        let mut initial = vec![0];
+        for (aggregate, _) in self.slots() {
+            match aggregate {
+                Aggregate::Count => {}
+                Aggregate::Min(_) => initial.push(u64::MAX),
+                Aggregate::Max(_) => initial.push(0),
+            }
+        }
+        AggregateState::new(initial)
+    }
+
+    /// Add a matching record to the state
+    pub(crate) fn accumulate<'a>(&self, state: &mut AggregateState<'a>, record: &'a F::Record) {
+        let slots = match self.group_by {
+            Some(field) => state.group_slots(record.str_field(field)),
+            None => &mut state.totals,
+        };
+
+        slots[0] += 1;
+        for (aggregate, slot) in self.slots() {
+            match aggregate {
+                Aggregate::Count => {}
+                Aggregate::Min(field) => slots[slot] = slots[slot].min(record.int_field(field)),
+                Aggregate::Max(field) => slots[slot] = slots[slot].max(record.int_field(field)),
+            }
+        }
+    }
+
+    pub(crate) fn finish<'a>(&self, state: AggregateState<'a>) -> Vec<AggregateRow<'a>> {
+        let row = |group, slots: &[u64]| AggregateRow {
+            group,
+            values: self
+                .slots()
+                .map(|(aggregate, slot)| match aggregate {
+                    Aggregate::Count => Some(slots[0]),
+                    // Min and max don't have a value if nothing matched
+                    _ if slots[0] == 0 => None,
+                    _ => Some(slots[slot]),
+                })
+                .collect(),
+        };
+
+        match self.group_by {
+            Some(_) => state
+                .groups
+                .iter()
+                .map(|(&group, slots)| row(Some(group), slots))
+                .collect(),
+            None => vec![row(None, &state.totals)],
+        }
+    }
+}
+
+/// One result row per group (sorted by the group's key), or a single row without a group by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateRow<'a> {
+    pub group: Option<&'a str>,
+    /// One value per aggregate in the query, in the same order
+    pub values: Vec<Option<u64>>,
+}
@@ -0,0 +1,182 @@
+//! Selection bitmaps, one bit per row of the input that says whether the row matched.
+//!
+//! Bits are stored in `u64` words, with row `n` at bit `n % 64` of word `n / 64`. This is the
+//! layout `run_filter_bitmap` in the `functions` crate writes, so compiled filters can fill a
+//! bitmap directly.
+
+use std::ops::{BitAnd, BitOr, Not};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionBitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl SelectionBitmap {
+    /// A bitmap of `len` rows with nothing selected
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    pub fn from_fn(len: usize, mut selected: impl FnMut(usize) -> bool) -> Self {
+        let mut bitmap = Self::new(len);
+        for index in 0..len {
+            if selected(index) {
+                bitmap.words[index / 64] |= 1 << (index % 64);
+            }
+        }
+        bitmap
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Bits past `len` in the last word must be left unset
+    pub(crate) fn words_mut(&mut self) -> &mut [u64] {
+        &mut self.words
+    }
+
+    pub fn is_selected(&self, index: usize) -> bool {
+        assert!(index < self.len, "row {} out of bounds", index);
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Indices of the selected rows, in order
+    pub fn iter_selected(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .flat_map(|(word_index, &word)| {
+                let mut word = word;
+                std::iter::from_fn(move || {
+                    if word == 0 {
+                        return None;
+                    }
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    Some(word_index * 64 + bit)
+                })
+            })
+    }
+
+    /// Clone the selected rows out of `rows`, which must be the input the bitmap was built from
+    pub fn gather<T: Clone>(&self, rows: &[T]) -> Vec<T> {
+        assert_eq!(
+            rows.len(),
+            self.len,
+            "bitmap and rows have different lengths"
+        );
+        self.iter_selected()
+            .map(|index| rows[index].clone())
+            .collect()
+    }
+
+    fn combine(&self, other: &Self, op: impl Fn(u64, u64) -> u64) -> Self {
+        assert_eq!(self.len, other.len, "bitmaps have different lengths");
+        Self {
+            words: self
+                .words
+                .iter()
+                .zip(&other.words)
+                .map(|(&a, &b)| op(a, b))
+                .collect(),
+            len: self.len,
+        }
+    }
+}
+
+impl BitAnd for &SelectionBitmap {
+    type Output = SelectionBitmap;
+
+    fn bitand(self, other: Self) -> SelectionBitmap {
+        self.combine(other, |a, b| a & b)
+    }
+}
+
+impl BitOr for &SelectionBitmap {
+    type Output = SelectionBitmap;
+
+    fn bitor(self, other: Self) -> SelectionBitmap {
+        self.combine(other, |a, b| a | b)
+    }
+}
+
+impl Not for &SelectionBitmap {
+    type Output = SelectionBitmap;
+
+    fn not(self) -> SelectionBitmap {
+        let mut words = self.words.iter().map(|word| !word).collect::<Vec<_>>();
+        // Keep the bits past the end unset
+        let tail_bits = self.len % 64;
+        if tail_bits > 0 {
+            if let Some(last) = words.last_mut() {
+                *last &= (1 << tail_bits) - 1;
+            }
+        }
+        SelectionBitmap {
+            words,
+            len: self.len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_leaves_bits_past_the_end_unset() {
+        for len in [0, 1, 63, 64, 65, 128, 130] {
+            let none = SelectionBitmap::new(len);
+            let all = !&none;
+            assert_eq!(all.count(), len, "len {}", len);
+            assert_eq!(
+                all.iter_selected().collect::<Vec<_>>(),
+                (0..len).collect::<Vec<_>>()
+            );
+            assert_eq!(!&all, none, "len {}", len);
+
+            let odd = SelectionBitmap::from_fn(len, |index| index % 2 == 1);
+            let even = !&odd;
+            assert_eq!(even.count(), len - odd.count(), "len {}", len);
+            assert_eq!(&odd & &even, none);
+            assert_eq!(&odd | &even, all);
+        }
+    }
+
+    #[test]
+    fn iter_selected_and_gather_follow_the_bits() {
+        let rows = (0..70).collect::<Vec<_>>();
+        let bitmap = SelectionBitmap::from_fn(rows.len(), |index| index % 3 == 0 || index == 67);
+        let expected = rows
+            .iter()
+            .copied()
+            .filter(|index| index % 3 == 0 || *index == 67)
+            .collect::<Vec<_>>();
+
+        assert_eq!(bitmap.iter_selected().collect::<Vec<_>>(), expected);
+        assert_eq!(bitmap.gather(&rows), expected);
+        assert_eq!(bitmap.count(), expected.len());
+        assert!(bitmap.is_selected(67));
+        assert!(!bitmap.is_selected(68));
+    }
+}
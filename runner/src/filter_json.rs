@@ -0,0 +1,517 @@
+//! Versioned JSON files for [`JoinFilters`] trees.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "filter": {
+//!     "all": [
+//!       { "filter": { "field": "email", "kind": "str_contains", "value": { "str": "example.com" } } },
+//!       { "not": { "filter": { "field": "birthdate", "kind": "int_less_than", "value": { "int": 0 } } } }
+//!     ]
+//!   }
+//! }
+//! ```
+//!
+//! Field and kind names are the snake case variant names. Bump [`FILTER_SCHEMA_VERSION`]
+//! whenever a change would make existing files parse differently.
+//!
+//! Filters over documents are read with the schema of the documents, which their field paths
+//! are looked up in, see [`document_filters_from_json`].
+
+use std::{collections::HashSet, fmt, path::Path};
+
+use serde::{
+    de::{
+        self, DeserializeOwned, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess,
+    },
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use shared::{
+    document::{DocumentField, DocumentSchema},
+    schema::RecordField,
+};
+
+use crate::{Filter, FilterError, FilterKind, FilterValue, JoinFilters};
+
+pub const FILTER_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "F: RecordField + Deserialize<'de>"))]
+struct FilterFile<F> {
+    version: u32,
+    filter: JoinFilters<F>,
+}
+
+#[derive(Debug)]
+pub enum FilterJsonError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for FilterJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterJsonError::Io(err) => write!(f, "{}", err),
+            FilterJsonError::Json(err) => write!(f, "{}", err),
+            FilterJsonError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported filter schema version {} (expected {})",
+                version, FILTER_SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FilterJsonError {}
+
+pub fn filters_to_json<F: RecordField + Serialize>(filters: &JoinFilters<F>) -> String {
+    let file = FilterFile {
+        version: FILTER_SCHEMA_VERSION,
+        filter: filters.clone(),
+    };
+    serde_json::to_string_pretty(&file).expect("filters always serialize")
+}
+
+pub fn filters_from_json(json: &str) -> Result<JoinFilters, FilterJsonError> {
+    record_filters_from_json(json)
+}
+
+/// Read filters over the fields of any record type
+pub fn record_filters_from_json<F: RecordField + DeserializeOwned>(
+    json: &str,
+) -> Result<JoinFilters<F>, FilterJsonError> {
+    check_version(json)?;
+    let file: FilterFile<F> = serde_json::from_str(json).map_err(FilterJsonError::Json)?;
+    Ok(file.filter)
+}
+
+/// Read filters over documents, with their fields looked up in `schema`
+pub fn document_filters_from_json(
+    schema: &DocumentSchema,
+    json: &str,
+) -> Result<JoinFilters<DocumentField>, FilterJsonError> {
+    #[derive(Deserialize)]
+    struct DocumentFilterFile {
+        filter: serde_json::Value,
+    }
+
+    check_version(json)?;
+    let file: DocumentFilterFile = serde_json::from_str(json).map_err(FilterJsonError::Json)?;
+    let seed = FiltersSeed {
+        lookup: &|name| schema.field(name),
+    };
+    seed.deserialize(file.filter).map_err(FilterJsonError::Json)
+}
+
+/// Check the version on its own first, so that files from other versions give a clear error
+/// rather than whatever the schema differences happen to trip over
+fn check_version(json: &str) -> Result<(), FilterJsonError> {
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+
+    let version: Version = serde_json::from_str(json).map_err(FilterJsonError::Json)?;
+    if version.version != FILTER_SCHEMA_VERSION {
+        return Err(FilterJsonError::UnsupportedVersion(version.version));
+    }
+    Ok(())
+}
+
+pub fn save_filters<F: RecordField + Serialize>(
+    path: impl AsRef<Path>,
+    filters: &JoinFilters<F>,
+) -> Result<(), FilterJsonError> {
+    std::fs::write(path, filters_to_json(filters)).map_err(FilterJsonError::Io)
+}
+
+pub fn load_filters(path: impl AsRef<Path>) -> Result<JoinFilters, FilterJsonError> {
+    load_record_filters(path)
+}
+
+pub fn load_record_filters<F: RecordField + DeserializeOwned>(
+    path: impl AsRef<Path>,
+) -> Result<JoinFilters<F>, FilterJsonError> {
+    let json = std::fs::read_to_string(path).map_err(FilterJsonError::Io)?;
+    record_filters_from_json(&json)
+}
+
+pub fn load_document_filters(
+    schema: &DocumentSchema,
+    path: impl AsRef<Path>,
+) -> Result<JoinFilters<DocumentField>, FilterJsonError> {
+    let json = std::fs::read_to_string(path).map_err(FilterJsonError::Io)?;
+    document_filters_from_json(schema, &json)
+}
+
+/// Unvalidated [`Filter`], deserialized filters go through [`Filter::new`]
+#[derive(Deserialize)]
+pub(crate) struct FilterDef<F> {
+    field: F,
+    kind: FilterKind,
+    value: FilterValue,
+}
+
+impl<F: RecordField> TryFrom<FilterDef<F>> for Filter<F> {
+    type Error = FilterError<F>;
+
+    fn try_from(def: FilterDef<F>) -> Result<Self, Self::Error> {
+        Filter::new(def.field, def.kind, def.value)
+    }
+}
+
+/// Reads a [`JoinFilters`] tree in the same format as its `Deserialize`, with fields looked up
+/// by name with `lookup` rather than `F::from_name`
+struct FiltersSeed<'a, F> {
+    lookup: &'a dyn Fn(&str) -> Option<F>,
+}
+
+impl<F> Clone for FiltersSeed<'_, F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<F> Copy for FiltersSeed<'_, F> {}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", variant_identifier)]
+enum JoinFiltersVariant {
+    Filter,
+    And,
+    Or,
+    Not,
+    All,
+    Any,
+    AnyElement,
+    AllElements,
+}
+
+const JOIN_FILTERS_VARIANTS: &[&str] = &[
+    "filter",
+    "and",
+    "or",
+    "not",
+    "all",
+    "any",
+    "any_element",
+    "all_elements",
+];
+
+impl<'de, F: RecordField> DeserializeSeed<'de> for FiltersSeed<'_, F> {
+    type Value = JoinFilters<F>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_enum("JoinFilters", JOIN_FILTERS_VARIANTS, self)
+    }
+}
+
+impl<'de, F: RecordField> de::Visitor<'de> for FiltersSeed<'_, F> {
+    type Value = JoinFilters<F>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a filter")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        let (variant, access) = data.variant()?;
+        let filter_seed = FilterSeed(self);
+        Ok(match variant {
+            JoinFiltersVariant::Filter => {
+                JoinFilters::Filter(access.newtype_variant_seed(filter_seed)?)
+            }
+            JoinFiltersVariant::AnyElement => {
+                JoinFilters::AnyElement(access.newtype_variant_seed(filter_seed)?)
+            }
+            JoinFiltersVariant::AllElements => {
+                JoinFilters::AllElements(access.newtype_variant_seed(filter_seed)?)
+            }
+            JoinFiltersVariant::Not => {
+                JoinFilters::Not(Box::new(access.newtype_variant_seed(self)?))
+            }
+            JoinFiltersVariant::And => {
+                let (left, right) = access.tuple_variant(2, PairSeed(self))?;
+                JoinFilters::And(Box::new(left), Box::new(right))
+            }
+            JoinFiltersVariant::Or => {
+                let (left, right) = access.tuple_variant(2, PairSeed(self))?;
+                JoinFilters::Or(Box::new(left), Box::new(right))
+            }
+            JoinFiltersVariant::All => {
+                JoinFilters::All(access.newtype_variant_seed(ListSeed(self))?)
+            }
+            JoinFiltersVariant::Any => {
+                JoinFilters::Any(access.newtype_variant_seed(ListSeed(self))?)
+            }
+        })
+    }
+}
+
+/// The two children of `And` and `Or`
+struct PairSeed<'a, F>(FiltersSeed<'a, F>);
+
+impl<'de, F: RecordField> de::Visitor<'de> for PairSeed<'_, F> {
+    type Value = (JoinFilters<F>, JoinFilters<F>);
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("two filters")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let left = seq
+            .next_element_seed(self.0)?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let right = seq
+            .next_element_seed(self.0)?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok((left, right))
+    }
+}
+
+/// The children of `All` and `Any`
+struct ListSeed<'a, F>(FiltersSeed<'a, F>);
+
+impl<'de, F: RecordField> DeserializeSeed<'de> for ListSeed<'_, F> {
+    type Value = Vec<JoinFilters<F>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: RecordField> de::Visitor<'de> for ListSeed<'_, F> {
+    type Value = Vec<JoinFilters<F>>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of filters")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut children = Vec::new();
+        while let Some(child) = seq.next_element_seed(self.0)? {
+            children.push(child);
+        }
+        Ok(children)
+    }
+}
+
+/// A [`FilterDef`], checked with [`Filter::new`] like deserialized filters are
+struct FilterSeed<'a, F>(FiltersSeed<'a, F>);
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum FilterDefField {
+    Field,
+    Kind,
+    Value,
+}
+
+impl<'de, F: RecordField> DeserializeSeed<'de> for FilterSeed<'_, F> {
+    type Value = Filter<F>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_struct("Filter", &["field", "kind", "value"], self)
+    }
+}
+
+impl<'de, F: RecordField> de::Visitor<'de> for FilterSeed<'_, F> {
+    type Value = Filter<F>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a filter on a field")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut field, mut kind, mut value) = (None, None, None);
+        while let Some(key) = map.next_key()? {
+            match key {
+                FilterDefField::Field => {
+                    let name: String = map.next_value()?;
+                    let found = (self.0.lookup)(&name)
+                        .ok_or_else(|| de::Error::custom(format!("unknown field `{}`", name)))?;
+                    field = Some(found);
+                }
+                FilterDefField::Kind => kind = Some(map.next_value()?),
+                FilterDefField::Value => value = Some(map.next_value()?),
+            }
+        }
+
+        let field = field.ok_or_else(|| de::Error::missing_field("field"))?;
+        let kind = kind.ok_or_else(|| de::Error::missing_field("kind"))?;
+        let value = value.ok_or_else(|| de::Error::missing_field("value"))?;
+        Filter::new(field, kind, value).map_err(de::Error::custom)
+    }
+}
+
+pub(crate) mod regex_pattern {
+    use std::sync::Arc;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::regex_dfa::RegexDfa;
+
+    pub fn serialize<S: Serializer>(
+        regex: &Arc<RegexDfa>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(regex.pattern())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<RegexDfa>, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        let regex = RegexDfa::new(&pattern).map_err(D::Error::custom)?;
+        Ok(Arc::new(regex))
+    }
+}
+
+/// Serialize sets in sorted order so saved files don't change between runs
+pub(crate) fn serialize_sorted<S: Serializer>(
+    values: &HashSet<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut values = values.iter().collect::<Vec<_>>();
+    values.sort_unstable();
+    values.serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::document::Document;
+
+    use super::*;
+    use crate::Field;
+
+    fn filter(field: Field, kind: FilterKind, value: FilterValue) -> JoinFilters {
+        JoinFilters::Filter(Filter::new(field, kind, value).unwrap())
+    }
+
+    fn error_message(result: Result<impl fmt::Debug, FilterJsonError>) -> String {
+        match result.unwrap_err() {
+            FilterJsonError::Json(err) => err.to_string(),
+            err => panic!("expected a JSON error, found {:?}", err),
+        }
+    }
+
+    fn file(filter: &str) -> String {
+        format!(r#"{{ "version": 1, "filter": {} }}"#, filter)
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let filters = JoinFilters::All(vec![
+            filter(
+                Field::Email,
+                FilterKind::StrMatches,
+                FilterValue::regex("^[a-z]+@example\\.com$").unwrap(),
+            ),
+            JoinFilters::Or(
+                Box::new(filter(
+                    Field::Gender,
+                    FilterKind::StrIn,
+                    FilterValue::str_set(["male", "female", "other"]),
+                )),
+                Box::new(JoinFilters::Not(Box::new(filter(
+                    Field::Birthdate,
+                    FilterKind::IntBetween,
+                    FilterValue::IntRange(0, 100),
+                )))),
+            ),
+            JoinFilters::AnyElement(
+                Filter::new(
+                    Field::Tags,
+                    FilterKind::StrEqualsIgnoreCase,
+                    FilterValue::Str("VIP".into()),
+                )
+                .unwrap(),
+            ),
+        ]);
+
+        let json = filters_to_json(&filters);
+        assert_eq!(filters_from_json(&json).unwrap(), filters);
+
+        // Sets are written sorted, so the same filters always give the same file
+        let values: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let set = &values["filter"]["all"][1]["or"][0]["filter"]["value"]["str_set"];
+        assert_eq!(*set, serde_json::json!(["female", "male", "other"]));
+        assert_eq!(
+            values["filter"]["all"][0]["filter"]["value"]["regex"],
+            "^[a-z]+@example\\.com$"
+        );
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let json = r#"{ "version": 2, "filter": { "any": [] } }"#;
+        assert!(matches!(
+            filters_from_json(json),
+            Err(FilterJsonError::UnsupportedVersion(2))
+        ));
+
+        let schema = DocumentSchema::infer(&[]);
+        assert!(matches!(
+            document_filters_from_json(&schema, json),
+            Err(FilterJsonError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let json = file(
+            r#"{ "filter": { "field": "shoe_size", "kind": "int_equals", "value": { "int": 9 } } }"#,
+        );
+        assert!(error_message(filters_from_json(&json)).contains("shoe_size"));
+
+        let document: Document = serde_json::from_str(r#"{ "name": "Ada" }"#).unwrap();
+        let schema = DocumentSchema::infer(&[document]);
+        assert!(error_message(document_filters_from_json(&schema, &json))
+            .contains("unknown field `shoe_size`"));
+    }
+
+    #[test]
+    fn checks_filters_with_filter_new() {
+        // A string kind with an integer value
+        let json = file(
+            r#"{ "filter": { "field": "email", "kind": "str_equals", "value": { "int": 1 } } }"#,
+        );
+        let message = FilterError::<Field>::InvalidValue {
+            kind: FilterKind::StrEquals,
+        }
+        .to_string();
+        assert!(error_message(filters_from_json(&json)).contains(&message));
+
+        let document: Document = serde_json::from_str(r#"{ "email": "a@b.c" }"#).unwrap();
+        let schema = DocumentSchema::infer(&[document]);
+        assert!(error_message(document_filters_from_json(&schema, &json)).contains(&message));
+    }
+
+    #[test]
+    fn parses_the_module_example() {
+        let json = r#"{
+  "version": 1,
+  "filter": {
+    "all": [
+      { "filter": { "field": "email", "kind": "str_contains", "value": { "str": "example.com" } } },
+      { "not": { "filter": { "field": "birthdate", "kind": "int_less_than", "value": { "int": 0 } } } }
+    ]
+  }
+}"#;
+        assert_eq!(
+            filters_from_json(json).unwrap(),
+            JoinFilters::All(vec![
+                filter(
+                    Field::Email,
+                    FilterKind::StrContains,
+                    FilterValue::Str("example.com".into()),
+                ),
+                JoinFilters::Not(Box::new(filter(
+                    Field::Birthdate,
+                    FilterKind::IntLessThan,
+                    FilterValue::Int(0),
+                ))),
+            ])
+        );
+    }
+}
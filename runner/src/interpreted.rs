@@ -1,49 +1,294 @@
-use shared::User;
+use shared::{
+    case_fold,
+    schema::{Record, RecordField},
+    ProjectedValue,
+};
 
-use crate::{Field, Filter, FilterKind, JoinFilters};
+use crate::{
+    aggregate::AggregateRow,
+    bitmap::SelectionBitmap,
+    order::{compare_records, new_top_k},
+    plan::{Operator, Plan, PlanError, PlanOutput},
+    prepare_filters,
+    projection::ProjectedRows,
+    Filter, FilterKind, FilterOptions, JoinFilters,
+};
 
-fn get_field(user: &User, field: Field) -> &str {
-    match field {
-        Field::Email => &user.email,
-        Field::Gender => &user.gender,
-        Field::PhoneNumber => &user.phone_number,
-        Field::LocationStreet => &user.location.street,
-        Field::LocationCity => &user.location.city,
-        Field::LocationState => &user.location.state,
-        Field::Username => &user.username,
-        Field::Password => &user.password,
-        Field::FirstName => &user.first_name,
-        Field::LastName => &user.last_name,
-        Field::Title => &user.title,
-        Field::Picture => &user.picture,
+pub(crate) fn get_field_value<F: RecordField>(record: &F::Record, field: F) -> ProjectedValue<'_> {
+    if !record.has_value(field) {
+        ProjectedValue::Null
+    } else if field.is_numeric() {
+        ProjectedValue::Int(record.int_field(field))
+    } else {
+        ProjectedValue::Str(record.str_field(field))
     }
 }
 
-pub fn run_filter(user: &User, filter: &Filter) -> bool {
-    let field = get_field(user, filter.field);
+pub fn run_filter<F: RecordField>(record: &F::Record, filter: &Filter<F>) -> bool {
+    if filter.field.is_list() {
+        return run_elements_filter(record, filter, false);
+    }
+
+    // A missing value matches nothing but `IsNull`
+    let has_value = record.has_value(filter.field);
+    match filter.kind {
+        FilterKind::IsNull => !has_value,
+        FilterKind::IsNotNull => has_value,
+        _ if !has_value => false,
+        kind if kind.is_numeric() => run_int_predicate(record.int_field(filter.field), filter),
+        _ => run_str_predicate(record.str_field(filter.field), filter),
+    }
+}
+
+/// `AnyElement` (or `AllElements` if `is_all`) of the filter
+fn run_elements_filter<F: RecordField>(
+    record: &F::Record,
+    filter: &Filter<F>,
+    is_all: bool,
+) -> bool {
+    if !filter.field.is_list() {
+        return run_filter(record, filter);
+    }
+
+    let mut elements = record.str_list_field(filter.field).iter();
+    if is_all {
+        elements.all(|element| run_str_predicate(element, filter))
+    } else {
+        elements.any(|element| run_str_predicate(element, filter))
+    }
+}
+
+fn run_str_predicate<F>(field: &str, filter: &Filter<F>) -> bool {
+    let value = &filter.value;
+    match filter.kind {
+        FilterKind::StrContains => field.contains(value.as_str()),
+        FilterKind::StrEquals => field == value.as_str(),
+        FilterKind::StrStartsWith => field.starts_with(value.as_str()),
+        FilterKind::StrEndsWith => field.ends_with(value.as_str()),
+        // The needle was folded when the filter was built
+        FilterKind::StrContainsIgnoreCase => case_fold::folded_contains(field, value.as_str()),
+        FilterKind::StrEqualsIgnoreCase => case_fold::folded_equals(field, value.as_str()),
+        FilterKind::StrStartsWithIgnoreCase => case_fold::folded_starts_with(field, value.as_str()),
+        FilterKind::StrEndsWithIgnoreCase => case_fold::folded_ends_with(field, value.as_str()),
+        FilterKind::StrMatches => value.as_regex().is_match(field.as_bytes()),
+        FilterKind::StrIn => value.as_str_set().contains(field),
+        kind => panic!("{:?} is not a string filter", kind),
+    }
+}
+
+fn run_int_predicate<F>(field: u64, filter: &Filter<F>) -> bool {
+    let value = &filter.value;
     match filter.kind {
-        FilterKind::StrContains => field.contains(&filter.value),
-        FilterKind::StrEquals => field == &filter.value,
-        FilterKind::StrStartsWith => field.starts_with(&filter.value),
-        FilterKind::StrEndsWith => field.ends_with(&filter.value),
+        FilterKind::IntEquals => field == value.as_int(),
+        FilterKind::IntNotEquals => field != value.as_int(),
+        FilterKind::IntLessThan => field < value.as_int(),
+        FilterKind::IntLessOrEqual => field <= value.as_int(),
+        FilterKind::IntGreaterThan => field > value.as_int(),
+        FilterKind::IntGreaterOrEqual => field >= value.as_int(),
+        FilterKind::IntBetween => {
+            let (min, max) = value.as_int_range();
+            (min..=max).contains(&field)
+        }
+        kind => panic!("{:?} is not an integer filter", kind),
     }
 }
 
-pub fn run_join_filters(user: &User, join_filters: &JoinFilters) -> bool {
+pub fn run_join_filters<F: RecordField>(record: &F::Record, join_filters: &JoinFilters<F>) -> bool {
     match join_filters {
-        JoinFilters::Filter(filter) => run_filter(user, filter),
+        JoinFilters::Filter(filter) => run_filter(record, filter),
         JoinFilters::And(left, right) => {
-            run_join_filters(user, left) && run_join_filters(user, right)
+            run_join_filters(record, left) && run_join_filters(record, right)
         }
         JoinFilters::Or(left, right) => {
-            run_join_filters(user, left) || run_join_filters(user, right)
+            run_join_filters(record, left) || run_join_filters(record, right)
         }
+        JoinFilters::Not(inner) => !run_join_filters(record, inner),
+        JoinFilters::All(children) => children.iter().all(|child| run_join_filters(record, child)),
+        JoinFilters::Any(children) => children.iter().any(|child| run_join_filters(record, child)),
+        JoinFilters::AnyElement(filter) => run_elements_filter(record, filter, false),
+        JoinFilters::AllElements(filter) => run_elements_filter(record, filter, true),
     }
 }
 
-pub fn filter_vec_with_filters(arr: &[User], filters: &JoinFilters) -> Vec<User> {
+pub fn filter_vec_with_filters<F: RecordField>(
+    arr: &[F::Record],
+    filters: &JoinFilters<F>,
+) -> Vec<F::Record> {
+    filter_vec_with_options(arr, filters, &FilterOptions::default())
+}
+
+pub fn filter_vec_with_options<F: RecordField>(
+    arr: &[F::Record],
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
+) -> Vec<F::Record> {
+    let filters = prepare_filters(filters, options);
     arr.iter()
-        .filter(|user| run_join_filters(user, filters))
+        .filter(|&record| run_join_filters(record, &filters))
         .cloned()
         .collect()
 }
+
+pub fn filter_indices_with_filters<F: RecordField>(
+    arr: &[F::Record],
+    filters: &JoinFilters<F>,
+) -> Vec<usize> {
+    let filters = prepare_filters(filters, &FilterOptions::default());
+    arr.iter()
+        .enumerate()
+        .filter(|&(_, record)| run_join_filters(record, &filters))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+pub fn filter_refs_with_filters<'a, F: RecordField>(
+    arr: &'a [F::Record],
+    filters: &JoinFilters<F>,
+) -> Vec<&'a F::Record> {
+    let filters = prepare_filters(filters, &FilterOptions::default());
+    arr.iter()
+        .filter(|&record| run_join_filters(record, &filters))
+        .collect()
+}
+
+pub fn filter_bitmap_with_filters<F: RecordField>(
+    arr: &[F::Record],
+    filters: &JoinFilters<F>,
+) -> SelectionBitmap {
+    let filters = prepare_filters(filters, &FilterOptions::default());
+    SelectionBitmap::from_fn(arr.len(), |index| run_join_filters(&arr[index], &filters))
+}
+
+pub fn filter_any_with_filters<F: RecordField>(
+    arr: &[F::Record],
+    filters: &JoinFilters<F>,
+) -> bool {
+    let filters = prepare_filters(filters, &FilterOptions::default());
+    arr.iter().any(|record| run_join_filters(record, &filters))
+}
+
+pub fn filter_all_with_filters<F: RecordField>(
+    arr: &[F::Record],
+    filters: &JoinFilters<F>,
+) -> bool {
+    let filters = prepare_filters(filters, &FilterOptions::default());
+    arr.iter().all(|record| run_join_filters(record, &filters))
+}
+
+pub fn filter_count_with_filters<F: RecordField>(
+    arr: &[F::Record],
+    filters: &JoinFilters<F>,
+) -> usize {
+    let filters = prepare_filters(filters, &FilterOptions::default());
+    arr.iter()
+        .filter(|&record| run_join_filters(record, &filters))
+        .count()
+}
+
+pub fn filter_find_first_with_filters<'a, F: RecordField>(
+    arr: &'a [F::Record],
+    filters: &JoinFilters<F>,
+) -> Option<&'a F::Record> {
+    let filters = prepare_filters(filters, &FilterOptions::default());
+    arr.iter()
+        .find(|&record| run_join_filters(record, &filters))
+}
+
+/// Only the fields in `options.projection` of each matching record, which can't be list fields
+pub fn filter_projected_with_options<'a, F: RecordField>(
+    arr: &'a [F::Record],
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
+) -> Result<ProjectedRows<'a, F>, PlanError<F>> {
+    options.check_fields()?;
+    let filters = prepare_filters(filters, options);
+    let values = arr
+        .iter()
+        .filter(|&record| run_join_filters(record, &filters))
+        .flat_map(|record| {
+            options
+                .projection
+                .iter()
+                .map(|&field| get_field_value(record, field))
+        })
+        .collect();
+    Ok(ProjectedRows::new(options.projection, values))
+}
+
+/// Run the aggregate query from `options.aggregate` over the matching records
+pub fn filter_aggregate_with_options<'a, F: RecordField>(
+    arr: &'a [F::Record],
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
+) -> Vec<AggregateRow<'a>> {
+    let query = options
+        .aggregate
+        .expect("aggregating needs FilterOptions::aggregate to be set");
+    let filters = prepare_filters(filters, options);
+
+    let mut state = query.new_state();
+    for record in arr {
+        if run_join_filters(record, &filters) {
+            query.accumulate(&mut state, record);
+        }
+    }
+    query.finish(state)
+}
+
+/// The first `options.limit` matching records, sorted by `options.order_by`, which can't have
+/// list fields
+pub fn filter_ordered_with_options<'a, F: RecordField>(
+    arr: &'a [F::Record],
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
+) -> Result<Vec<&'a F::Record>, PlanError<F>> {
+    options.check_fields()?;
+    let filters = prepare_filters(filters, options);
+    let less = |a: &F::Record, b: &F::Record| compare_records(a, b, options.order_by).is_lt();
+
+    let mut top_k = new_top_k(options.limit);
+    for record in arr {
+        if options.order_by.is_empty() && top_k.is_full() {
+            break;
+        }
+        if run_join_filters(record, &filters) {
+            top_k.push(record, less);
+        }
+    }
+    top_k.sort(less);
+    Ok(top_k.rows)
+}
+
+/// Run a plan one operator at a time, each over the whole output of the one before it
+pub fn execute_plan<'a, F: RecordField>(
+    arr: &'a [F::Record],
+    plan: &Plan<F>,
+    options: &FilterOptions<F>,
+) -> Result<PlanOutput<'a, F>, PlanError<F>> {
+    let mut rows = arr.iter().collect::<Vec<_>>();
+    for operator in plan.operators(options)? {
+        match operator {
+            Operator::Filter(filters) => rows.retain(|&record| run_join_filters(record, &filters)),
+            Operator::Limit(limit) => rows.truncate(limit),
+            Operator::Sort { order_by, limit } => {
+                rows.sort_by(|&a, &b| compare_records(a, b, &order_by));
+                rows.truncate(limit.unwrap_or(usize::MAX));
+            }
+            Operator::Project(fields) => {
+                let values = rows
+                    .iter()
+                    .flat_map(|&record| fields.iter().map(|&field| get_field_value(record, field)))
+                    .collect();
+                return Ok(PlanOutput::Projected(ProjectedRows::new(&fields, values)));
+            }
+            Operator::Aggregate(query) => {
+                let mut state = query.new_state();
+                for record in rows {
+                    query.accumulate(&mut state, record);
+                }
+                return Ok(PlanOutput::Aggregated(query.finish(state)));
+            }
+        }
+    }
+    Ok(PlanOutput::Rows(rows))
+}
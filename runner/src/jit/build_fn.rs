@@ -1,29 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
 use llvm_sys::core::*;
 use llvm_sys::prelude::{
-    LLVMBasicBlockRef, LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMValueRef,
+    LLVMBasicBlockRef, LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMTypeRef, LLVMValueRef,
 };
 use llvm_sys::*;
+use shared::{
+    schema::{Record, RecordField},
+    substring_search,
+};
+
+use crate::{
+    aggregate::{Aggregate, AggregateQuery},
+    order::{SortDirection, SortKey},
+    Filter, FilterKind, FilterOptions, FilterValue, JoinFilters,
+};
+
+use super::{record_symbol, to_c_str};
 
-use crate::{Field, Filter, JoinFilters};
+/// Longer constants are compared with the generic functions, to avoid bloating the IR
+const MAX_INLINE_STR_COMPARE_LEN: usize = 64;
 
-use super::to_c_str;
+/// Values that are worth computing only once per filter function
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ValueKey<F> {
+    Field(F),
+    /// Whether a nullable field has a value
+    HasValue(F),
+    FieldPtr(F),
+    FieldLen(F),
+    /// A constant from [`FnBuilder::build_global_str`], converted to a `&str`
+    Str(String),
+    /// The value a document path leads to, by the path's keys joined with `.`
+    PathValue(String),
+    /// The field case folded into its buffer, for fields in `FnBuilder::folded_fields`
+    FoldedField(F),
+}
 
-struct FnBuilder {
+struct FnBuilder<F> {
     module: LLVMModuleRef,
     context: LLVMContextRef,
     builder: LLVMBuilderRef,
     user_arg: LLVMValueRef,
     str_counter: usize,
+    inline_str_compares: bool,
+    /// Globals are usable from anywhere in the module, so identical constants share one
+    str_globals: HashMap<String, LLVMValueRef>,
+    skip_tables: HashMap<String, LLVMValueRef>,
+    /// Values already built in a block that dominates the current insertion point, see
+    /// `build_join_filter` for how this is kept in sync with the blocks being built
+    values: HashMap<ValueKey<F>, LLVMValueRef>,
+    /// Fields read by more than one ignore case filter, which are folded once and then
+    /// compared with the plain string filters rather than folded by each filter
+    folded_fields: HashSet<F>,
+    /// `String` buffers the folded fields are written to, which are initialized on entry and
+    /// have to be dropped before returning
+    str_buffers: Vec<(F, LLVMValueRef)>,
 }
 
-pub unsafe fn build_filter_fn(
+/// Fields that more than one ignore case filter reads. List elements are left out, as each
+/// element is only read by the one filter in the loop over them.
+fn fields_folded_more_than_once<F: RecordField>(filters: &JoinFilters<F>) -> HashSet<F> {
+    fn count<F: RecordField>(filters: &JoinFilters<F>, counts: &mut HashMap<F, usize>) {
+        match filters {
+            JoinFilters::Filter(filter) => {
+                if filter.kind.is_ignore_case() && !filter.field.is_list() {
+                    *counts.entry(filter.field).or_default() += 1;
+                }
+            }
+            JoinFilters::AnyElement(_) | JoinFilters::AllElements(_) => {}
+            JoinFilters::Not(inner) => count(inner, counts),
+            JoinFilters::And(left, right) | JoinFilters::Or(left, right) => {
+                count(left, counts);
+                count(right, counts);
+            }
+            JoinFilters::All(children) | JoinFilters::Any(children) => {
+                for child in children {
+                    count(child, counts);
+                }
+            }
+        }
+    }
+
+    let mut counts = HashMap::new();
+    count(filters, &mut counts);
+    counts
+        .into_iter()
+        .filter(|&(_, count)| count > 1)
+        .map(|(field, _)| field)
+        .collect()
+}
+
+/// Split `len` bytes into `(offset, len)` chunks of the widest size that fits. Instead of
+/// falling back to smaller chunks at the end, the last chunk overlaps the previous one.
+fn compare_chunks(len: usize) -> Vec<(usize, usize)> {
+    let Some(chunk_len) = [8, 4, 2, 1].into_iter().find(|&size| size <= len) else {
+        return Vec::new();
+    };
+
+    let mut chunks = (0..len / chunk_len)
+        .map(|i| (i * chunk_len, chunk_len))
+        .collect::<Vec<_>>();
+    if chunks.len() * chunk_len < len {
+        chunks.push((len - chunk_len, chunk_len));
+    }
+    chunks
+}
+
+pub unsafe fn build_filter_fn<F: RecordField>(
     name: &str,
     module: LLVMModuleRef,
     context: LLVMContextRef,
-    filters: &JoinFilters,
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
 ) {
     // Grab the function signature we want to copy and add it to the module
-    let fn_val = LLVMGetNamedFunction(module, to_c_str("filter_fn_sig").as_ptr());
+    let sig_name = record_symbol::<F::Record>("filter_fn_sig");
+    let fn_val = LLVMGetNamedFunction(module, to_c_str(&sig_name).as_ptr());
     let fn_type = LLVMGlobalGetValueType(fn_val);
     let fn_value = LLVMAddFunction(module, to_c_str(name).as_ptr(), fn_type);
 
@@ -39,33 +132,353 @@ pub unsafe fn build_filter_fn(
 
     let builder = LLVMCreateBuilderInContext(context);
 
+    // Position at entry block
+    LLVMPositionBuilderAtEnd(builder, entry_block);
+
+    let mut builder = FnBuilder::new(module, context, builder, user_arg, options);
+    builder.folded_fields = fields_folded_more_than_once(filters);
+
+    builder.build_join_filter(filters, fail_block, success_block);
+
+    // The return blocks are built last, once it's known which buffers they have to drop
+    let i1_ty = LLVMInt1TypeInContext(context);
+
     // Build fail condition (return false)
-    LLVMPositionBuilderAtEnd(builder, fail_block);
-    let false_value = LLVMConstInt(LLVMInt1TypeInContext(context), 0, 0);
-    LLVMBuildRet(builder, false_value);
+    LLVMPositionBuilderAtEnd(builder.builder, fail_block);
+    builder.build_str_buffer_drops();
+    LLVMBuildRet(builder.builder, LLVMConstInt(i1_ty, 0, 0));
 
     // Build success condition (return true)
-    LLVMPositionBuilderAtEnd(builder, success_block);
-    let true_value = LLVMConstInt(LLVMInt1TypeInContext(context), 1, 0);
-    LLVMBuildRet(builder, true_value);
+    LLVMPositionBuilderAtEnd(builder.builder, success_block);
+    builder.build_str_buffer_drops();
+    LLVMBuildRet(builder.builder, LLVMConstInt(i1_ty, 1, 0));
 
-    // Position at entry block
+    LLVMDisposeBuilder(builder.builder);
+}
+
+/// Build a function that pushes the given fields of a record onto a projection output vec
+pub unsafe fn build_project_fn<F: RecordField>(
+    name: &str,
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    fields: &[F],
+    options: &FilterOptions<F>,
+) {
+    // Grab the function signature we want to copy and add it to the module
+    let sig_name = record_symbol::<F::Record>("project_fn_sig");
+    let fn_val = LLVMGetNamedFunction(module, to_c_str(&sig_name).as_ptr());
+    let fn_type = LLVMGlobalGetValueType(fn_val);
+    let fn_value = LLVMAddFunction(module, to_c_str(name).as_ptr(), fn_type);
+
+    // Function should be private
+    LLVMSetLinkage(fn_value, LLVMLinkage::LLVMPrivateLinkage);
+
+    let entry_block = LLVMAppendBasicBlockInContext(context, fn_value, to_c_str("entry").as_ptr());
+    let user_arg = LLVMGetParam(fn_value, 0);
+    let output_arg = LLVMGetParam(fn_value, 1);
+
+    let builder = LLVMCreateBuilderInContext(context);
     LLVMPositionBuilderAtEnd(builder, entry_block);
 
-    let mut builder = FnBuilder {
-        module,
-        context,
-        builder,
-        user_arg,
-        str_counter: 0,
+    let mut builder = FnBuilder::new(module, context, builder, user_arg, options);
+    let i64_ty = LLVMInt64TypeInContext(context);
+
+    for &field in fields {
+        let value = builder.build_get_user_field(field);
+        let value = if field.is_numeric() {
+            LLVMBuildZExtOrBitCast(builder.builder, value, i64_ty, to_c_str("value").as_ptr())
+        } else {
+            value
+        };
+
+        if field.is_nullable() {
+            let has_value = builder.build_has_value(field);
+            let push_fn = if field.is_numeric() {
+                "project_push_nullable_int"
+            } else {
+                "project_push_nullable_str"
+            };
+            builder.make_call(push_fn, "", &mut [output_arg, has_value, value]);
+        } else if field.is_numeric() {
+            builder.make_call("project_push_int", "", &mut [output_arg, value]);
+        } else {
+            builder.make_call("project_push_str", "", &mut [output_arg, value]);
+        }
+    }
+
+    LLVMBuildRetVoid(builder.builder);
+
+    LLVMDisposeBuilder(builder.builder);
+}
+
+/// Build a function that adds a matching record to an `AggregateState`, with the slot layout
+/// from `AggregateQuery::slots`
+pub unsafe fn build_aggregate_fn<F: RecordField>(
+    name: &str,
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    query: &AggregateQuery<F>,
+    options: &FilterOptions<F>,
+) {
+    // Grab the function signature we want to copy and add it to the module
+    let sig_name = record_symbol::<F::Record>("aggregate_fn_sig");
+    let fn_val = LLVMGetNamedFunction(module, to_c_str(&sig_name).as_ptr());
+    let fn_type = LLVMGlobalGetValueType(fn_val);
+    let fn_value = LLVMAddFunction(module, to_c_str(name).as_ptr(), fn_type);
+
+    // Function should be private
+    LLVMSetLinkage(fn_value, LLVMLinkage::LLVMPrivateLinkage);
+
+    let entry_block = LLVMAppendBasicBlockInContext(context, fn_value, to_c_str("entry").as_ptr());
+    let user_arg = LLVMGetParam(fn_value, 0);
+    let state_arg = LLVMGetParam(fn_value, 1);
+
+    let builder = LLVMCreateBuilderInContext(context);
+    LLVMPositionBuilderAtEnd(builder, entry_block);
+
+    let mut builder = FnBuilder::new(module, context, builder, user_arg, options);
+    let i64_ty = LLVMInt64TypeInContext(context);
+
+    let slots = match query.group_by() {
+        Some(field) => {
+            let key = builder.build_get_user_field(field);
+            builder.make_call("aggregate_state_group", "slots", &mut [state_arg, key])
+        }
+        None => builder.make_call("aggregate_state_totals", "slots", &mut [state_arg]),
     };
 
-    builder.build_join_filter(filters, fail_block, success_block);
+    let llvm_builder = builder.builder;
+    let build_slot_ptr = |slot: usize| {
+        LLVMBuildGEP2(
+            llvm_builder,
+            i64_ty,
+            slots,
+            [LLVMConstInt(i64_ty, slot as u64, 0)].as_mut_ptr(),
+            1,
+            to_c_str("slot_ptr").as_ptr(),
+        )
+    };
+
+    // Slot 0 counts the matching users
+    let count_ptr = build_slot_ptr(0);
+    let count = LLVMBuildLoad2(
+        builder.builder,
+        i64_ty,
+        count_ptr,
+        to_c_str("count").as_ptr(),
+    );
+    let count = LLVMBuildAdd(
+        builder.builder,
+        count,
+        LLVMConstInt(i64_ty, 1, 0),
+        to_c_str("count").as_ptr(),
+    );
+    LLVMBuildStore(builder.builder, count, count_ptr);
+
+    for (aggregate, slot) in query.slots() {
+        let (field, predicate) = match aggregate {
+            Aggregate::Count => continue,
+            Aggregate::Min(field) => (field, LLVMIntPredicate::LLVMIntULT),
+            Aggregate::Max(field) => (field, LLVMIntPredicate::LLVMIntUGT),
+        };
+
+        let value = builder.build_get_user_field(field);
+        let value =
+            LLVMBuildZExtOrBitCast(builder.builder, value, i64_ty, to_c_str("value").as_ptr());
+
+        let slot_ptr = build_slot_ptr(slot);
+        let current = LLVMBuildLoad2(
+            builder.builder,
+            i64_ty,
+            slot_ptr,
+            to_c_str("current").as_ptr(),
+        );
+        let replace = LLVMBuildICmp(
+            builder.builder,
+            predicate,
+            value,
+            current,
+            to_c_str("replace").as_ptr(),
+        );
+        let new = LLVMBuildSelect(
+            builder.builder,
+            replace,
+            value,
+            current,
+            to_c_str("new").as_ptr(),
+        );
+        LLVMBuildStore(builder.builder, new, slot_ptr);
+    }
+
+    LLVMBuildRetVoid(builder.builder);
 
     LLVMDisposeBuilder(builder.builder);
 }
 
-impl FnBuilder {
+/// Build a function that returns whether the first record comes before the second by the
+/// sort keys, with the same tie-break on the input order as `order::compare_records`
+pub unsafe fn build_sort_less_fn<F: RecordField>(
+    name: &str,
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    order_by: &[SortKey<F>],
+    options: &FilterOptions<F>,
+) {
+    // Grab the function signature we want to copy and add it to the module
+    let sig_name = record_symbol::<F::Record>("sort_less_fn_sig");
+    let fn_val = LLVMGetNamedFunction(module, to_c_str(&sig_name).as_ptr());
+    let fn_type = LLVMGlobalGetValueType(fn_val);
+    let fn_value = LLVMAddFunction(module, to_c_str(name).as_ptr(), fn_type);
+
+    // Function should be private
+    LLVMSetLinkage(fn_value, LLVMLinkage::LLVMPrivateLinkage);
+
+    let entry_block = LLVMAppendBasicBlockInContext(context, fn_value, to_c_str("entry").as_ptr());
+    let a_arg = LLVMGetParam(fn_value, 0);
+    let b_arg = LLVMGetParam(fn_value, 1);
+
+    let less_block = LLVMAppendBasicBlockInContext(context, fn_value, to_c_str("less").as_ptr());
+    let greater_block =
+        LLVMAppendBasicBlockInContext(context, fn_value, to_c_str("greater").as_ptr());
+
+    let builder = LLVMCreateBuilderInContext(context);
+    let i1_ty = LLVMInt1TypeInContext(context);
+
+    LLVMPositionBuilderAtEnd(builder, less_block);
+    LLVMBuildRet(builder, LLVMConstInt(i1_ty, 1, 0));
+
+    LLVMPositionBuilderAtEnd(builder, greater_block);
+    LLVMBuildRet(builder, LLVMConstInt(i1_ty, 0, 0));
+
+    LLVMPositionBuilderAtEnd(builder, entry_block);
+
+    // One builder per record, so each caches the fields of its own record
+    let mut a = FnBuilder::new(module, context, builder, a_arg, options);
+    let mut b = FnBuilder::new(module, context, builder, b_arg, options);
+    let i32_ty = LLVMInt32TypeInContext(context);
+
+    // Each key is compared in its own block, moving on to the next key only if they're equal
+    for key in order_by {
+        let a_value = a.build_get_user_field(key.field);
+        let b_value = b.build_get_user_field(key.field);
+
+        let (lt, gt) = if key.field.is_numeric() {
+            (
+                LLVMBuildICmp(
+                    builder,
+                    LLVMIntPredicate::LLVMIntULT,
+                    a_value,
+                    b_value,
+                    to_c_str("lt").as_ptr(),
+                ),
+                LLVMBuildICmp(
+                    builder,
+                    LLVMIntPredicate::LLVMIntUGT,
+                    a_value,
+                    b_value,
+                    to_c_str("gt").as_ptr(),
+                ),
+            )
+        } else {
+            let ordering = a.make_call("str_compare", "ordering", &mut [a_value, b_value]);
+            let zero = LLVMConstInt(i32_ty, 0, 0);
+            (
+                LLVMBuildICmp(
+                    builder,
+                    LLVMIntPredicate::LLVMIntSLT,
+                    ordering,
+                    zero,
+                    to_c_str("lt").as_ptr(),
+                ),
+                LLVMBuildICmp(
+                    builder,
+                    LLVMIntPredicate::LLVMIntSGT,
+                    ordering,
+                    zero,
+                    to_c_str("gt").as_ptr(),
+                ),
+            )
+        };
+
+        // Missing values come first, compare whether there is one before the values (which
+        // are equal if both are missing)
+        let (lt, gt) = if key.field.is_nullable() {
+            let a_has_value = a.build_has_value(key.field);
+            let b_has_value = b.build_has_value(key.field);
+            let build_cmp = |predicate, name: &str| {
+                LLVMBuildICmp(
+                    builder,
+                    predicate,
+                    a_has_value,
+                    b_has_value,
+                    to_c_str(name).as_ptr(),
+                )
+            };
+            let both_or_neither = build_cmp(LLVMIntPredicate::LLVMIntEQ, "both_or_neither");
+            let only_b = build_cmp(LLVMIntPredicate::LLVMIntULT, "only_b");
+            let only_a = build_cmp(LLVMIntPredicate::LLVMIntUGT, "only_a");
+            let lt = LLVMBuildAnd(builder, both_or_neither, lt, to_c_str("lt").as_ptr());
+            let gt = LLVMBuildAnd(builder, both_or_neither, gt, to_c_str("gt").as_ptr());
+            (
+                LLVMBuildOr(builder, only_b, lt, to_c_str("lt").as_ptr()),
+                LLVMBuildOr(builder, only_a, gt, to_c_str("gt").as_ptr()),
+            )
+        } else {
+            (lt, gt)
+        };
+
+        let (lt_block, gt_block) = match key.direction {
+            SortDirection::Ascending => (less_block, greater_block),
+            SortDirection::Descending => (greater_block, less_block),
+        };
+
+        let not_lt_block = a.append_block("not_lt");
+        let next_block = a.append_block("next_key");
+        LLVMBuildCondBr(builder, lt, lt_block, not_lt_block);
+        LLVMPositionBuilderAtEnd(builder, not_lt_block);
+        LLVMBuildCondBr(builder, gt, gt_block, next_block);
+        LLVMPositionBuilderAtEnd(builder, next_block);
+    }
+
+    // Equal keys keep the input order
+    let i64_ty = LLVMInt64TypeInContext(context);
+    let a_addr = LLVMBuildPtrToInt(builder, a_arg, i64_ty, to_c_str("a_addr").as_ptr());
+    let b_addr = LLVMBuildPtrToInt(builder, b_arg, i64_ty, to_c_str("b_addr").as_ptr());
+    let before = LLVMBuildICmp(
+        builder,
+        LLVMIntPredicate::LLVMIntULT,
+        a_addr,
+        b_addr,
+        to_c_str("before").as_ptr(),
+    );
+    LLVMBuildRet(builder, before);
+
+    LLVMDisposeBuilder(builder);
+}
+
+impl<F: RecordField> FnBuilder<F> {
+    unsafe fn new(
+        module: LLVMModuleRef,
+        context: LLVMContextRef,
+        builder: LLVMBuilderRef,
+        user_arg: LLVMValueRef,
+        options: &FilterOptions<F>,
+    ) -> Self {
+        FnBuilder {
+            module,
+            context,
+            builder,
+            user_arg,
+            str_counter: 0,
+            inline_str_compares: options.inline_str_compares,
+            str_globals: HashMap::new(),
+            skip_tables: HashMap::new(),
+            values: HashMap::new(),
+            folded_fields: HashSet::new(),
+            str_buffers: Vec::new(),
+        }
+    }
+
     unsafe fn make_call(
         &self,
         fn_name: &str,
@@ -91,6 +504,10 @@ impl FnBuilder {
     }
 
     unsafe fn build_global_str(&mut self, text: &str) -> *mut LLVMValue {
+        if let Some(&str) = self.str_globals.get(text) {
+            return str;
+        }
+
         let global_name = format!("str_{}", self.str_counter);
         self.str_counter += 1;
 
@@ -137,88 +554,755 @@ impl FnBuilder {
         LLVMSetLinkage(str_characters, LLVMLinkage::LLVMPrivateLinkage);
         LLVMSetLinkage(str, LLVMLinkage::LLVMPrivateLinkage);
 
+        self.str_globals.insert(text.to_string(), str);
         str
     }
 
-    unsafe fn build_get_user_field(&self, field: Field) -> LLVMValueRef {
-        match field {
-            Field::Email => self.make_call("user_get_field_email", "email", &mut [self.user_arg]),
-            Field::Gender => {
-                self.make_call("user_get_field_gender", "gender", &mut [self.user_arg])
+    /// Look up a value built earlier on every path to the current block, or build it now
+    unsafe fn build_cached(
+        &mut self,
+        key: ValueKey<F>,
+        build: impl FnOnce(&mut Self) -> LLVMValueRef,
+    ) -> LLVMValueRef {
+        if let Some(&value) = self.values.get(&key) {
+            return value;
+        }
+
+        let value = build(self);
+        self.values.insert(key, value);
+        value
+    }
+
+    unsafe fn build_const_str(&mut self, text: &str) -> LLVMValueRef {
+        self.build_cached(ValueKey::Str(text.to_string()), |this| {
+            let text = this.build_global_str(text);
+            this.make_call("separated_str_as_str", "str", &mut [text])
+        })
+    }
+
+    /// The field's pointer and length, for filters that read its bytes directly
+    unsafe fn build_get_user_field_bytes(&mut self, field: F) -> (LLVMValueRef, LLVMValueRef) {
+        let str = self.build_get_user_field(field);
+        let ptr = self.build_cached(ValueKey::FieldPtr(field), |this| {
+            this.make_call("str_as_ptr", "field_ptr", &mut [str])
+        });
+        let len = self.build_cached(ValueKey::FieldLen(field), |this| {
+            this.make_call("str_len", "field_len", &mut [str])
+        });
+        (ptr, len)
+    }
+
+    /// Substring search tables are built once here, rather than by every call of the search
+    unsafe fn build_global_skip_table(&mut self, needle: &str) -> LLVMValueRef {
+        if let Some(&table) = self.skip_tables.get(needle) {
+            return table;
+        }
+
+        let global_name = format!("skip_table_{}", self.str_counter);
+        self.str_counter += 1;
+
+        let i32_ty = LLVMInt32TypeInContext(self.context);
+        let mut entries = substring_search::skip_table(needle.as_bytes())
+            .iter()
+            .map(|&skip| LLVMConstInt(i32_ty, skip as u64, 0))
+            .collect::<Vec<_>>();
+        let table_ty = LLVMArrayType2(i32_ty, entries.len() as u64);
+
+        let table = LLVMAddGlobal(self.module, table_ty, to_c_str(&global_name).as_ptr());
+        LLVMSetGlobalConstant(table, 1);
+        LLVMSetInitializer(
+            table,
+            LLVMConstArray(i32_ty, entries.as_mut_ptr(), entries.len() as u32),
+        );
+        LLVMSetLinkage(table, LLVMLinkage::LLVMPrivateLinkage);
+
+        self.skip_tables.insert(needle.to_string(), table);
+        table
+    }
+
+    unsafe fn build_get_user_field(&mut self, field: F) -> LLVMValueRef {
+        self.build_cached(ValueKey::Field(field), |this| {
+            this.build_user_field_call(field)
+        })
+    }
+
+    unsafe fn build_user_field_call(&mut self, field: F) -> LLVMValueRef {
+        let arg = self.build_field_arg(field);
+        self.make_call(field.getter(), field.name(), &mut [arg])
+    }
+
+    /// What the field's getters take, the record itself, or the value at the end of the
+    /// field's path. Each key is looked up once, so fields sharing the start of their paths
+    /// share its lookups too.
+    unsafe fn build_field_arg(&mut self, field: F) -> LLVMValueRef {
+        let Some(path) = field.path() else {
+            return self.user_arg;
+        };
+
+        let lookup = record_symbol::<F::Record>("lookup");
+        let mut value = self.user_arg;
+        for (depth, &key) in path.iter().enumerate() {
+            let parent = value;
+            value = self.build_cached(ValueKey::PathValue(path[..=depth].join(".")), |this| {
+                let key = this.build_const_str(key);
+                this.make_call(&lookup, "value", &mut [parent, key])
+            });
+        }
+        value
+    }
+
+    /// The field case folded, computed once per path through the filter like the field itself.
+    /// The folded string borrows the field's buffer, which is only written again by another
+    /// path's fold of the same field.
+    unsafe fn build_folded_field(&mut self, field: F) -> LLVMValueRef {
+        self.build_cached(ValueKey::FoldedField(field), |this| {
+            let buffer = this.build_str_buffer(field);
+            let value = this.build_get_user_field(field);
+            this.make_call("str_fold_into", "folded", &mut [value, buffer])
+        })
+    }
+
+    /// An initialized `String` for the field's folded value, on the stack of the function
+    unsafe fn build_str_buffer(&mut self, field: F) -> LLVMValueRef {
+        if let Some(&(_, buffer)) = self.str_buffers.iter().find(|(other, _)| *other == field) {
+            return buffer;
+        }
+
+        // `String` is three words on x86_64
+        let i64_ty = LLVMInt64TypeInContext(self.context);
+        let buffer = self.build_entry_alloca(LLVMArrayType2(i64_ty, 3), "str_buffer");
+
+        // Initialized right after the alloca, so the buffer is valid on every path that reaches
+        // a return, whether or not it got to the fold
+        let builder = LLVMCreateBuilderInContext(self.context);
+        let next_instruction = LLVMGetNextInstruction(buffer);
+        if next_instruction.is_null() {
+            LLVMPositionBuilderAtEnd(builder, LLVMGetInstructionParent(buffer));
+        } else {
+            LLVMPositionBuilderBefore(builder, next_instruction);
+        }
+        let init = LLVMGetNamedFunction(self.module, to_c_str("str_buffer_init").as_ptr());
+        LLVMBuildCall2(
+            builder,
+            LLVMGlobalGetValueType(init),
+            init,
+            [buffer].as_mut_ptr(),
+            1,
+            to_c_str("").as_ptr(),
+        );
+        LLVMDisposeBuilder(builder);
+
+        self.str_buffers.push((field, buffer));
+        buffer
+    }
+
+    unsafe fn build_str_buffer_drops(&self) {
+        for &(_, buffer) in &self.str_buffers {
+            self.make_call("str_buffer_drop", "", &mut [buffer]);
+        }
+    }
+
+    /// Panics if the field isn't nullable
+    unsafe fn build_has_value(&mut self, field: F) -> LLVMValueRef {
+        self.build_cached(ValueKey::HasValue(field), |this| {
+            let getter = field
+                .presence_getter()
+                .unwrap_or_else(|| panic!("{:?} is not a nullable field", field));
+            let arg = this.build_field_arg(field);
+            this.make_call(getter, "has_value", &mut [arg])
+        })
+    }
+
+    unsafe fn build_filter(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        let i1_ty = LLVMInt1TypeInContext(self.context);
+
+        if !filter.field.is_nullable() {
+            return match filter.kind {
+                FilterKind::IsNull => LLVMConstInt(i1_ty, 0, 0),
+                FilterKind::IsNotNull => LLVMConstInt(i1_ty, 1, 0),
+                _ => self.build_value_filter(filter),
+            };
+        }
+
+        // Missing values read as an empty string or 0, so the filter can run on them and be
+        // masked out afterwards instead of branching around it
+        let has_value = self.build_has_value(filter.field);
+        match filter.kind {
+            FilterKind::IsNull => {
+                LLVMBuildNot(self.builder, has_value, to_c_str("is_null").as_ptr())
             }
-            Field::PhoneNumber => self.make_call(
-                "user_get_field_phone_number",
-                "phone_number",
-                &mut [self.user_arg],
+            FilterKind::IsNotNull => has_value,
+            _ => {
+                let matched = self.build_value_filter(filter);
+                LLVMBuildAnd(
+                    self.builder,
+                    has_value,
+                    matched,
+                    to_c_str("matched").as_ptr(),
+                )
+            }
+        }
+    }
+
+    /// The filter on the field's value, ignoring whether it has one
+    unsafe fn build_value_filter(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        match filter.value {
+            FilterValue::Str(_) => self.build_str_filter(filter),
+            FilterValue::Int(_) | FilterValue::IntRange(..) => self.build_int_filter(filter),
+            FilterValue::Regex(_) => self.build_regex_filter(filter),
+            FilterValue::StrSet(_) => self.build_str_in_filter(filter),
+            FilterValue::Null => panic!("{:?} is not a value filter", filter.kind),
+        }
+    }
+
+    /// Set membership is lowered into a switch on the field length, followed by a tree of
+    /// switches on whichever byte best separates the remaining candidates. Each leaf does a
+    /// single full comparison against the one candidate left.
+    unsafe fn build_str_in_filter(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        let i64_ty = LLVMInt64TypeInContext(self.context);
+
+        let mut candidates_by_len = BTreeMap::<usize, Vec<&str>>::new();
+        for value in filter.value.as_str_set() {
+            candidates_by_len
+                .entry(value.len())
+                .or_default()
+                .push(value);
+        }
+
+        let field = self.build_get_user_field(filter.field);
+        let (field_ptr, field_len) = self.build_get_user_field_bytes(filter.field);
+
+        let matched_block = self.append_block("in_matched");
+        let failed_block = self.append_block("in_failed");
+        let done_block = self.append_block("in_done");
+
+        let len_switch = LLVMBuildSwitch(
+            self.builder,
+            field_len,
+            failed_block,
+            candidates_by_len.len() as u32,
+        );
+        for (len, mut candidates) in candidates_by_len {
+            let len_block = self.append_block("in_len");
+            LLVMAddCase(len_switch, LLVMConstInt(i64_ty, len as u64, 0), len_block);
+
+            // Sort so the generated code doesn't depend on the set's iteration order
+            candidates.sort_unstable();
+
+            LLVMPositionBuilderAtEnd(self.builder, len_block);
+            self.build_str_in_byte_switch(
+                field,
+                field_ptr,
+                &candidates,
+                matched_block,
+                failed_block,
+            );
+        }
+
+        self.build_result_phi(matched_block, failed_block, done_block, "in")
+    }
+
+    /// `candidates` must be distinct, sorted and all the same length as the field.
+    unsafe fn build_str_in_byte_switch(
+        &mut self,
+        field: LLVMValueRef,
+        field_ptr: LLVMValueRef,
+        candidates: &[&str],
+        matched_block: LLVMBasicBlockRef,
+        failed_block: LLVMBasicBlockRef,
+    ) {
+        if let [candidate] = candidates {
+            // The length is already known to match
+            if self.inline_str_compares && candidate.len() <= MAX_INLINE_STR_COMPARE_LEN {
+                self.build_const_bytes_compare(
+                    field_ptr,
+                    candidate.as_bytes(),
+                    matched_block,
+                    failed_block,
+                );
+            } else {
+                // Not cached, as this block doesn't dominate anything after the filter
+                let text = self.build_global_str(candidate);
+                let str = self.make_call("separated_str_as_str", "str", &mut [text]);
+                let equals = self.make_call("filter_str_equals", "equals", &mut [field, str]);
+                LLVMBuildCondBr(self.builder, equals, matched_block, failed_block);
+            }
+            return;
+        }
+
+        // Distinct strings of the same length always differ somewhere, pick the byte
+        // position that splits them into the most groups
+        let len = candidates[0].len();
+        let position = (0..len)
+            .max_by_key(|&position| {
+                let bytes = candidates
+                    .iter()
+                    .map(|candidate| candidate.as_bytes()[position])
+                    .collect::<BTreeSet<_>>();
+                bytes.len()
+            })
+            .unwrap();
+
+        let mut groups = BTreeMap::<u8, Vec<&str>>::new();
+        for &candidate in candidates {
+            groups
+                .entry(candidate.as_bytes()[position])
+                .or_default()
+                .push(candidate);
+        }
+
+        let i8_ty = LLVMInt8TypeInContext(self.context);
+        let i64_ty = LLVMInt64TypeInContext(self.context);
+        let byte_ptr = LLVMBuildGEP2(
+            self.builder,
+            i8_ty,
+            field_ptr,
+            [LLVMConstInt(i64_ty, position as u64, 0)].as_mut_ptr(),
+            1,
+            to_c_str("byte_ptr").as_ptr(),
+        );
+        let byte = LLVMBuildLoad2(self.builder, i8_ty, byte_ptr, to_c_str("byte").as_ptr());
+
+        let switch = LLVMBuildSwitch(self.builder, byte, failed_block, groups.len() as u32);
+        for (byte, group) in groups {
+            let group_block = self.append_block("in_byte");
+            LLVMAddCase(switch, LLVMConstInt(i8_ty, byte as u64, 0), group_block);
+
+            LLVMPositionBuilderAtEnd(self.builder, group_block);
+            self.build_str_in_byte_switch(field, field_ptr, &group, matched_block, failed_block);
+        }
+    }
+
+    unsafe fn append_block(&self, name: &str) -> LLVMBasicBlockRef {
+        LLVMAppendBasicBlockInContext(
+            self.context,
+            LLVMGetBasicBlockParent(LLVMGetInsertBlock(self.builder)),
+            to_c_str(name).as_ptr(),
+        )
+    }
+
+    /// Allocas need to be at the start of the entry block for LLVM to promote them to registers
+    unsafe fn build_entry_alloca(&self, ty: LLVMTypeRef, name: &str) -> LLVMValueRef {
+        let fn_value = LLVMGetBasicBlockParent(LLVMGetInsertBlock(self.builder));
+        let entry_block = LLVMGetEntryBasicBlock(fn_value);
+
+        let builder = LLVMCreateBuilderInContext(self.context);
+        let first_instruction = LLVMGetFirstInstruction(entry_block);
+        if first_instruction.is_null() {
+            LLVMPositionBuilderAtEnd(builder, entry_block);
+        } else {
+            LLVMPositionBuilderBefore(builder, first_instruction);
+        }
+
+        let alloca = LLVMBuildAlloca(builder, ty, to_c_str(name).as_ptr());
+        LLVMDisposeBuilder(builder);
+        alloca
+    }
+
+    /// Join the blocks a multi block filter ends in, into a single `i1` result
+    unsafe fn build_result_phi(
+        &self,
+        matched_block: LLVMBasicBlockRef,
+        failed_block: LLVMBasicBlockRef,
+        done_block: LLVMBasicBlockRef,
+        name: &str,
+    ) -> LLVMValueRef {
+        let i1_ty = LLVMInt1TypeInContext(self.context);
+
+        LLVMPositionBuilderAtEnd(self.builder, matched_block);
+        LLVMBuildBr(self.builder, done_block);
+        LLVMPositionBuilderAtEnd(self.builder, failed_block);
+        LLVMBuildBr(self.builder, done_block);
+
+        LLVMPositionBuilderAtEnd(self.builder, done_block);
+        let result = LLVMBuildPhi(self.builder, i1_ty, to_c_str(name).as_ptr());
+        LLVMAddIncoming(
+            result,
+            [LLVMConstInt(i1_ty, 1, 0), LLVMConstInt(i1_ty, 0, 0)].as_mut_ptr(),
+            [matched_block, failed_block].as_mut_ptr(),
+            2,
+        );
+        result
+    }
+
+    /// The regex DFA is lowered into a state machine, with one block per DFA state that
+    /// switches on the next byte of the field to pick the next state's block.
+    unsafe fn build_regex_filter(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        let dfa = filter.value.as_regex();
+
+        let i8_ty = LLVMInt8TypeInContext(self.context);
+        let i64_ty = LLVMInt64TypeInContext(self.context);
+
+        let (field_ptr, field_len) = self.build_get_user_field_bytes(filter.field);
+
+        let index_ptr = self.build_entry_alloca(i64_ty, "regex_index");
+        LLVMBuildStore(self.builder, LLVMConstInt(i64_ty, 0, 0), index_ptr);
+
+        let matched_block = self.append_block("regex_matched");
+        let failed_block = self.append_block("regex_failed");
+        let done_block = self.append_block("regex_done");
+        let state_blocks = dfa
+            .states()
+            .iter()
+            .map(|_| self.append_block("regex_state"))
+            .collect::<Vec<_>>();
+
+        LLVMBuildBr(self.builder, state_blocks[0]);
+
+        for (state, &state_block) in dfa.states().iter().zip(&state_blocks) {
+            LLVMPositionBuilderAtEnd(self.builder, state_block);
+
+            if state.is_match {
+                LLVMBuildBr(self.builder, matched_block);
+                continue;
+            }
+            if state.is_dead {
+                LLVMBuildBr(self.builder, failed_block);
+                continue;
+            }
+
+            let index = LLVMBuildLoad2(self.builder, i64_ty, index_ptr, to_c_str("index").as_ptr());
+            let at_end = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntEQ,
+                index,
+                field_len,
+                to_c_str("at_end").as_ptr(),
+            );
+            let step_block = self.append_block("regex_step");
+            let end_block = if state.matches_at_end {
+                matched_block
+            } else {
+                failed_block
+            };
+            LLVMBuildCondBr(self.builder, at_end, end_block, step_block);
+
+            LLVMPositionBuilderAtEnd(self.builder, step_block);
+            let byte_ptr = LLVMBuildGEP2(
+                self.builder,
+                i8_ty,
+                field_ptr,
+                [index].as_mut_ptr(),
+                1,
+                to_c_str("byte_ptr").as_ptr(),
+            );
+            let byte = LLVMBuildLoad2(self.builder, i8_ty, byte_ptr, to_c_str("byte").as_ptr());
+            let next_index = LLVMBuildAdd(
+                self.builder,
+                index,
+                LLVMConstInt(i64_ty, 1, 0),
+                to_c_str("next_index").as_ptr(),
+            );
+            LLVMBuildStore(self.builder, next_index, index_ptr);
+
+            // Most bytes usually lead to the same state, so make that the default case
+            let mut target_counts = vec![0; state_blocks.len()];
+            for &target in state.transitions.iter() {
+                target_counts[target] += 1;
+            }
+            let default_target = (0..target_counts.len())
+                .max_by_key(|&target| target_counts[target])
+                .unwrap();
+
+            let switch = LLVMBuildSwitch(
+                self.builder,
+                byte,
+                state_blocks[default_target],
+                (256 - target_counts[default_target]) as u32,
+            );
+            for (byte, &target) in state.transitions.iter().enumerate() {
+                if target != default_target {
+                    LLVMAddCase(
+                        switch,
+                        LLVMConstInt(i8_ty, byte as u64, 0),
+                        state_blocks[target],
+                    );
+                }
+            }
+        }
+
+        self.build_result_phi(matched_block, failed_block, done_block, "matches")
+    }
+
+    unsafe fn build_str_filter(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        let inline_kind = matches!(
+            filter.kind,
+            FilterKind::StrEquals | FilterKind::StrStartsWith | FilterKind::StrEndsWith
+        );
+        if self.inline_str_compares
+            && inline_kind
+            && filter.value.as_str().len() <= MAX_INLINE_STR_COMPARE_LEN
+        {
+            return self.build_inline_str_compare(filter);
+        }
+
+        if filter.kind.is_ignore_case() && self.folded_fields.contains(&filter.field) {
+            return self.build_folded_str_filter(filter);
+        }
+
+        // The ignore case functions expect the constant to be folded already, which the filter
+        // did when it was built, so only the field has to be folded at runtime
+        let str = self.build_const_str(filter.value.as_str());
+
+        let field = self.build_get_user_field(filter.field);
+
+        match filter.kind {
+            FilterKind::StrContains => {
+                let skip_table = self.build_global_skip_table(filter.value.as_str());
+                self.make_call(
+                    "filter_str_contains_with_table",
+                    "contains",
+                    &mut [field, str, skip_table],
+                )
+            }
+            FilterKind::StrEquals => {
+                self.make_call("filter_str_equals", "equals", &mut [field, str])
+            }
+            FilterKind::StrStartsWith => {
+                self.make_call("filter_str_starts_with", "starts_with", &mut [field, str])
+            }
+            FilterKind::StrEndsWith => {
+                self.make_call("filter_str_ends_with", "ends_with", &mut [field, str])
+            }
+            FilterKind::StrContainsIgnoreCase => self.make_call(
+                "filter_str_contains_ignore_case",
+                "contains_ignore_case",
+                &mut [field, str],
             ),
-            Field::LocationStreet => self.make_call(
-                "user_get_field_location_street",
-                "location_street",
-                &mut [self.user_arg],
+            FilterKind::StrEqualsIgnoreCase => self.make_call(
+                "filter_str_equals_ignore_case",
+                "equals_ignore_case",
+                &mut [field, str],
             ),
-            Field::LocationCity => self.make_call(
-                "user_get_field_location_city",
-                "location_city",
-                &mut [self.user_arg],
+            FilterKind::StrStartsWithIgnoreCase => self.make_call(
+                "filter_str_starts_with_ignore_case",
+                "starts_with_ignore_case",
+                &mut [field, str],
             ),
-            Field::LocationState => self.make_call(
-                "user_get_field_location_state",
-                "location_state",
-                &mut [self.user_arg],
+            FilterKind::StrEndsWithIgnoreCase => self.make_call(
+                "filter_str_ends_with_ignore_case",
+                "ends_with_ignore_case",
+                &mut [field, str],
             ),
-            Field::Username => {
-                self.make_call("user_get_field_username", "username", &mut [self.user_arg])
-            }
-            Field::Password => {
-                self.make_call("user_get_field_password", "password", &mut [self.user_arg])
+            kind => panic!("{:?} is not a string filter", kind),
+        }
+    }
+
+    /// An ignore case filter on a field that's folded once for all of them. Simple case folding
+    /// maps chars one to one, so comparing the folded field against the folded needle with
+    /// the plain filters gives the same result as the ignore case ones.
+    unsafe fn build_folded_str_filter(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        let str = self.build_const_str(filter.value.as_str());
+        let folded = self.build_folded_field(filter.field);
+
+        match filter.kind {
+            FilterKind::StrContainsIgnoreCase => {
+                let skip_table = self.build_global_skip_table(filter.value.as_str());
+                self.make_call(
+                    "filter_str_contains_with_table",
+                    "contains_ignore_case",
+                    &mut [folded, str, skip_table],
+                )
             }
-            Field::FirstName => self.make_call(
-                "user_get_field_first_name",
-                "first_name",
-                &mut [self.user_arg],
+            FilterKind::StrEqualsIgnoreCase => self.make_call(
+                "filter_str_equals",
+                "equals_ignore_case",
+                &mut [folded, str],
+            ),
+            FilterKind::StrStartsWithIgnoreCase => self.make_call(
+                "filter_str_starts_with",
+                "starts_with_ignore_case",
+                &mut [folded, str],
             ),
-            Field::LastName => self.make_call(
-                "user_get_field_last_name",
-                "last_name",
-                &mut [self.user_arg],
+            FilterKind::StrEndsWithIgnoreCase => self.make_call(
+                "filter_str_ends_with",
+                "ends_with_ignore_case",
+                &mut [folded, str],
             ),
-            Field::Title => self.make_call("user_get_field_title", "title", &mut [self.user_arg]),
-            Field::Picture => {
-                self.make_call("user_get_field_picture", "picture", &mut [self.user_arg])
+            kind => panic!("{:?} is not an ignore case filter", kind),
+        }
+    }
+
+    /// `StrEquals`/`StrStartsWith`/`StrEndsWith` against a constant are a length check followed
+    /// by word sized compares against the constant's bytes, instead of a call to a generic
+    /// comparison.
+    unsafe fn build_inline_str_compare(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        let i8_ty = LLVMInt8TypeInContext(self.context);
+        let i64_ty = LLVMInt64TypeInContext(self.context);
+
+        let needle = filter.value.as_str().as_bytes();
+        let needle_len = LLVMConstInt(i64_ty, needle.len() as u64, 0);
+
+        let (field_ptr, field_len) = self.build_get_user_field_bytes(filter.field);
+
+        let matched_block = self.append_block("str_cmp_matched");
+        let failed_block = self.append_block("str_cmp_failed");
+        let done_block = self.append_block("str_cmp_done");
+        let compare_block = self.append_block("str_cmp_bytes");
+
+        let len_predicate = match filter.kind {
+            FilterKind::StrEquals => LLVMIntPredicate::LLVMIntEQ,
+            _ => LLVMIntPredicate::LLVMIntUGE,
+        };
+        let len_ok = LLVMBuildICmp(
+            self.builder,
+            len_predicate,
+            field_len,
+            needle_len,
+            to_c_str("len_ok").as_ptr(),
+        );
+        LLVMBuildCondBr(self.builder, len_ok, compare_block, failed_block);
+
+        LLVMPositionBuilderAtEnd(self.builder, compare_block);
+        let start_ptr = match filter.kind {
+            FilterKind::StrEndsWith => {
+                let start = LLVMBuildSub(
+                    self.builder,
+                    field_len,
+                    needle_len,
+                    to_c_str("suffix_start").as_ptr(),
+                );
+                LLVMBuildGEP2(
+                    self.builder,
+                    i8_ty,
+                    field_ptr,
+                    [start].as_mut_ptr(),
+                    1,
+                    to_c_str("suffix_ptr").as_ptr(),
+                )
             }
+            _ => field_ptr,
+        };
+        self.build_const_bytes_compare(start_ptr, needle, matched_block, failed_block);
+
+        self.build_result_phi(matched_block, failed_block, done_block, "str_cmp")
+    }
+
+    /// Compare the bytes at `ptr` against `bytes` using the widest loads that fit, branching to
+    /// `failed_block` on the first mismatch. The caller has to check that enough bytes are
+    /// readable.
+    unsafe fn build_const_bytes_compare(
+        &mut self,
+        ptr: LLVMValueRef,
+        bytes: &[u8],
+        matched_block: LLVMBasicBlockRef,
+        failed_block: LLVMBasicBlockRef,
+    ) {
+        let i8_ty = LLVMInt8TypeInContext(self.context);
+        let i64_ty = LLVMInt64TypeInContext(self.context);
+
+        for (offset, chunk_len) in compare_chunks(bytes.len()) {
+            let chunk = &bytes[offset..offset + chunk_len];
+            let chunk_ty = LLVMIntTypeInContext(self.context, (chunk_len * 8) as u32);
+
+            let chunk_ptr = LLVMBuildGEP2(
+                self.builder,
+                i8_ty,
+                ptr,
+                [LLVMConstInt(i64_ty, offset as u64, 0)].as_mut_ptr(),
+                1,
+                to_c_str("chunk_ptr").as_ptr(),
+            );
+            let value = LLVMBuildLoad2(
+                self.builder,
+                chunk_ty,
+                chunk_ptr,
+                to_c_str("chunk").as_ptr(),
+            );
+            LLVMSetAlignment(value, 1);
+
+            // Loads are little endian, so the first byte ends up as the lowest one
+            let expected = chunk
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+            let equal = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntEQ,
+                value,
+                LLVMConstInt(chunk_ty, expected, 0),
+                to_c_str("chunk_eq").as_ptr(),
+            );
+
+            let next_block = self.append_block("str_cmp_next");
+            LLVMBuildCondBr(self.builder, equal, next_block, failed_block);
+            LLVMPositionBuilderAtEnd(self.builder, next_block);
         }
+
+        LLVMBuildBr(self.builder, matched_block);
     }
 
-    unsafe fn build_filter(&mut self, filter: &Filter) -> LLVMValueRef {
-        let text = self.build_global_str(&filter.value);
-        let str = self.make_call("separated_str_as_str", "str", &mut [text]);
+    /// Integer filters compare directly in IR, there are no functions for them to call
+    unsafe fn build_int_filter(&mut self, filter: &Filter<F>) -> LLVMValueRef {
+        let i64_ty = LLVMInt64TypeInContext(self.context);
 
+        // Getters return the field's own width (e.g. u32 for postcodes), so widen everything to u64
         let field = self.build_get_user_field(filter.field);
+        let field = LLVMBuildZExtOrBitCast(self.builder, field, i64_ty, to_c_str("field").as_ptr());
+
+        let build_cmp = |predicate: LLVMIntPredicate, value: u64, name: &str| {
+            LLVMBuildICmp(
+                self.builder,
+                predicate,
+                field,
+                LLVMConstInt(i64_ty, value, 0),
+                to_c_str(name).as_ptr(),
+            )
+        };
 
         match filter.kind {
-            crate::FilterKind::StrContains => {
-                self.make_call("filter_str_contains", "contains", &mut [field, str])
+            FilterKind::IntEquals => {
+                build_cmp(LLVMIntPredicate::LLVMIntEQ, filter.value.as_int(), "eq")
             }
-            crate::FilterKind::StrEquals => {
-                self.make_call("filter_str_equals", "equals", &mut [field, str])
+            FilterKind::IntNotEquals => {
+                build_cmp(LLVMIntPredicate::LLVMIntNE, filter.value.as_int(), "ne")
             }
-            crate::FilterKind::StrStartsWith => {
-                self.make_call("filter_str_starts_with", "starts_with", &mut [field, str])
+            FilterKind::IntLessThan => {
+                build_cmp(LLVMIntPredicate::LLVMIntULT, filter.value.as_int(), "lt")
             }
-            crate::FilterKind::StrEndsWith => {
-                self.make_call("filter_str_ends_with", "ends_with", &mut [field, str])
+            FilterKind::IntLessOrEqual => {
+                build_cmp(LLVMIntPredicate::LLVMIntULE, filter.value.as_int(), "le")
+            }
+            FilterKind::IntGreaterThan => {
+                build_cmp(LLVMIntPredicate::LLVMIntUGT, filter.value.as_int(), "gt")
             }
+            FilterKind::IntGreaterOrEqual => {
+                build_cmp(LLVMIntPredicate::LLVMIntUGE, filter.value.as_int(), "ge")
+            }
+            FilterKind::IntBetween => {
+                let (min, max) = filter.value.as_int_range();
+                let above_min = build_cmp(LLVMIntPredicate::LLVMIntUGE, min, "above_min");
+                let below_max = build_cmp(LLVMIntPredicate::LLVMIntULE, max, "below_max");
+                LLVMBuildAnd(
+                    self.builder,
+                    above_min,
+                    below_max,
+                    to_c_str("between").as_ptr(),
+                )
+            }
+            kind => panic!("{:?} is not an integer filter", kind),
         }
     }
 
+    /// Cached values stay valid as long as the blocks being built are dominated by the block
+    /// the value was built in. Once this returns, only values that were built before any
+    /// branching inside `filter` remain cached, since those dominate every block `filter`
+    /// branches out to.
     unsafe fn build_join_filter(
         &mut self,
-        filter: &JoinFilters,
+        filter: &JoinFilters<F>,
         fail_block: LLVMBasicBlockRef,
         success_block: LLVMBasicBlockRef,
     ) {
         match filter {
-            JoinFilters::Filter(f) => {
+            JoinFilters::Filter(f) | JoinFilters::AnyElement(f) if f.field.is_list() => {
+                self.build_elements_filter(f, fail_block, success_block, false)
+            }
+            JoinFilters::AllElements(f) if f.field.is_list() => {
+                self.build_elements_filter(f, fail_block, success_block, true)
+            }
+            // The value of any other field is its only element
+            JoinFilters::Filter(f) | JoinFilters::AnyElement(f) | JoinFilters::AllElements(f) => {
                 let result = self.build_filter(f);
                 // Build br
                 LLVMBuildCondBr(self.builder, result, success_block, fail_block);
@@ -231,8 +1315,10 @@ impl FnBuilder {
                 );
 
                 self.build_join_filter(left, fail_block, and_middle_block);
+                let values = self.values.clone();
                 LLVMPositionBuilderAtEnd(self.builder, and_middle_block);
                 self.build_join_filter(right, fail_block, success_block);
+                self.values = values;
             }
             JoinFilters::Or(left, right) => {
                 let or_middle_block = LLVMAppendBasicBlockInContext(
@@ -242,29 +1328,246 @@ impl FnBuilder {
                 );
 
                 self.build_join_filter(left, or_middle_block, success_block);
+                let values = self.values.clone();
                 LLVMPositionBuilderAtEnd(self.builder, or_middle_block);
                 self.build_join_filter(right, fail_block, success_block);
+                self.values = values;
+            }
+            JoinFilters::Not(inner) => {
+                // Negation is free, just swap where the inner filter branches to
+                self.build_join_filter(inner, success_block, fail_block);
+            }
+            JoinFilters::All(children) => {
+                self.build_join_filter_chain(children, "all_next", fail_block, success_block, true)
+            }
+            JoinFilters::Any(children) => {
+                self.build_join_filter_chain(children, "any_next", fail_block, success_block, false)
             }
         }
     }
+
+    /// Loop over the elements of a list field, running the filter on each one as if it was
+    /// the field's value. `Any` bails to `success_block` on the first match, `All` to
+    /// `fail_block` on the first mismatch, and running out of elements decides the other way.
+    unsafe fn build_elements_filter(
+        &mut self,
+        filter: &Filter<F>,
+        fail_block: LLVMBasicBlockRef,
+        success_block: LLVMBasicBlockRef,
+        is_all: bool,
+    ) {
+        let i64_ty = LLVMInt64TypeInContext(self.context);
+        let field = filter.field;
+        let element_getter = field
+            .element_getter()
+            .unwrap_or_else(|| panic!("{:?} is not a list field", field));
+
+        // List getters return the length
+        let len = self.build_user_field_call(field);
+        let index_ptr = self.build_entry_alloca(i64_ty, "element_index");
+        LLVMBuildStore(self.builder, LLVMConstInt(i64_ty, 0, 0), index_ptr);
+
+        let loop_block = self.append_block("elements_loop");
+        let body_block = self.append_block("elements_body");
+        LLVMBuildBr(self.builder, loop_block);
+
+        LLVMPositionBuilderAtEnd(self.builder, loop_block);
+        let index = LLVMBuildLoad2(self.builder, i64_ty, index_ptr, to_c_str("index").as_ptr());
+        let at_end = LLVMBuildICmp(
+            self.builder,
+            LLVMIntPredicate::LLVMIntEQ,
+            index,
+            len,
+            to_c_str("at_end").as_ptr(),
+        );
+        let end_block = if is_all { success_block } else { fail_block };
+        LLVMBuildCondBr(self.builder, at_end, end_block, body_block);
+
+        LLVMPositionBuilderAtEnd(self.builder, body_block);
+        let element = self.make_call(element_getter, "element", &mut [self.user_arg, index]);
+        let next_index = LLVMBuildAdd(
+            self.builder,
+            index,
+            LLVMConstInt(i64_ty, 1, 0),
+            to_c_str("next_index").as_ptr(),
+        );
+        LLVMBuildStore(self.builder, next_index, index_ptr);
+
+        // Values built for one element don't dominate anything outside the body
+        let values = self.values.clone();
+        self.values.insert(ValueKey::Field(field), element);
+        let matched = self.build_filter(filter);
+        self.values = values;
+
+        if is_all {
+            LLVMBuildCondBr(self.builder, matched, loop_block, fail_block);
+        } else {
+            LLVMBuildCondBr(self.builder, matched, success_block, loop_block);
+        }
+    }
+
+    /// Build a flat chain of blocks for an n-ary `All`/`Any`, one block per child.
+    /// For `All` each child continues to the next on success and bails to `fail_block`,
+    /// for `Any` each child continues to the next on failure and bails to `success_block`.
+    unsafe fn build_join_filter_chain(
+        &mut self,
+        children: &[JoinFilters<F>],
+        block_name: &str,
+        fail_block: LLVMBasicBlockRef,
+        success_block: LLVMBasicBlockRef,
+        is_all: bool,
+    ) {
+        let Some((last, rest)) = children.split_last() else {
+            // Empty `All` is always true, empty `Any` is always false
+            LLVMBuildBr(
+                self.builder,
+                if is_all { success_block } else { fail_block },
+            );
+            return;
+        };
+
+        let fn_value = LLVMGetBasicBlockParent(LLVMGetInsertBlock(self.builder));
+        // Each child's block is only reached through the previous child, so values keep
+        // accumulating along the chain, but only the first child's ones dominate every exit
+        let mut first_child_values = None;
+        for child in rest {
+            let next_block = LLVMAppendBasicBlockInContext(
+                self.context,
+                fn_value,
+                to_c_str(block_name).as_ptr(),
+            );
+
+            if is_all {
+                self.build_join_filter(child, fail_block, next_block);
+            } else {
+                self.build_join_filter(child, next_block, success_block);
+            }
+            first_child_values.get_or_insert_with(|| self.values.clone());
+            LLVMPositionBuilderAtEnd(self.builder, next_block);
+        }
+
+        self.build_join_filter(last, fail_block, success_block);
+        if let Some(values) = first_child_values {
+            self.values = values;
+        }
+    }
 }
 
-pub unsafe fn build_fn(
+pub unsafe fn build_fn<F: RecordField>(
     name: &str,
     module: LLVMModuleRef,
     context: LLVMContextRef,
-    filters: &JoinFilters,
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
 ) {
-    build_filter_fn("filter", module, context, filters);
+    build_filter_fn("filter", module, context, filters, options);
+    build_project_fn("project", module, context, options.projection, options);
+    if let Some(query) = options.aggregate {
+        build_aggregate_fn("aggregate", module, context, query, options);
+    }
+    if !options.order_by.is_empty() {
+        build_sort_less_fn("sort_less", module, context, options.order_by, options);
+    }
 
+    build_entry_fn::<F::Record>(name, "fn_sig", "run_filter", &["filter"], module, context);
+    build_entry_fn::<F::Record>(
+        &format!("{}_projected", name),
+        "projection_fn_sig",
+        "run_filter_project",
+        &["filter", "project"],
+        module,
+        context,
+    );
+    build_entry_fn::<F::Record>(
+        &format!("{}_indices", name),
+        "indices_fn_sig",
+        "run_filter_indices",
+        &["filter"],
+        module,
+        context,
+    );
+    build_entry_fn::<F::Record>(
+        &format!("{}_refs", name),
+        "refs_fn_sig",
+        "run_filter_refs",
+        &["filter"],
+        module,
+        context,
+    );
+    if options.aggregate.is_some() {
+        build_entry_fn::<F::Record>(
+            &format!("{}_aggregate", name),
+            "aggregation_fn_sig",
+            "run_filter_aggregate",
+            &["filter", "aggregate"],
+            module,
+            context,
+        );
+    }
+    if options.order_by.is_empty() {
+        build_entry_fn::<F::Record>(
+            &format!("{}_ordered", name),
+            "ordered_fn_sig",
+            "run_filter_limit",
+            &["filter"],
+            module,
+            context,
+        );
+    } else {
+        build_entry_fn::<F::Record>(
+            &format!("{}_ordered", name),
+            "ordered_fn_sig",
+            "run_filter_top_k",
+            &["filter", "sort_less"],
+            module,
+            context,
+        );
+    }
+    for (suffix, sig_name) in [
+        ("any", "quantifier_fn_sig"),
+        ("all", "quantifier_fn_sig"),
+        ("count", "count_fn_sig"),
+        ("find_first", "find_first_fn_sig"),
+    ] {
+        build_entry_fn::<F::Record>(
+            &format!("{}_{}", name, suffix),
+            sig_name,
+            &format!("run_filter_{}", suffix),
+            &["filter"],
+            module,
+            context,
+        );
+    }
+    build_entry_fn::<F::Record>(
+        &format!("{}_bitmap", name),
+        "bitmap_fn_sig",
+        "run_filter_bitmap",
+        &["filter"],
+        module,
+        context,
+    );
+}
+
+/// Build an exported function with the signature of `sig_name`, which passes both of its
+/// arguments on to `runner_name` followed by the `callbacks` functions. Both names are the
+/// unprefixed names of `R`'s exports.
+unsafe fn build_entry_fn<R: Record>(
+    name: &str,
+    sig_name: &str,
+    runner_name: &str,
+    callbacks: &[&str],
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+) {
     // Grab the function signature we want to copy and add it to the module
-    let fn_val = LLVMGetNamedFunction(module, to_c_str("fn_sig").as_ptr());
+    let sig_name = record_symbol::<R>(sig_name);
+    let fn_val = LLVMGetNamedFunction(module, to_c_str(&sig_name).as_ptr());
     let fn_type = LLVMGlobalGetValueType(fn_val);
     let fn_value = LLVMAddFunction(module, to_c_str(name).as_ptr(), fn_type);
 
     let entry_block = LLVMAppendBasicBlockInContext(context, fn_value, to_c_str("entry").as_ptr());
     let users_arr_arg = LLVMGetParam(fn_value, 0);
-    let result_vec_arg = LLVMGetParam(fn_value, 1);
+    let output_arg = LLVMGetParam(fn_value, 1);
 
     let builder = LLVMCreateBuilderInContext(context);
     LLVMPositionBuilderAtEnd(builder, entry_block);
@@ -288,14 +1591,15 @@ pub unsafe fn build_fn(
         )
     };
 
-    let filter_fn_ptr = LLVMGetNamedFunction(module, to_c_str("filter").as_ptr());
-
-    make_call(
-        "run_filter",
-        "result",
-        &mut [users_arr_arg, result_vec_arg, filter_fn_ptr],
+    let mut args = vec![users_arr_arg, output_arg];
+    args.extend(
+        callbacks
+            .iter()
+            .map(|callback| LLVMGetNamedFunction(module, to_c_str(callback).as_ptr())),
     );
 
+    make_call(&record_symbol::<R>(runner_name), "", &mut args);
+
     // Return
     LLVMBuildRetVoid(builder);
 
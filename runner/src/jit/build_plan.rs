@@ -0,0 +1,300 @@
+use llvm_sys::core::*;
+use llvm_sys::prelude::{
+    LLVMBasicBlockRef, LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMValueRef,
+};
+use llvm_sys::*;
+use shared::schema::RecordField;
+
+use crate::{
+    aggregate::AggregateQuery, order::SortKey, plan::Operator, FilterOptions, JoinFilters,
+};
+
+use super::{
+    build_fn::{build_aggregate_fn, build_filter_fn, build_project_fn, build_sort_less_fn},
+    record_symbol, to_c_str,
+};
+
+/// A loop over either the input or the rows of a sort, with a row function that runs the
+/// operators between the loop's source and the next sort (or the end of the plan)
+struct Stage {
+    /// The index and comparison function of the sort the stage reads from, `None` for the scan
+    source: Option<(usize, LLVMValueRef)>,
+    row_fn: LLVMValueRef,
+}
+
+/// Lowers the operators of a plan one at a time, each appending its code to the row function
+/// of the current stage. A row that an operator drops jumps to `next_row_block`, and once
+/// nothing more can get through the stage, `stop_block` ends its loop early.
+struct PlanLowering<'a, F: RecordField> {
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    builder: LLVMBuilderRef,
+    options: &'a FilterOptions<'a, F>,
+    stages: Vec<Stage>,
+    user_arg: LLVMValueRef,
+    state_arg: LLVMValueRef,
+    next_row_block: LLVMBasicBlockRef,
+    stop_block: LLVMBasicBlockRef,
+    filter_count: usize,
+    limit_count: usize,
+    sort_count: usize,
+}
+
+/// Build an exported function with the signature of `plan_fn_sig` that runs all of the
+/// operators. The compiled code fills in a `PlanState` made by `plan::new_state`.
+pub unsafe fn build_plan_fn<F: RecordField>(
+    name: &str,
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    operators: &[Operator<F>],
+    options: &FilterOptions<F>,
+) {
+    let builder = LLVMCreateBuilderInContext(context);
+    let mut lowering = PlanLowering {
+        module,
+        context,
+        builder,
+        options,
+        stages: Vec::new(),
+        user_arg: std::ptr::null_mut(),
+        state_arg: std::ptr::null_mut(),
+        next_row_block: std::ptr::null_mut(),
+        stop_block: std::ptr::null_mut(),
+        filter_count: 0,
+        limit_count: 0,
+        sort_count: 0,
+    };
+
+    lowering.begin_stage(None);
+    for operator in operators {
+        lowering.lower(operator);
+    }
+    // Without a projection or aggregate at the end, the rows themselves are the output
+    if !matches!(
+        operators.last(),
+        Some(Operator::Project(_) | Operator::Aggregate(_))
+    ) {
+        lowering.make_record_call(
+            "plan_push_row",
+            "",
+            &mut [lowering.state_arg, lowering.user_arg],
+        );
+    }
+    LLVMBuildBr(builder, lowering.next_row_block);
+
+    lowering.build_entry(name);
+
+    LLVMDisposeBuilder(builder);
+}
+
+impl<F: RecordField> PlanLowering<'_, F> {
+    unsafe fn make_call(
+        &self,
+        fn_name: &str,
+        result_name: &str,
+        args: &mut [*mut LLVMValue],
+    ) -> LLVMValueRef {
+        let f = LLVMGetNamedFunction(self.module, to_c_str(fn_name).as_ptr());
+
+        if f.is_null() {
+            panic!("Function {} not found", fn_name);
+        }
+
+        let ty = LLVMGlobalGetValueType(f);
+
+        LLVMBuildCall2(
+            self.builder,
+            ty,
+            f,
+            args.as_mut_ptr(),
+            args.len() as u32,
+            to_c_str(result_name).as_ptr(),
+        )
+    }
+
+    /// Call one of the functions the `functions` crate exports for the record type
+    unsafe fn make_record_call(
+        &self,
+        fn_name: &str,
+        result_name: &str,
+        args: &mut [*mut LLVMValue],
+    ) -> LLVMValueRef {
+        self.make_call(&record_symbol::<F::Record>(fn_name), result_name, args)
+    }
+
+    unsafe fn named_function(&self, name: &str) -> LLVMValueRef {
+        LLVMGetNamedFunction(self.module, to_c_str(name).as_ptr())
+    }
+
+    unsafe fn const_index(&self, index: usize) -> LLVMValueRef {
+        LLVMConstInt(LLVMInt64TypeInContext(self.context), index as u64, 0)
+    }
+
+    unsafe fn append_block(&self, name: &str) -> LLVMBasicBlockRef {
+        let row_fn = self.stages.last().unwrap().row_fn;
+        LLVMAppendBasicBlockInContext(self.context, row_fn, to_c_str(name).as_ptr())
+    }
+
+    /// Start the row function of a new stage, positioned at its entry block
+    unsafe fn begin_stage(&mut self, source: Option<(usize, LLVMValueRef)>) {
+        // Grab the function signature we want to copy and add it to the module
+        let fn_val = self.named_function(&record_symbol::<F::Record>("plan_row_fn_sig"));
+        let fn_type = LLVMGlobalGetValueType(fn_val);
+        let row_fn_name = format!("plan_row_{}", self.stages.len());
+        let row_fn = LLVMAddFunction(self.module, to_c_str(&row_fn_name).as_ptr(), fn_type);
+
+        // Function should be private
+        LLVMSetLinkage(row_fn, LLVMLinkage::LLVMPrivateLinkage);
+
+        self.stages.push(Stage { source, row_fn });
+        self.user_arg = LLVMGetParam(row_fn, 0);
+        self.state_arg = LLVMGetParam(row_fn, 1);
+
+        let entry_block = self.append_block("entry");
+        self.next_row_block = self.append_block("next_row");
+        self.stop_block = self.append_block("stop");
+
+        let i1_ty = LLVMInt1TypeInContext(self.context);
+        LLVMPositionBuilderAtEnd(self.builder, self.next_row_block);
+        LLVMBuildRet(self.builder, LLVMConstInt(i1_ty, 1, 0));
+        LLVMPositionBuilderAtEnd(self.builder, self.stop_block);
+        LLVMBuildRet(self.builder, LLVMConstInt(i1_ty, 0, 0));
+
+        LLVMPositionBuilderAtEnd(self.builder, entry_block);
+    }
+
+    unsafe fn lower(&mut self, operator: &Operator<F>) {
+        match operator {
+            Operator::Filter(filters) => self.lower_filter(filters),
+            Operator::Limit(_) => self.lower_limit(),
+            Operator::Sort { order_by, .. } => self.lower_sort(order_by),
+            Operator::Project(fields) => self.lower_project(fields),
+            Operator::Aggregate(query) => self.lower_aggregate(query),
+        }
+    }
+
+    unsafe fn lower_filter(&mut self, filters: &JoinFilters<F>) {
+        let filter_fn_name = format!("plan_filter_{}", self.filter_count);
+        self.filter_count += 1;
+        build_filter_fn(
+            &filter_fn_name,
+            self.module,
+            self.context,
+            filters,
+            self.options,
+        );
+
+        let matched = self.make_call(&filter_fn_name, "matched", &mut [self.user_arg]);
+        let matched_block = self.append_block("matched");
+        LLVMBuildCondBr(self.builder, matched, matched_block, self.next_row_block);
+        LLVMPositionBuilderAtEnd(self.builder, matched_block);
+    }
+
+    /// The limit itself is in the state, which counts down the rows it lets through
+    unsafe fn lower_limit(&mut self) {
+        let index = self.const_index(self.limit_count);
+        self.limit_count += 1;
+
+        let taken = self.make_record_call("plan_take_limit", "taken", &mut [self.state_arg, index]);
+        let taken_block = self.append_block("taken");
+        LLVMBuildCondBr(self.builder, taken, taken_block, self.stop_block);
+        LLVMPositionBuilderAtEnd(self.builder, taken_block);
+    }
+
+    /// Ends the stage by collecting the row, the operators after it run in a new stage over
+    /// the sorted rows
+    unsafe fn lower_sort(&mut self, order_by: &[SortKey<F>]) {
+        let sort_index = self.sort_count;
+        self.sort_count += 1;
+
+        let less_fn_name = format!("plan_sort_less_{}", sort_index);
+        build_sort_less_fn(
+            &less_fn_name,
+            self.module,
+            self.context,
+            order_by,
+            self.options,
+        );
+        let less_fn = self.named_function(&less_fn_name);
+
+        let index = self.const_index(sort_index);
+        self.make_record_call(
+            "plan_push_sorted",
+            "",
+            &mut [self.state_arg, index, self.user_arg, less_fn],
+        );
+        LLVMBuildBr(self.builder, self.next_row_block);
+
+        self.begin_stage(Some((sort_index, less_fn)));
+    }
+
+    unsafe fn lower_project(&mut self, fields: &[F]) {
+        build_project_fn(
+            "plan_project",
+            self.module,
+            self.context,
+            fields,
+            self.options,
+        );
+
+        let output =
+            self.make_record_call("plan_state_projected", "projected", &mut [self.state_arg]);
+        self.make_call("plan_project", "", &mut [self.user_arg, output]);
+    }
+
+    unsafe fn lower_aggregate(&mut self, query: &AggregateQuery<F>) {
+        build_aggregate_fn(
+            "plan_aggregate",
+            self.module,
+            self.context,
+            query,
+            self.options,
+        );
+
+        let state =
+            self.make_record_call("plan_state_aggregate", "aggregate", &mut [self.state_arg]);
+        self.make_call("plan_aggregate", "", &mut [self.user_arg, state]);
+    }
+
+    /// Build the exported function, which runs the stages' loops one after the other
+    unsafe fn build_entry(&self, name: &str) {
+        // Grab the function signature we want to copy and add it to the module
+        let fn_val = self.named_function(&record_symbol::<F::Record>("plan_fn_sig"));
+        let fn_type = LLVMGlobalGetValueType(fn_val);
+        let fn_value = LLVMAddFunction(self.module, to_c_str(name).as_ptr(), fn_type);
+
+        let entry_block =
+            LLVMAppendBasicBlockInContext(self.context, fn_value, to_c_str("entry").as_ptr());
+        let users_arr_arg = LLVMGetParam(fn_value, 0);
+        let state_arg = LLVMGetParam(fn_value, 1);
+
+        LLVMPositionBuilderAtEnd(self.builder, entry_block);
+
+        for stage in &self.stages {
+            match stage.source {
+                None => {
+                    self.make_record_call(
+                        "run_plan_scan",
+                        "",
+                        &mut [users_arr_arg, state_arg, stage.row_fn],
+                    );
+                }
+                Some((sort_index, less_fn)) => {
+                    self.make_record_call(
+                        "run_plan_sorted",
+                        "",
+                        &mut [
+                            state_arg,
+                            self.const_index(sort_index),
+                            less_fn,
+                            stage.row_fn,
+                        ],
+                    );
+                }
+            }
+        }
+
+        // Return
+        LLVMBuildRetVoid(self.builder);
+    }
+}
@@ -6,11 +6,29 @@ use llvm_sys::{
     error::LLVMGetErrorMessage,
     orc2::{lljit::*, *},
 };
-use shared::User;
+use shared::{aggregate::AggregateState, plan::PlanState, top_k::TopK, ProjectedValue, User};
 
 use super::{to_c_str, ModuleWithContext};
 
-pub type JitFunction = unsafe extern "C" fn(_vec: &[User], output_vec: *mut Vec<User>);
+/// Entry points of a module built for records of type `R`
+pub type JitFunction<R = User> = unsafe extern "C" fn(_vec: &[R], output_vec: *mut Vec<R>);
+pub type JitIndicesFunction<R = User> =
+    unsafe extern "C" fn(_vec: &[R], output_vec: *mut Vec<usize>);
+pub type JitRefsFunction<R = User> =
+    for<'a> unsafe extern "C" fn(_vec: &'a [R], output_vec: *mut Vec<&'a R>);
+pub type JitProjectedFunction<R = User> =
+    for<'a> unsafe extern "C" fn(_vec: &'a [R], output_vec: *mut Vec<ProjectedValue<'a>>);
+pub type JitAggregateFunction<R = User> =
+    for<'a> unsafe extern "C" fn(_vec: &'a [R], state: *mut AggregateState<'a>);
+pub type JitOrderedFunction<R = User> =
+    for<'a> unsafe extern "C" fn(_vec: &'a [R], state: *mut TopK<'a, R>);
+pub type JitQuantifierFunction<R = User> = unsafe extern "C" fn(_vec: &[R], output: *mut bool);
+pub type JitCountFunction<R = User> = unsafe extern "C" fn(_vec: &[R], output: *mut usize);
+pub type JitFindFirstFunction<R = User> =
+    for<'a> unsafe extern "C" fn(_vec: &'a [R], output: *mut Option<&'a R>);
+pub type JitPlanFunction<R = User> =
+    for<'a> unsafe extern "C" fn(_vec: &'a [R], state: *mut PlanState<'a, R>);
+pub type JitBitmapFunction<R = User> = unsafe extern "C" fn(_vec: &[R], bitmap: *mut u64);
 
 struct JitFunctionModule {
     resource_tracker: LLVMOrcResourceTrackerRef,
@@ -92,14 +110,7 @@ impl JitExecutionEngine {
             panic!("Failed to add module: {}", err);
         }
 
-        let mut compiled = 0;
-        let err = LLVMOrcLLJITLookup(self.orc_jit, &mut compiled, to_c_str(&name).as_ptr());
-        if !err.is_null() {
-            let err = CStr::from_ptr(LLVMGetErrorMessage(err)).to_string_lossy();
-            panic!("Failed to lookup function: {}", err);
-        }
-
-        let compiled = mem::transmute::<_, JitFunction>(compiled);
+        let compiled = mem::transmute::<_, JitFunction>(self.lookup_address(name));
 
         self.functions.insert(
             name.to_string(),
@@ -111,9 +122,21 @@ impl JitExecutionEngine {
         );
     }
 
-    pub fn get_function_ptr(&self, name: &str) -> JitFunction {
+    /// Find any function from the added modules, for modules with more than one entry point
+    pub unsafe fn lookup_address(&self, name: &str) -> u64 {
+        let mut compiled = 0;
+        let err = LLVMOrcLLJITLookup(self.orc_jit, &mut compiled, to_c_str(&name).as_ptr());
+        if !err.is_null() {
+            let err = CStr::from_ptr(LLVMGetErrorMessage(err)).to_string_lossy();
+            panic!("Failed to lookup function: {}", err);
+        }
+        compiled
+    }
+
+    /// The module must have been built for records of type `R`
+    pub unsafe fn get_function_ptr<R>(&self, name: &str) -> JitFunction<R> {
         let function = self.functions.get(name).unwrap();
-        function.function
+        mem::transmute::<JitFunction, JitFunction<R>>(function.function)
     }
 
     pub unsafe fn remove_group_fn(&mut self, name: &str) {
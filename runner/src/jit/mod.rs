@@ -1,23 +1,33 @@
 use std::{
     borrow::Cow,
     ffi::{CStr, CString},
+    mem,
 };
 
 use llvm_sys::{
     orc2::{LLVMOrcThreadSafeContextGetContext, LLVMOrcThreadSafeContextRef},
-    prelude::LLVMModuleRef,
+    prelude::{LLVMContextRef, LLVMModuleRef},
     target::{
         LLVM_InitializeNativeAsmParser, LLVM_InitializeNativeAsmPrinter,
         LLVM_InitializeNativeTarget,
     },
 };
-use shared::User;
+use shared::schema::{Record, RecordField};
 
-use crate::JoinFilters;
+use crate::{
+    aggregate::{AggregateQuery, AggregateRow},
+    bitmap::SelectionBitmap,
+    order::new_top_k,
+    plan::{self, Operator, Plan, PlanError, PlanOutput},
+    prepare_filters,
+    projection::ProjectedRows,
+    Field, FilterOptions, JoinFilters,
+};
 
 use self::optimizing::Optimizer;
 
 mod build_fn;
+mod build_plan;
 mod exec_engine;
 mod io;
 mod optimizing;
@@ -40,20 +50,136 @@ pub(crate) fn to_c_str<'s>(mut s: &'s str) -> Cow<'s, CStr> {
     unsafe { Cow::from(CStr::from_ptr(s.as_ptr() as *const _)) }
 }
 
-pub struct CallableJitFn {
+/// Name of a signature or runner the `functions` crate exports for records of type `R`
+pub(crate) fn record_symbol<R: Record>(name: &str) -> String {
+    format!("{}{}", R::SYMBOL_PREFIX, name)
+}
+
+pub struct CallableJitFn<F: RecordField = Field> {
     _ee: exec_engine::JitExecutionEngine,
-    fn_ptr: exec_engine::JitFunction,
+    fn_ptr: exec_engine::JitFunction<F::Record>,
+    indices_fn_ptr: exec_engine::JitIndicesFunction<F::Record>,
+    refs_fn_ptr: exec_engine::JitRefsFunction<F::Record>,
+    projected_fn_ptr: exec_engine::JitProjectedFunction<F::Record>,
+    projection: Vec<F>,
+    aggregate: Option<(
+        AggregateQuery<F>,
+        exec_engine::JitAggregateFunction<F::Record>,
+    )>,
+    ordered_fn_ptr: exec_engine::JitOrderedFunction<F::Record>,
+    limit: Option<usize>,
+    any_fn_ptr: exec_engine::JitQuantifierFunction<F::Record>,
+    all_fn_ptr: exec_engine::JitQuantifierFunction<F::Record>,
+    count_fn_ptr: exec_engine::JitCountFunction<F::Record>,
+    find_first_fn_ptr: exec_engine::JitFindFirstFunction<F::Record>,
+    bitmap_fn_ptr: exec_engine::JitBitmapFunction<F::Record>,
 }
 
-impl CallableJitFn {
-    pub unsafe fn execute(&self, vec: &[User]) -> Vec<User> {
+impl<F: RecordField> CallableJitFn<F> {
+    pub unsafe fn execute(&self, vec: &[F::Record]) -> Vec<F::Record> {
         let mut output_vec = Vec::new();
         (self.fn_ptr)(vec, &mut output_vec);
         output_vec
     }
+
+    /// Indices of the matching records in `vec`, without copying them
+    pub unsafe fn execute_indices(&self, vec: &[F::Record]) -> Vec<usize> {
+        let mut output_vec = Vec::new();
+        (self.indices_fn_ptr)(vec, &mut output_vec);
+        output_vec
+    }
+
+    pub unsafe fn execute_refs<'a>(&self, vec: &'a [F::Record]) -> Vec<&'a F::Record> {
+        let mut output_vec = Vec::new();
+        (self.refs_fn_ptr)(vec, &mut output_vec);
+        output_vec
+    }
+
+    /// The fields from [`FilterOptions::projection`] of each matching user. Panics if the module
+    /// was built without any projected fields.
+    pub unsafe fn execute_projected<'a>(&self, vec: &'a [F::Record]) -> ProjectedRows<'a, F> {
+        let mut output_vec = Vec::new();
+        (self.projected_fn_ptr)(vec, &mut output_vec);
+        ProjectedRows::new(&self.projection, output_vec)
+    }
+
+    /// Run the query from [`FilterOptions::aggregate`] over the matching records. Panics if
+    /// the module was built without one.
+    pub unsafe fn execute_aggregate<'a>(&self, vec: &'a [F::Record]) -> Vec<AggregateRow<'a>> {
+        let (query, aggregate_fn_ptr) = self
+            .aggregate
+            .as_ref()
+            .expect("the module was built without an aggregate query");
+
+        let mut state = query.new_state();
+        aggregate_fn_ptr(vec, &mut state);
+        query.finish(state)
+    }
+
+    /// The first [`FilterOptions::limit`] matching records, sorted by
+    /// [`FilterOptions::order_by`]
+    pub unsafe fn execute_ordered<'a>(&self, vec: &'a [F::Record]) -> Vec<&'a F::Record> {
+        let mut state = new_top_k(self.limit);
+        (self.ordered_fn_ptr)(vec, &mut state);
+        state.rows
+    }
+
+    /// Whether any record matches, stopping at the first match
+    pub unsafe fn any(&self, vec: &[F::Record]) -> bool {
+        let mut output = false;
+        (self.any_fn_ptr)(vec, &mut output);
+        output
+    }
+
+    /// Whether every record matches (true for no records), stopping at the first record that
+    /// doesn't
+    pub unsafe fn all(&self, vec: &[F::Record]) -> bool {
+        let mut output = true;
+        (self.all_fn_ptr)(vec, &mut output);
+        output
+    }
+
+    /// Number of matching records, without collecting them
+    pub unsafe fn count(&self, vec: &[F::Record]) -> usize {
+        let mut output = 0;
+        (self.count_fn_ptr)(vec, &mut output);
+        output
+    }
+
+    pub unsafe fn find_first<'a>(&self, vec: &'a [F::Record]) -> Option<&'a F::Record> {
+        let mut output = None;
+        (self.find_first_fn_ptr)(vec, &mut output);
+        output
+    }
+
+    /// Evaluate the filter without copying any records, see [`SelectionBitmap`]
+    pub unsafe fn execute_bitmap(&self, vec: &[F::Record]) -> SelectionBitmap {
+        let mut bitmap = SelectionBitmap::new(vec.len());
+        (self.bitmap_fn_ptr)(vec, bitmap.words_mut().as_mut_ptr());
+        bitmap
+    }
+}
+
+pub struct CallablePlanFn<F: RecordField = Field> {
+    _ee: exec_engine::JitExecutionEngine,
+    fn_ptr: exec_engine::JitPlanFunction<F::Record>,
+    operators: Vec<Operator<F>>,
+}
+
+impl<F: RecordField> CallablePlanFn<F> {
+    pub unsafe fn execute<'a>(&self, vec: &'a [F::Record]) -> PlanOutput<'a, F> {
+        let mut state = plan::new_state(&self.operators);
+        (self.fn_ptr)(vec, &mut state);
+        plan::finish(&self.operators, state)
+    }
 }
 
-pub unsafe fn build_module(filters: &JoinFilters) -> CallableJitFn {
+/// Load the functions module, add the functions `build` builds to it, then optimize and
+/// compile it. `name` is the function the engine tracks the module by.
+unsafe fn compile_module(
+    name: &str,
+    build: impl FnOnce(LLVMModuleRef, LLVMContextRef),
+) -> exec_engine::JitExecutionEngine {
     LLVM_InitializeNativeTarget();
     LLVM_InitializeNativeAsmPrinter();
     LLVM_InitializeNativeAsmParser();
@@ -68,7 +194,7 @@ pub unsafe fn build_module(filters: &JoinFilters) -> CallableJitFn {
 
     println!("Building module");
     let now = std::time::Instant::now();
-    build_fn::build_fn("execute", loaded.module, context, filters);
+    build(module, context);
     io::print_module_to_file(module, "jit.ll");
     Optimizer::new().optimize_module(module);
     io::print_module_to_file(module, "jit_opt.ll");
@@ -76,11 +202,85 @@ pub unsafe fn build_module(filters: &JoinFilters) -> CallableJitFn {
 
     println!("Adding module");
     let now = std::time::Instant::now();
-    exec_engine.add_function("execute", loaded);
+    exec_engine.add_function(name, loaded);
     dbg!(now.elapsed());
 
-    CallableJitFn {
+    exec_engine
+}
+
+pub unsafe fn build_module<F: RecordField>(filters: &JoinFilters<F>) -> CallableJitFn<F> {
+    build_module_with_options(filters, &FilterOptions::default())
+        .expect("the default options don't project or sort by any fields")
+}
+
+/// Fails if the options project or sort by a list field
+pub unsafe fn build_module_with_options<F: RecordField>(
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
+) -> Result<CallableJitFn<F>, PlanError<F>> {
+    options.check_fields()?;
+    let filters = prepare_filters(filters, options);
+    let exec_engine = compile_module("execute", |module, context| {
+        build_fn::build_fn("execute", module, context, &filters, options)
+    });
+
+    Ok(CallableJitFn {
         fn_ptr: exec_engine.get_function_ptr("execute"),
+        indices_fn_ptr: mem::transmute::<u64, exec_engine::JitIndicesFunction<F::Record>>(
+            exec_engine.lookup_address("execute_indices"),
+        ),
+        refs_fn_ptr: mem::transmute::<u64, exec_engine::JitRefsFunction<F::Record>>(
+            exec_engine.lookup_address("execute_refs"),
+        ),
+        projected_fn_ptr: mem::transmute::<u64, exec_engine::JitProjectedFunction<F::Record>>(
+            exec_engine.lookup_address("execute_projected"),
+        ),
+        projection: options.projection.to_vec(),
+        aggregate: options.aggregate.map(|query| {
+            let fn_ptr = mem::transmute::<u64, exec_engine::JitAggregateFunction<F::Record>>(
+                exec_engine.lookup_address("execute_aggregate"),
+            );
+            (query.clone(), fn_ptr)
+        }),
+        ordered_fn_ptr: mem::transmute::<u64, exec_engine::JitOrderedFunction<F::Record>>(
+            exec_engine.lookup_address("execute_ordered"),
+        ),
+        limit: options.limit,
+        any_fn_ptr: mem::transmute::<u64, exec_engine::JitQuantifierFunction<F::Record>>(
+            exec_engine.lookup_address("execute_any"),
+        ),
+        all_fn_ptr: mem::transmute::<u64, exec_engine::JitQuantifierFunction<F::Record>>(
+            exec_engine.lookup_address("execute_all"),
+        ),
+        count_fn_ptr: mem::transmute::<u64, exec_engine::JitCountFunction<F::Record>>(
+            exec_engine.lookup_address("execute_count"),
+        ),
+        find_first_fn_ptr: mem::transmute::<u64, exec_engine::JitFindFirstFunction<F::Record>>(
+            exec_engine.lookup_address("execute_find_first"),
+        ),
+        bitmap_fn_ptr: mem::transmute::<u64, exec_engine::JitBitmapFunction<F::Record>>(
+            exec_engine.lookup_address("execute_bitmap"),
+        ),
         _ee: exec_engine,
-    }
+    })
+}
+
+/// Compile the whole plan into one function. Only the options for how filters are prepared
+/// and built are used, the plan itself decides the output.
+pub unsafe fn build_plan_module<F: RecordField>(
+    plan: &Plan<F>,
+    options: &FilterOptions<F>,
+) -> Result<CallablePlanFn<F>, PlanError<F>> {
+    let operators = plan.operators(options)?;
+    let exec_engine = compile_module("execute_plan", |module, context| {
+        build_plan::build_plan_fn("execute_plan", module, context, &operators, options)
+    });
+
+    Ok(CallablePlanFn {
+        fn_ptr: mem::transmute::<u64, exec_engine::JitPlanFunction<F::Record>>(
+            exec_engine.lookup_address("execute_plan"),
+        ),
+        operators,
+        _ee: exec_engine,
+    })
 }
@@ -1,46 +1,394 @@
-use shared::User;
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    path::Path,
+    sync::Arc,
+};
 
+use serde::{Deserialize, Serialize};
+use shared::{case_fold, document::Document, schema::RecordField, User};
+
+use self::{
+    aggregate::AggregateQuery,
+    order::SortKey,
+    plan::{check_not_list, PlanError},
+    planner::reorder_by_selectivity,
+    regex_dfa::RegexDfa,
+    simplify::simplify,
+};
+
+pub mod aggregate;
+pub mod bitmap;
+pub mod filter_json;
 pub mod interpreted;
 pub mod jit;
+pub mod order;
+pub mod plan;
+pub mod planner;
+pub mod projection;
+pub mod query;
+pub mod regex_dfa;
+pub mod simplify;
 
-#[derive(Debug, Clone, Copy)]
+/// Fields of [`User`], the record type the runner has always worked on. Everything else can run
+/// over any record with a schema (see `shared::schema`), and defaults to users.
+pub use shared::UserField as Field;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[allow(dead_code)]
 pub enum FilterKind {
     StrContains,
     StrEquals,
     StrStartsWith,
     StrEndsWith,
+    StrContainsIgnoreCase,
+    StrEqualsIgnoreCase,
+    StrStartsWithIgnoreCase,
+    StrEndsWithIgnoreCase,
+    StrMatches,
+    StrIn,
+    IntEquals,
+    IntNotEquals,
+    IntLessThan,
+    IntLessOrEqual,
+    IntGreaterThan,
+    IntGreaterOrEqual,
+    /// Inclusive on both ends
+    IntBetween,
+    /// Whether a nullable field is missing, fields that aren't nullable never are
+    IsNull,
+    IsNotNull,
 }
 
-#[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
-pub enum Field {
-    Email,
-    Gender,
-    PhoneNumber,
-    LocationStreet,
-    LocationCity,
-    LocationState,
-    Username,
-    Password,
-    FirstName,
-    LastName,
-    Title,
-    Picture,
-}
-
-#[derive(Debug, Clone)]
-pub struct Filter {
-    field: Field,
+impl FilterKind {
+    /// Whether the kind applies to numeric fields rather than string fields
+    pub fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            FilterKind::IntEquals
+                | FilterKind::IntNotEquals
+                | FilterKind::IntLessThan
+                | FilterKind::IntLessOrEqual
+                | FilterKind::IntGreaterThan
+                | FilterKind::IntGreaterOrEqual
+                | FilterKind::IntBetween
+        )
+    }
+
+    /// Whether the kind checks if the field has a value, these apply to fields of any type
+    pub fn is_null_check(self) -> bool {
+        matches!(self, FilterKind::IsNull | FilterKind::IsNotNull)
+    }
+
+    pub fn is_ignore_case(self) -> bool {
+        matches!(
+            self,
+            FilterKind::StrContainsIgnoreCase
+                | FilterKind::StrEqualsIgnoreCase
+                | FilterKind::StrStartsWithIgnoreCase
+                | FilterKind::StrEndsWithIgnoreCase
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterValue {
+    Str(String),
+    Int(u64),
+    /// Inclusive range
+    IntRange(u64, u64),
+    /// Serialized as the pattern, and compiled again when deserialized
+    Regex(#[serde(with = "filter_json::regex_pattern")] Arc<RegexDfa>),
+    StrSet(#[serde(serialize_with = "filter_json::serialize_sorted")] HashSet<String>),
+    /// For `IsNull` and `IsNotNull`, which don't compare against anything
+    Null,
+}
+
+impl FilterValue {
+    pub fn regex(pattern: &str) -> Result<FilterValue, regex_dfa::RegexError> {
+        Ok(FilterValue::Regex(Arc::new(RegexDfa::new(pattern)?)))
+    }
+
+    pub fn str_set<S: Into<String>>(values: impl IntoIterator<Item = S>) -> FilterValue {
+        FilterValue::StrSet(values.into_iter().map(Into::into).collect())
+    }
+
+    /// Whether this is the kind of value `kind` expects
+    pub fn fits_kind(&self, kind: FilterKind) -> bool {
+        match self {
+            FilterValue::Str(_) => matches!(
+                kind,
+                FilterKind::StrContains
+                    | FilterKind::StrEquals
+                    | FilterKind::StrStartsWith
+                    | FilterKind::StrEndsWith
+                    | FilterKind::StrContainsIgnoreCase
+                    | FilterKind::StrEqualsIgnoreCase
+                    | FilterKind::StrStartsWithIgnoreCase
+                    | FilterKind::StrEndsWithIgnoreCase
+            ),
+            FilterValue::Int(_) => kind.is_numeric() && kind != FilterKind::IntBetween,
+            FilterValue::IntRange(..) => kind == FilterKind::IntBetween,
+            FilterValue::Regex(_) => kind == FilterKind::StrMatches,
+            FilterValue::StrSet(_) => kind == FilterKind::StrIn,
+            FilterValue::Null => kind.is_null_check(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            FilterValue::Str(value) => value,
+            other => panic!("Expected a string filter value, found {:?}", other),
+        }
+    }
+
+    pub fn as_int(&self) -> u64 {
+        match self {
+            FilterValue::Int(value) => *value,
+            other => panic!("Expected an integer filter value, found {:?}", other),
+        }
+    }
+
+    pub fn as_int_range(&self) -> (u64, u64) {
+        match self {
+            FilterValue::IntRange(min, max) => (*min, *max),
+            other => panic!("Expected an integer range filter value, found {:?}", other),
+        }
+    }
+
+    pub fn as_regex(&self) -> &RegexDfa {
+        match self {
+            FilterValue::Regex(regex) => regex,
+            other => panic!("Expected a regex filter value, found {:?}", other),
+        }
+    }
+
+    pub fn as_str_set(&self) -> &HashSet<String> {
+        match self {
+            FilterValue::StrSet(values) => values,
+            other => panic!("Expected a string set filter value, found {:?}", other),
+        }
+    }
+}
+
+// Regexes are compared by their pattern, and sets by their contents
+impl PartialEq for FilterValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (FilterValue::Str(a), FilterValue::Str(b)) => a == b,
+            (FilterValue::Int(a), FilterValue::Int(b)) => a == b,
+            (FilterValue::IntRange(a_min, a_max), FilterValue::IntRange(b_min, b_max)) => {
+                a_min == b_min && a_max == b_max
+            }
+            (FilterValue::Regex(a), FilterValue::Regex(b)) => a.pattern() == b.pattern(),
+            (FilterValue::StrSet(a), FilterValue::StrSet(b)) => a == b,
+            (FilterValue::Null, FilterValue::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for FilterValue {}
+
+impl Hash for FilterValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            FilterValue::Str(value) => value.hash(state),
+            FilterValue::Int(value) => value.hash(state),
+            FilterValue::IntRange(min, max) => (min, max).hash(state),
+            FilterValue::Regex(regex) => regex.pattern().hash(state),
+            FilterValue::StrSet(values) => {
+                // Set iteration order isn't stable, so sort for a consistent hash
+                let mut values = values.iter().collect::<Vec<_>>();
+                values.sort_unstable();
+                values.hash(state);
+            }
+            FilterValue::Null => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(
+    try_from = "filter_json::FilterDef<F>",
+    bound(deserialize = "F: RecordField + Deserialize<'de>")
+)]
+pub struct Filter<F = Field> {
+    field: F,
     kind: FilterKind,
-    value: String,
+    value: FilterValue,
 }
 
-#[derive(Debug, Clone)]
-pub enum JoinFilters {
-    Filter(Filter),
-    And(Box<JoinFilters>, Box<JoinFilters>),
-    Or(Box<JoinFilters>, Box<JoinFilters>),
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError<F = Field> {
+    /// The filter kind can't be used on this field's type
+    InvalidField { field: F, kind: FilterKind },
+    /// The value isn't the type the filter kind expects
+    InvalidValue { kind: FilterKind },
+}
+
+impl<F: fmt::Debug> fmt::Display for FilterError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::InvalidField { field, kind } => {
+                write!(
+                    f,
+                    "{:?} filters can't be used on the {:?} field",
+                    kind, field
+                )
+            }
+            FilterError::InvalidValue { kind } => {
+                write!(f, "invalid value for a {:?} filter", kind)
+            }
+        }
+    }
+}
+
+impl<F: fmt::Debug> std::error::Error for FilterError<F> {}
+
+impl<F: RecordField> Filter<F> {
+    pub fn new(field: F, kind: FilterKind, value: FilterValue) -> Result<Self, FilterError<F>> {
+        if !kind.is_null_check() && field.is_numeric() != kind.is_numeric() {
+            return Err(FilterError::InvalidField { field, kind });
+        }
+        // Filters on lists apply to their elements, which are never missing
+        if kind.is_null_check() && field.is_list() {
+            return Err(FilterError::InvalidField { field, kind });
+        }
+        if !value.fits_kind(kind) {
+            return Err(FilterError::InvalidValue { kind });
+        }
+
+        Ok(Filter::from_parts(field, kind, value))
+    }
+
+    /// Build a filter that was already checked like [`Filter::new`] does. Ignore case filters
+    /// keep their needle folded, so both engines only have to fold the field for each record.
+    pub(crate) fn from_parts(field: F, kind: FilterKind, value: FilterValue) -> Self {
+        let value = match value {
+            FilterValue::Str(needle) if kind.is_ignore_case() => {
+                FilterValue::Str(case_fold::fold_str(&needle))
+            }
+            value => value,
+        };
+        Filter { field, kind, value }
+    }
+
+    pub fn field(&self) -> F {
+        self.field
+    }
+
+    pub fn kind(&self) -> FilterKind {
+        self.kind
+    }
+
+    pub fn value(&self) -> &FilterValue {
+        &self.value
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(
+    rename_all = "snake_case",
+    bound(deserialize = "F: RecordField + Deserialize<'de>")
+)]
+pub enum JoinFilters<F = Field> {
+    Filter(Filter<F>),
+    And(Box<JoinFilters<F>>, Box<JoinFilters<F>>),
+    Or(Box<JoinFilters<F>>, Box<JoinFilters<F>>),
+    Not(Box<JoinFilters<F>>),
+    /// N-ary `And`, true if every child matches (or there are no children)
+    All(Vec<JoinFilters<F>>),
+    /// N-ary `Or`, true if any child matches (false if there are no children)
+    Any(Vec<JoinFilters<F>>),
+    /// True if the filter matches any element of its list field (false if the list is empty).
+    /// A `Filter` on a list field means the same. On other fields, the field's value is the
+    /// only element.
+    AnyElement(Filter<F>),
+    /// True if the filter matches every element of its list field (or the list is empty)
+    AllElements(Filter<F>),
+}
+
+/// Options for how a filter is prepared before it's interpreted or built into a module
+pub struct FilterOptions<'a, F: RecordField = Field> {
+    /// Reorder `And`/`Or` children by their selectivity on these records (usually the input
+    /// itself, it's sampled). `None` keeps the order the filter was written in.
+    pub reorder_sample: Option<&'a [F::Record]>,
+    /// JIT only, compare string constants inline in IR rather than calling the generic
+    /// `filter_str_*` functions where possible
+    pub inline_str_compares: bool,
+    /// Fields to output for each matching record in projected runs, in this order. List fields
+    /// can't be projected.
+    pub projection: &'a [F],
+    /// Query to run over the matching records in aggregate runs
+    pub aggregate: Option<&'a AggregateQuery<F>>,
+    /// Order of the matching records in ordered runs, which can't be by list fields. Empty
+    /// keeps the input order
+    pub order_by: &'a [SortKey<F>],
+    /// Most matching records to return from ordered runs
+    pub limit: Option<usize>,
+}
+
+// Derived impls would need the record type to implement them too
+impl<F: RecordField> Clone for FilterOptions<'_, F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<F: RecordField> Copy for FilterOptions<'_, F> {}
+
+impl<F: RecordField> fmt::Debug for FilterOptions<'_, F>
+where
+    F::Record: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterOptions")
+            .field("reorder_sample", &self.reorder_sample)
+            .field("inline_str_compares", &self.inline_str_compares)
+            .field("projection", &self.projection)
+            .field("aggregate", &self.aggregate)
+            .field("order_by", &self.order_by)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl<F: RecordField> Default for FilterOptions<'_, F> {
+    fn default() -> Self {
+        Self {
+            reorder_sample: None,
+            inline_str_compares: true,
+            projection: &[],
+            aggregate: None,
+            order_by: &[],
+            limit: None,
+        }
+    }
+}
+
+impl<F: RecordField> FilterOptions<'_, F> {
+    /// Projected and sorted by fields need a single value per record, like they do in plans
+    pub(crate) fn check_fields(&self) -> Result<(), PlanError<F>> {
+        check_not_list(self.projection.iter().copied())?;
+        check_not_list(self.order_by.iter().map(|key| key.field))
+    }
+}
+
+/// Simplify the filter, and plan it if the options ask for it
+pub(crate) fn prepare_filters<F: RecordField>(
+    filters: &JoinFilters<F>,
+    options: &FilterOptions<F>,
+) -> JoinFilters<F> {
+    let filters = simplify(filters);
+    match options.reorder_sample {
+        Some(records) => reorder_by_selectivity(&filters, records),
+        None => filters,
+    }
 }
 
 pub fn read_data() -> Vec<User> {
@@ -48,6 +396,21 @@ pub fn read_data() -> Vec<User> {
     serde_json::from_str(&contents).unwrap()
 }
 
+/// Read users from a json file in the same format as `data.json`
+pub fn read_data_file(path: impl AsRef<Path>) -> Result<Vec<User>, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Read documents from a json file holding an array of them, in any shape. Their fields are
+/// found with `DocumentSchema::infer`, see `shared::document`.
+pub fn read_documents_file(
+    path: impl AsRef<Path>,
+) -> Result<Vec<Document>, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
 pub fn build_complex_filter() -> JoinFilters {
     // Very arbitrary complex filters
 
@@ -57,7 +420,7 @@ pub fn build_complex_filter() -> JoinFilters {
     let burner_filter = JoinFilters::Filter(Filter {
         field: Field::FirstName,
         kind: FilterKind::StrStartsWith,
-        value: "a long value".to_string(),
+        value: FilterValue::Str("a long value".to_string()),
     });
     let burner_filter = JoinFilters::Or(
         Box::new(burner_filter.clone()),
@@ -85,18 +448,18 @@ pub fn build_complex_filter() -> JoinFilters {
             Box::new(JoinFilters::Filter(Filter {
                 field: Field::Email,
                 kind: FilterKind::StrContains,
-                value: "example.com".to_string(),
+                value: FilterValue::Str("example.com".to_string()),
             })),
             Box::new(JoinFilters::Filter(Filter {
                 field: Field::LocationCity,
                 kind: FilterKind::StrEquals,
-                value: "New York".to_string(),
+                value: FilterValue::Str("New York".to_string()),
             })),
         )),
         Box::new(JoinFilters::Filter(Filter {
             field: Field::Gender,
             kind: FilterKind::StrEquals,
-            value: "female".to_string(),
+            value: FilterValue::Str("female".to_string()),
         })),
     );
 
@@ -105,18 +468,18 @@ pub fn build_complex_filter() -> JoinFilters {
             Box::new(JoinFilters::Filter(Filter {
                 field: Field::Username,
                 kind: FilterKind::StrStartsWith,
-                value: "user_".to_string(),
+                value: FilterValue::Str("user_".to_string()),
             })),
             Box::new(JoinFilters::Filter(Filter {
                 field: Field::LocationState,
                 kind: FilterKind::StrEndsWith,
-                value: "shire".to_string(),
+                value: FilterValue::Str("shire".to_string()),
             })),
         )),
         Box::new(JoinFilters::Filter(Filter {
             field: Field::PhoneNumber,
             kind: FilterKind::StrContains,
-            value: "+123".to_string(),
+            value: FilterValue::Str("+123".to_string()),
         })),
     );
 
@@ -124,24 +487,24 @@ pub fn build_complex_filter() -> JoinFilters {
         Box::new(JoinFilters::Filter(Filter {
             field: Field::FirstName,
             kind: FilterKind::StrEquals,
-            value: "John".to_string(),
+            value: FilterValue::Str("John".to_string()),
         })),
         Box::new(JoinFilters::Or(
             Box::new(JoinFilters::Filter(Filter {
                 field: Field::LastName,
                 kind: FilterKind::StrEquals,
-                value: "Doe".to_string(),
+                value: FilterValue::Str("Doe".to_string()),
             })),
             Box::new(JoinFilters::And(
                 Box::new(JoinFilters::Filter(Filter {
                     field: Field::LocationCity,
                     kind: FilterKind::StrEquals,
-                    value: "London".to_string(),
+                    value: FilterValue::Str("London".to_string()),
                 })),
                 Box::new(JoinFilters::Filter(Filter {
                     field: Field::Title,
                     kind: FilterKind::StrEquals,
-                    value: "Dr".to_string(),
+                    value: FilterValue::Str("Dr".to_string()),
                 })),
             )),
         )),
@@ -1,15 +1,332 @@
-use runner::{build_complex_filter, interpreted, jit::build_module, read_data};
+use std::process::exit;
+
+use runner::{
+    aggregate::{Aggregate, AggregateQuery},
+    build_complex_filter, filter_json, interpreted,
+    jit::build_module_with_options,
+    order::SortKey,
+    query, read_data, read_data_file, read_documents_file, Field, FilterOptions, JoinFilters,
+};
+use serde::Serialize;
+use shared::{
+    document::{DocumentField, DocumentSchema},
+    schema::RecordField,
+};
+
+const USAGE: &str = "\
+usage: runner [options] [query]
+
+options:
+  --filter-file <path>  Load the filter from a json filter file instead of a query
+  --save-filter <path>  Save the filter being run as a json filter file
+  --data <path>         Load users from a json file instead of the bundled data.json
+  --documents           Load the data file as json documents of any shape instead of users,
+                        with fields addressed by path, e.g. location.city. Only filtering
+                        (and --reorder) is supported for documents.
+  --reorder             Reorder and/or conditions by their selectivity on the data
+  --project <fields>    Also output only these comma separated fields of matching users
+  --aggregate <list>    Also aggregate the matching users, e.g. count,min(birthdate)
+  --group-by <field>    Group the aggregates by a string field
+  --order-by <keys>     Also output matching users sorted by these comma separated fields,
+                        each optionally followed by asc or desc, e.g. last_name,birthdate desc
+  --limit <n>           Output at most n sorted users
+
+If neither a query nor a filter file is given, the hardcoded complex filter is used.";
+
+#[derive(Default)]
+struct Args {
+    query: Option<String>,
+    filter_file: Option<String>,
+    save_filter: Option<String>,
+    data_file: Option<String>,
+    documents: bool,
+    reorder: bool,
+    projection: Vec<Field>,
+    aggregates: Vec<Aggregate>,
+    group_by: Option<Field>,
+    order_by: Vec<SortKey>,
+    limit: Option<usize>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    exit(1);
+}
+
+fn parse_field(name: &str) -> Field {
+    let name = name.trim();
+    Field::from_name(name).unwrap_or_else(|| usage_error(&format!("unknown field {}", name)))
+}
+
+/// Projections and sort keys need a single value per user
+fn parse_scalar_field(name: &str) -> Field {
+    let field = parse_field(name);
+    if field.is_list() {
+        usage_error(&format!("{} is a list field", name.trim()));
+    }
+    field
+}
+
+fn parse_aggregate(aggregate: &str) -> Aggregate {
+    let aggregate = aggregate.trim();
+    if aggregate == "count" {
+        return Aggregate::Count;
+    }
+
+    let (function, field) = aggregate
+        .strip_suffix(')')
+        .and_then(|aggregate| aggregate.split_once('('))
+        .unwrap_or_else(|| usage_error(&format!("invalid aggregate {}", aggregate)));
+    match function {
+        "min" => Aggregate::Min(parse_field(field)),
+        "max" => Aggregate::Max(parse_field(field)),
+        _ => usage_error(&format!("unknown aggregate function {}", function)),
+    }
+}
+
+fn parse_sort_key(key: &str) -> SortKey {
+    match key.split_whitespace().collect::<Vec<_>>()[..] {
+        [field] | [field, "asc"] => SortKey::ascending(parse_scalar_field(field)),
+        [field, "desc"] => SortKey::descending(parse_scalar_field(field)),
+        _ => usage_error(&format!("invalid sort key {}", key)),
+    }
+}
+
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1);
+
+    while let Some(arg) = iter.next() {
+        let mut option_value = || {
+            iter.next()
+                .unwrap_or_else(|| usage_error(&format!("missing value for {}", arg)))
+        };
+
+        match arg.as_str() {
+            "--filter-file" => args.filter_file = Some(option_value()),
+            "--save-filter" => args.save_filter = Some(option_value()),
+            "--data" => args.data_file = Some(option_value()),
+            "--documents" => args.documents = true,
+            "--reorder" => args.reorder = true,
+            "--project" => {
+                args.projection = option_value().split(',').map(parse_scalar_field).collect()
+            }
+            "--aggregate" => {
+                args.aggregates = option_value().split(',').map(parse_aggregate).collect()
+            }
+            "--group-by" => args.group_by = Some(parse_field(&option_value())),
+            "--order-by" => args.order_by = option_value().split(',').map(parse_sort_key).collect(),
+            "--limit" => {
+                let value = option_value();
+                let limit = value
+                    .parse()
+                    .unwrap_or_else(|_| usage_error(&format!("invalid limit {}", value)));
+                args.limit = Some(limit);
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            _ if arg.starts_with("--") => usage_error(&format!("unknown option {}", arg)),
+            _ if args.query.is_none() => args.query = Some(arg),
+            _ => usage_error("only one query can be given"),
+        }
+    }
+
+    if args.query.is_some() && args.filter_file.is_some() {
+        usage_error("a query and a filter file can't both be given");
+    }
+    if args.group_by.is_some() && args.aggregates.is_empty() {
+        usage_error("--group-by needs --aggregate");
+    }
+    if args.documents {
+        if args.data_file.is_none() {
+            usage_error("--documents needs --data");
+        }
+        if args.query.is_none() && args.filter_file.is_none() {
+            usage_error("--documents needs a query or a filter file");
+        }
+        if !args.projection.is_empty()
+            || !args.aggregates.is_empty()
+            || !args.order_by.is_empty()
+            || args.limit.is_some()
+        {
+            usage_error("--documents only supports filtering");
+        }
+    }
+
+    args
+}
+
+fn save_filter<F: RecordField + Serialize>(args: &Args, filters: &JoinFilters<F>) {
+    if let Some(path) = &args.save_filter {
+        if let Err(err) = filter_json::save_filters(path, filters) {
+            eprintln!("failed to save filter file {}: {}", path, err);
+            exit(1);
+        }
+    }
+}
+
+/// Fields of documents are only known from the data, so the filter is parsed after it's loaded
+fn run_documents(args: &Args) {
+    let path = args.data_file.as_ref().unwrap();
+    let documents = read_documents_file(path).unwrap_or_else(|err| {
+        eprintln!("failed to read data file {}: {}", path, err);
+        exit(1);
+    });
+    let schema = DocumentSchema::infer(&documents);
+    let field_names = schema.fields().iter().map(|field| field.name());
+    println!(
+        "Document fields: {}",
+        field_names.collect::<Vec<_>>().join(", ")
+    );
+
+    let filters: JoinFilters<DocumentField> = if let Some(input) = &args.query {
+        query::parse_record_filter_with(&schema, input).unwrap_or_else(|err| {
+            eprintln!("{}", err.display_with_source(input));
+            exit(1);
+        })
+    } else {
+        let path = args.filter_file.as_ref().unwrap();
+        filter_json::load_document_filters(&schema, path).unwrap_or_else(|err| {
+            eprintln!("failed to load filter file {}: {}", path, err);
+            exit(1);
+        })
+    };
+    save_filter(args, &filters);
+
+    let options = FilterOptions {
+        reorder_sample: args.reorder.then_some(documents.as_slice()),
+        ..Default::default()
+    };
+
+    unsafe {
+        let jit_fn = build_module_with_options(&filters, &options).unwrap_or_else(|err| {
+            eprintln!("invalid options: {}", err);
+            exit(1);
+        });
+
+        let filtered = interpreted::filter_vec_with_options(&documents, &filters, &options);
+        println!("Interpreted len: {}", filtered.len());
+
+        let jit_indices = jit_fn.execute_indices(&documents);
+        println!("JIT indices len: {}", jit_indices.len());
+        println!("JIT count: {}", jit_fn.count(&documents));
+    }
+}
 
 fn main() {
-    let users = read_data();
-    let filters = build_complex_filter();
+    let args = parse_args();
+    if args.documents {
+        run_documents(&args);
+        return;
+    }
+
+    let users = match &args.data_file {
+        Some(path) => read_data_file(path).unwrap_or_else(|err| {
+            eprintln!("failed to read data file {}: {}", path, err);
+            exit(1);
+        }),
+        None => read_data(),
+    };
+
+    let filters = if let Some(input) = &args.query {
+        query::parse_filter(input).unwrap_or_else(|err| {
+            eprintln!("{}", err.display_with_source(input));
+            exit(1);
+        })
+    } else if let Some(path) = &args.filter_file {
+        filter_json::load_filters(path).unwrap_or_else(|err| {
+            eprintln!("failed to load filter file {}: {}", path, err);
+            exit(1);
+        })
+    } else {
+        build_complex_filter()
+    };
+
+    save_filter(&args, &filters);
+
+    let aggregate = (!args.aggregates.is_empty()).then(|| {
+        AggregateQuery::new(args.group_by, args.aggregates.clone()).unwrap_or_else(|err| {
+            eprintln!("invalid aggregate query: {}", err);
+            exit(1);
+        })
+    });
+
+    let options = FilterOptions {
+        reorder_sample: args.reorder.then_some(users.as_slice()),
+        projection: &args.projection,
+        aggregate: aggregate.as_ref(),
+        order_by: &args.order_by,
+        limit: args.limit,
+        ..Default::default()
+    };
+
     unsafe {
-        let jit_fn = build_module(&filters);
+        let jit_fn = build_module_with_options(&filters, &options).unwrap_or_else(|err| {
+            eprintln!("invalid options: {}", err);
+            exit(1);
+        });
 
-        let filtered_users = interpreted::filter_vec_with_filters(&users, &filters);
+        let filtered_users = interpreted::filter_vec_with_options(&users, &filters, &options);
         println!("Interpreted len: {}", filtered_users.len());
 
         let jit_filtered_users = jit_fn.execute(&users);
         println!("JIT len: {}", jit_filtered_users.len());
+
+        let jit_indices = jit_fn.execute_indices(&users);
+        println!("JIT indices len: {}", jit_indices.len());
+
+        let jit_bitmap = jit_fn.execute_bitmap(&users);
+        println!("JIT bitmap count: {}", jit_bitmap.count());
+
+        println!("JIT count: {}", jit_fn.count(&users));
+        println!(
+            "JIT any: {}, all: {}, first match: {:?}",
+            jit_fn.any(&users),
+            jit_fn.all(&users),
+            jit_fn.find_first(&users).map(|user| &user.username)
+        );
+
+        if !args.projection.is_empty() {
+            let projected = interpreted::filter_projected_with_options(&users, &filters, &options)
+                .expect("checked when the module was built");
+            println!("Interpreted projected len: {}", projected.len());
+
+            let jit_projected = jit_fn.execute_projected(&users);
+            println!("JIT projected len: {}", jit_projected.len());
+            for row in jit_projected.rows().take(5) {
+                println!("  {:?}", row);
+            }
+        }
+
+        if options.aggregate.is_some() {
+            let rows = interpreted::filter_aggregate_with_options(&users, &filters, &options);
+            println!("Interpreted aggregate rows: {}", rows.len());
+
+            let jit_rows = jit_fn.execute_aggregate(&users);
+            println!("JIT aggregate rows: {}", jit_rows.len());
+            for row in jit_rows.iter().take(10) {
+                match row.group {
+                    Some(group) => println!("  {}: {:?}", group, row.values),
+                    None => println!("  {:?}", row.values),
+                }
+            }
+        }
+
+        if !args.order_by.is_empty() || args.limit.is_some() {
+            let ordered = interpreted::filter_ordered_with_options(&users, &filters, &options)
+                .expect("checked when the module was built");
+            println!("Interpreted ordered len: {}", ordered.len());
+
+            let jit_ordered = jit_fn.execute_ordered(&users);
+            println!("JIT ordered len: {}", jit_ordered.len());
+            for user in jit_ordered.iter().take(10) {
+                println!(
+                    "  {} {} ({}, {})",
+                    user.first_name, user.last_name, user.location.city, user.birthdate
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,72 @@
+//! ORDER BY and LIMIT over the users that match a filter.
+//!
+//! Both engines keep the best `limit` matches in a [`TopK`] heap while scanning, instead of
+//! collecting every match and sorting it afterwards. Rows with equal sort keys keep the order
+//! they had in the input, so the JIT and the interpreter return exactly the same rows. Missing
+//! values of nullable fields sort before every value.
+
+use std::cmp::Ordering;
+
+use shared::{schema::RecordField, top_k::TopK, ProjectedValue};
+
+use crate::{interpreted::get_field_value, Field};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SortKey<F = Field> {
+    pub field: F,
+    pub direction: SortDirection,
+}
+
+impl<F> SortKey<F> {
+    pub fn ascending(field: F) -> Self {
+        SortKey {
+            field,
+            direction: SortDirection::Ascending,
+        }
+    }
+
+    pub fn descending(field: F) -> Self {
+        SortKey {
+            field,
+            direction: SortDirection::Descending,
+        }
+    }
+}
+
+/// Compare two records by the keys in order, falling back to their position in the input
+/// (both must be borrowed from the same slice)
+pub(crate) fn compare_records<F: RecordField>(
+    a: &F::Record,
+    b: &F::Record,
+    order_by: &[SortKey<F>],
+) -> Ordering {
+    for key in order_by {
+        let ordering = match (get_field_value(a, key.field), get_field_value(b, key.field)) {
+            (ProjectedValue::Str(a), ProjectedValue::Str(b)) => a.cmp(b),
+            (ProjectedValue::Int(a), ProjectedValue::Int(b)) => a.cmp(&b),
+            // Missing values come first, like `None` does
+            (ProjectedValue::Null, ProjectedValue::Null) => Ordering::Equal,
+            (ProjectedValue::Null, _) => Ordering::Less,
+            (_, ProjectedValue::Null) => Ordering::Greater,
+            _ => unreachable!("a field always has the same type"),
+        };
+        let ordering = match key.direction {
+            SortDirection::Ascending => ordering,
+            SortDirection::Descending => ordering.reverse(),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    (a as *const F::Record).cmp(&(b as *const F::Record))
+}
+
+pub(crate) fn new_top_k<'a, R>(limit: Option<usize>) -> TopK<'a, R> {
+    TopK::new(limit.unwrap_or(usize::MAX))
+}
@@ -0,0 +1,313 @@
+//! Logical query plans, a chain of operators that starts from a scan of the input records.
+//!
+//! ```text
+//! Scan -> Filter -> Sort -> Limit -> Project
+//! ```
+//!
+//! Before either engine runs a plan, it's checked and lowered into a flat list of
+//! [`Operator`]s. The interpreter runs them one at a time over the whole input, while the JIT
+//! gives each operator its own lowering pass into a single fused function (see
+//! `jit/build_plan.rs`). A projection reorders freely with the operators after it, so it's
+//! moved to the end, and a limit right after a sort becomes a top-k sort.
+
+use std::fmt;
+
+use shared::{aggregate::AggregateState, plan::PlanState, schema::RecordField, top_k::TopK};
+
+use crate::{
+    aggregate::{Aggregate, AggregateQuery, AggregateRow},
+    order::SortKey,
+    prepare_filters,
+    projection::ProjectedRows,
+    Field, FilterOptions, JoinFilters,
+};
+
+#[derive(Debug, Clone)]
+pub enum Plan<F = Field> {
+    /// Every input record, in order
+    Scan,
+    Filter {
+        input: Box<Plan<F>>,
+        filters: JoinFilters<F>,
+    },
+    Project {
+        input: Box<Plan<F>>,
+        fields: Vec<F>,
+    },
+    Aggregate {
+        input: Box<Plan<F>>,
+        query: AggregateQuery<F>,
+    },
+    Sort {
+        input: Box<Plan<F>>,
+        order_by: Vec<SortKey<F>>,
+    },
+    Limit {
+        input: Box<Plan<F>>,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanError<F = Field> {
+    /// Only the aggregate rows come out of an aggregate, so nothing can run after it
+    AfterAggregate,
+    EmptyProjection,
+    /// The field was dropped by an earlier projection
+    NotProjected(F),
+    /// Lists can only be filtered on, not projected or sorted by
+    ListField(F),
+}
+
+impl<F: fmt::Debug> fmt::Display for PlanError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::AfterAggregate => write!(f, "nothing can run after an aggregate"),
+            PlanError::EmptyProjection => write!(f, "a projection needs at least one field"),
+            PlanError::NotProjected(field) => {
+                write!(f, "{:?} was dropped by an earlier projection", field)
+            }
+            PlanError::ListField(field) => {
+                write!(
+                    f,
+                    "the list field {:?} can't be projected or sorted by",
+                    field
+                )
+            }
+        }
+    }
+}
+
+impl<F: fmt::Debug> std::error::Error for PlanError<F> {}
+
+/// What comes out of a plan, depending on its last operator
+#[derive(Debug, Clone)]
+pub enum PlanOutput<'a, F: RecordField = Field> {
+    Rows(Vec<&'a F::Record>),
+    Projected(ProjectedRows<'a, F>),
+    Aggregated(Vec<AggregateRow<'a>>),
+}
+
+/// A checked plan operator. `Project` and `Aggregate` are only ever last.
+#[derive(Debug, Clone)]
+pub(crate) enum Operator<F = Field> {
+    /// Already prepared with the options the plan was lowered with
+    Filter(JoinFilters<F>),
+    Limit(usize),
+    /// Sort, keeping only the first `limit` rows
+    Sort {
+        order_by: Vec<SortKey<F>>,
+        limit: Option<usize>,
+    },
+    Project(Vec<F>),
+    Aggregate(AggregateQuery<F>),
+}
+
+impl<F: RecordField> Plan<F> {
+    pub fn scan() -> Self {
+        Plan::Scan
+    }
+
+    pub fn filter(self, filters: JoinFilters<F>) -> Self {
+        Plan::Filter {
+            input: Box::new(self),
+            filters,
+        }
+    }
+
+    pub fn project(self, fields: Vec<F>) -> Self {
+        Plan::Project {
+            input: Box::new(self),
+            fields,
+        }
+    }
+
+    pub fn aggregate(self, query: AggregateQuery<F>) -> Self {
+        Plan::Aggregate {
+            input: Box::new(self),
+            query,
+        }
+    }
+
+    pub fn sort(self, order_by: Vec<SortKey<F>>) -> Self {
+        Plan::Sort {
+            input: Box::new(self),
+            order_by,
+        }
+    }
+
+    pub fn limit(self, limit: usize) -> Self {
+        Plan::Limit {
+            input: Box::new(self),
+            limit,
+        }
+    }
+
+    /// The nodes from the scan up to this one
+    fn chain(&self) -> Vec<&Self> {
+        let mut chain = vec![self];
+        while let Some(input) = chain.last().unwrap().input() {
+            chain.push(input);
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn input(&self) -> Option<&Self> {
+        match self {
+            Plan::Scan => None,
+            Plan::Filter { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::Aggregate { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. } => Some(input),
+        }
+    }
+
+    /// Check the plan and lower it into the operators both engines run
+    pub(crate) fn operators(
+        &self,
+        options: &FilterOptions<F>,
+    ) -> Result<Vec<Operator<F>>, PlanError<F>> {
+        let mut operators = Vec::new();
+        // The projection is only applied at the end, until then it only limits the fields
+        // the other operators can read
+        let mut projection: Option<&[F]> = None;
+
+        for node in self.chain() {
+            if let Some(Operator::Aggregate(_)) = operators.last() {
+                return Err(PlanError::AfterAggregate);
+            }
+
+            match node {
+                Plan::Scan => {}
+                Plan::Filter { filters, .. } => {
+                    let mut fields = Vec::new();
+                    filter_fields(filters, &mut fields);
+                    check_projected(projection, fields.into_iter())?;
+                    operators.push(Operator::Filter(prepare_filters(filters, options)));
+                }
+                Plan::Project { fields, .. } => {
+                    if fields.is_empty() {
+                        return Err(PlanError::EmptyProjection);
+                    }
+                    check_projected(projection, fields.iter().copied())?;
+                    check_not_list(fields.iter().copied())?;
+                    projection = Some(fields);
+                }
+                Plan::Aggregate { query, .. } => {
+                    check_projected(projection, query_fields(query))?;
+                    // The aggregate only outputs its own rows, so the projection is dropped
+                    projection = None;
+                    operators.push(Operator::Aggregate(query.clone()));
+                }
+                Plan::Sort { order_by, .. } => {
+                    check_projected(projection, order_by.iter().map(|key| key.field))?;
+                    check_not_list(order_by.iter().map(|key| key.field))?;
+                    operators.push(Operator::Sort {
+                        order_by: order_by.clone(),
+                        limit: None,
+                    });
+                }
+                &Plan::Limit { limit, .. } => match operators.last_mut() {
+                    Some(Operator::Sort {
+                        limit: sort_limit, ..
+                    }) => *sort_limit = Some(sort_limit.map_or(limit, |l| l.min(limit))),
+                    _ => operators.push(Operator::Limit(limit)),
+                },
+            }
+        }
+
+        if let Some(fields) = projection {
+            operators.push(Operator::Project(fields.to_vec()));
+        }
+        Ok(operators)
+    }
+}
+
+fn check_projected<F: RecordField>(
+    projection: Option<&[F]>,
+    mut fields: impl Iterator<Item = F>,
+) -> Result<(), PlanError<F>> {
+    let Some(projection) = projection else {
+        return Ok(());
+    };
+    match fields.find(|field| !projection.contains(field)) {
+        Some(field) => Err(PlanError::NotProjected(field)),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn check_not_list<F: RecordField>(
+    mut fields: impl Iterator<Item = F>,
+) -> Result<(), PlanError<F>> {
+    match fields.find(|field| field.is_list()) {
+        Some(field) => Err(PlanError::ListField(field)),
+        None => Ok(()),
+    }
+}
+
+fn filter_fields<F: RecordField>(filters: &JoinFilters<F>, fields: &mut Vec<F>) {
+    match filters {
+        JoinFilters::Filter(filter)
+        | JoinFilters::AnyElement(filter)
+        | JoinFilters::AllElements(filter) => fields.push(filter.field),
+        JoinFilters::And(left, right) | JoinFilters::Or(left, right) => {
+            filter_fields(left, fields);
+            filter_fields(right, fields);
+        }
+        JoinFilters::Not(inner) => filter_fields(inner, fields),
+        JoinFilters::All(children) | JoinFilters::Any(children) => {
+            for child in children {
+                filter_fields(child, fields);
+            }
+        }
+    }
+}
+
+fn query_fields<F: RecordField>(query: &AggregateQuery<F>) -> impl Iterator<Item = F> + '_ {
+    let aggregate_fields = query
+        .aggregates()
+        .iter()
+        .filter_map(|aggregate| match *aggregate {
+            Aggregate::Count => None,
+            Aggregate::Min(field) | Aggregate::Max(field) => Some(field),
+        });
+    query.group_by().into_iter().chain(aggregate_fields)
+}
+
+/// The state a compiled plan starts from, with the `Limit` and `Sort` operators in order
+pub(crate) fn new_state<'a, F: RecordField>(operators: &[Operator<F>]) -> PlanState<'a, F::Record> {
+    let mut state = PlanState {
+        limits: Vec::new(),
+        sorted: Vec::new(),
+        rows: Vec::new(),
+        projected: Vec::new(),
+        aggregate: AggregateState::new(Vec::new()),
+    };
+    for operator in operators {
+        match operator {
+            Operator::Limit(limit) => state.limits.push(*limit),
+            Operator::Sort { limit, .. } => {
+                state.sorted.push(TopK::new(limit.unwrap_or(usize::MAX)))
+            }
+            Operator::Aggregate(query) => state.aggregate = query.new_state(),
+            Operator::Filter(_) | Operator::Project(_) => {}
+        }
+    }
+    state
+}
+
+/// Read the output of the last operator back from the state
+pub(crate) fn finish<'a, F: RecordField>(
+    operators: &[Operator<F>],
+    state: PlanState<'a, F::Record>,
+) -> PlanOutput<'a, F> {
+    match operators.last() {
+        Some(Operator::Project(fields)) => {
+            PlanOutput::Projected(ProjectedRows::new(fields, state.projected))
+        }
+        Some(Operator::Aggregate(query)) => PlanOutput::Aggregated(query.finish(state.aggregate)),
+        _ => PlanOutput::Rows(state.rows),
+    }
+}
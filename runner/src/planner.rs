@@ -0,0 +1,208 @@
+//! Optional planning pass that reorders `All`/`Any` children so that cheap, decisive
+//! predicates run first.
+//!
+//! Each node's pass rate is measured on a sample of the input, and each filter gets a rough
+//! cost from its kind and the average length of the field it reads. Children of `All` are
+//! sorted by `cost / (1 - pass rate)` and children of `Any` by `cost / pass rate`, which
+//! minimizes the expected work of short circuit evaluation if the children are independent.
+//!
+//! Runs after [`simplify`](crate::simplify::simplify), so only `All`/`Any` nodes are expected.
+
+use std::collections::HashMap;
+
+use shared::schema::{Record, RecordField};
+
+use crate::{interpreted::run_join_filters, Filter, FilterKind, FilterValue, JoinFilters};
+
+/// Upper bound on the number of records the estimates are taken from
+const SAMPLE_SIZE: usize = 1024;
+
+struct Planned<F> {
+    filters: JoinFilters<F>,
+    /// Rough amount of work to evaluate the node for one record
+    cost: f64,
+    /// Fraction of the sample the node matches
+    pass_rate: f64,
+}
+
+struct Planner<'a, F: RecordField> {
+    sample: Vec<&'a F::Record>,
+    field_lens: HashMap<F, f64>,
+}
+
+/// Reorder the children of every `All`/`Any` node by their estimated selectivity and cost on
+/// `records`. The result matches exactly the same records, only the evaluation order changes.
+pub fn reorder_by_selectivity<F: RecordField>(
+    filters: &JoinFilters<F>,
+    records: &[F::Record],
+) -> JoinFilters<F> {
+    if records.is_empty() {
+        return filters.clone();
+    }
+
+    // Spread the sample evenly over the input, in case it's sorted in some way
+    let step = records.len().div_ceil(SAMPLE_SIZE);
+    let mut planner = Planner {
+        sample: records.iter().step_by(step).collect(),
+        field_lens: HashMap::new(),
+    };
+
+    planner.plan(filters).filters
+}
+
+impl<F: RecordField> Planner<'_, F> {
+    fn plan(&mut self, filters: &JoinFilters<F>) -> Planned<F> {
+        match filters {
+            JoinFilters::Filter(filter)
+            | JoinFilters::AnyElement(filter)
+            | JoinFilters::AllElements(filter) => Planned {
+                cost: self.filter_cost(filter) * self.average_elements(filter.field()),
+                pass_rate: self.pass_rate(filters),
+                filters: filters.clone(),
+            },
+            JoinFilters::Not(inner) => {
+                let inner = self.plan(inner);
+                Planned {
+                    filters: JoinFilters::Not(Box::new(inner.filters)),
+                    cost: inner.cost,
+                    pass_rate: 1.0 - inner.pass_rate,
+                }
+            }
+            JoinFilters::All(children) => self.plan_chain(children, true),
+            JoinFilters::Any(children) => self.plan_chain(children, false),
+            JoinFilters::And(left, right) => {
+                self.plan_chain(&[(**left).clone(), (**right).clone()], true)
+            }
+            JoinFilters::Or(left, right) => {
+                self.plan_chain(&[(**left).clone(), (**right).clone()], false)
+            }
+        }
+    }
+
+    fn plan_chain(&mut self, children: &[JoinFilters<F>], is_all: bool) -> Planned<F> {
+        let mut children = children
+            .iter()
+            .map(|child| self.plan(child))
+            .collect::<Vec<_>>();
+
+        // Probability that evaluating this child ends the chain early
+        let short_circuit_rate = |child: &Planned<F>| {
+            if is_all {
+                1.0 - child.pass_rate
+            } else {
+                child.pass_rate
+            }
+        };
+
+        // Children that never short circuit get an infinite rank and end up last. The sort is
+        // stable, so ties keep the order they were written in.
+        children.sort_by(|a, b| {
+            let rank_a = a.cost / short_circuit_rate(a);
+            let rank_b = b.cost / short_circuit_rate(b);
+            rank_a.total_cmp(&rank_b)
+        });
+
+        // Expected cost, assuming each child is only reached if all previous ones didn't end
+        // the chain
+        let mut cost = 0.0;
+        let mut reached = 1.0;
+        for child in &children {
+            cost += reached * child.cost;
+            reached *= 1.0 - short_circuit_rate(child);
+        }
+
+        let children = children.into_iter().map(|child| child.filters).collect();
+        let filters = if is_all {
+            JoinFilters::All(children)
+        } else {
+            JoinFilters::Any(children)
+        };
+
+        Planned {
+            cost,
+            pass_rate: self.pass_rate(&filters),
+            filters,
+        }
+    }
+
+    /// Measured rather than derived from the children, so correlated children are accounted for
+    fn pass_rate(&self, filters: &JoinFilters<F>) -> f64 {
+        let passed = self
+            .sample
+            .iter()
+            .filter(|&&record| run_join_filters(record, filters))
+            .count();
+        passed as f64 / self.sample.len() as f64
+    }
+
+    /// For list fields, the average length of an element
+    fn average_len(&mut self, field: F) -> f64 {
+        let sample = &self.sample;
+        *self.field_lens.entry(field).or_insert_with(|| {
+            if field.is_list() {
+                let elements = sample
+                    .iter()
+                    .flat_map(|record| record.str_list_field(field))
+                    .collect::<Vec<_>>();
+                let total = elements.iter().map(|element| element.len()).sum::<usize>();
+                return total as f64 / elements.len().max(1) as f64;
+            }
+
+            let total = sample
+                .iter()
+                .map(|record| record.str_field(field).len())
+                .sum::<usize>();
+            total as f64 / sample.len() as f64
+        })
+    }
+
+    /// How many times a filter on the field runs per record, the average length of lists
+    /// (with a floor of 1 for the loop itself) and 1 for anything else
+    fn average_elements(&self, field: F) -> f64 {
+        if !field.is_list() {
+            return 1.0;
+        }
+        let total = self
+            .sample
+            .iter()
+            .map(|record| record.str_list_field(field).len())
+            .sum::<usize>();
+        (total as f64 / self.sample.len() as f64).max(1.0)
+    }
+
+    /// Very rough cost model, roughly in bytes compared
+    fn filter_cost(&mut self, filter: &Filter<F>) -> f64 {
+        let needle_len = match filter.value() {
+            FilterValue::Str(value) => value.len() as f64,
+            _ => 0.0,
+        };
+
+        match filter.kind() {
+            FilterKind::IntEquals
+            | FilterKind::IntNotEquals
+            | FilterKind::IntLessThan
+            | FilterKind::IntLessOrEqual
+            | FilterKind::IntGreaterThan
+            | FilterKind::IntGreaterOrEqual
+            | FilterKind::IntBetween
+            | FilterKind::IsNull
+            | FilterKind::IsNotNull => 1.0,
+            // Length check first, and then a memcmp that usually fails early
+            FilterKind::StrEquals | FilterKind::StrStartsWith | FilterKind::StrEndsWith => {
+                2.0 + needle_len / 8.0
+            }
+            // Hashing in the interpreter, byte switches in the JIT
+            FilterKind::StrIn => 4.0 + self.average_len(filter.field()) / 8.0,
+            // Decodes and folds chars, but only up to the needle's length
+            FilterKind::StrEqualsIgnoreCase
+            | FilterKind::StrStartsWithIgnoreCase
+            | FilterKind::StrEndsWithIgnoreCase => 4.0 + needle_len,
+            // Scans the whole field
+            FilterKind::StrContains => 2.0 + self.average_len(filter.field()),
+            FilterKind::StrMatches => 2.0 + 2.0 * self.average_len(filter.field()),
+            FilterKind::StrContainsIgnoreCase => {
+                4.0 + 4.0 * self.average_len(filter.field()) + needle_len
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Projected output, only the requested fields of each matching user.
+//!
+//! Values are borrowed from the input users and stored in one flat vec, row after row, with
+//! one value per projected field in the order the fields were given.
+
+use shared::{schema::RecordField, ProjectedValue};
+
+use crate::Field;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectedRows<'a, F = Field> {
+    fields: Vec<F>,
+    values: Vec<ProjectedValue<'a>>,
+}
+
+impl<'a, F: RecordField> ProjectedRows<'a, F> {
+    /// `values` must hold a whole number of rows of `fields`
+    pub(crate) fn new(fields: &[F], values: Vec<ProjectedValue<'a>>) -> Self {
+        assert!(!fields.is_empty(), "a projection needs at least one field");
+        assert_eq!(values.len() % fields.len(), 0, "incomplete projected row");
+
+        Self {
+            fields: fields.to_vec(),
+            values,
+        }
+    }
+
+    pub fn fields(&self) -> &[F] {
+        &self.fields
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len() / self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn row(&self, index: usize) -> &[ProjectedValue<'a>] {
+        let width = self.fields.len();
+        &self.values[index * width..(index + 1) * width]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[ProjectedValue<'a>]> + '_ {
+        self.values.chunks_exact(self.fields.len())
+    }
+
+    /// The value of `field` in row `index`, or `None` if the field wasn't projected
+    pub fn get(&self, index: usize, field: F) -> Option<ProjectedValue<'a>> {
+        let column = self.fields.iter().position(|&f| f == field)?;
+        Some(self.row(index)[column])
+    }
+}
@@ -0,0 +1,1022 @@
+//! A small textual query language that parses into [`JoinFilters`].
+//!
+//! ```text
+//! email contains "example.com" and (gender = "female" or title starts_with "dr")
+//! not email ends_with "example.com"
+//! birthdate between 0 and 946684800 and location_postcode >= 1000
+//! nickname is not null and not nickname = "bob"
+//! ```
+//!
+//! `not` binds tighter than `and`, which binds tighter than `or`. Chains of `and`/`or` parse
+//! into a single `All`/`Any` node. Parentheses and `not`s can be nested up to 128 deep.
+//!
+//! Field names are the names in the record's schema, for users the same as the
+//! `user_get_field_*` exports in the functions crate (`location_city` can also be written as
+//! `location.city`). Numeric fields take the `=`,
+//! `!=`, `<`, `<=`, `>`, `>=` and `between .. and ..` operators with integer literals.
+//! `matches` takes a regex, which is compiled into a DFA while parsing, and `in` takes a list
+//! of strings (`location_city in ["London", "Paris"]`). Any field takes `is null` and
+//! `is not null`, and a nullable field without a value matches no other predicate. List
+//! fields take a quantifier and a string predicate on their elements, `any tags starts_with
+//! "vip"` matches if any element does and `all secondary_emails ends_with "@corp.com"` if
+//! every element does (including when the list is empty).
+//!
+//! Queries over documents are parsed with [`parse_record_filter_with`] and the schema of the
+//! documents, where fields are paths like `location.city`.
+
+use std::{collections::HashSet, fmt, ops::Range, sync::Arc};
+
+use shared::{
+    document::{DocumentField, DocumentSchema},
+    schema::RecordField,
+};
+
+use crate::{regex_dfa::RegexDfa, Field, Filter, FilterKind, FilterValue, JoinFilters};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// Byte range in the input that the error refers to
+    pub span: Range<usize>,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// Render the error with the offending part of the input underlined.
+    pub fn display_with_source(&self, source: &str) -> String {
+        let start = source[..self.span.start].chars().count();
+        let len = source[self.span.clone()].chars().count().max(1);
+        format!(
+            "error: {}\n  {}\n  {}{}",
+            self.message,
+            source,
+            " ".repeat(start),
+            "^".repeat(len)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(u64),
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(ident) => format!("`{}`", ident),
+            Token::Str(_) => "string literal".to_string(),
+            Token::Int(_) => "integer literal".to_string(),
+            Token::Eq => "`=`".to_string(),
+            Token::Ne => "`!=`".to_string(),
+            Token::Lt => "`<`".to_string(),
+            Token::Le => "`<=`".to_string(),
+            Token::Gt => "`>`".to_string(),
+            Token::Ge => "`>=`".to_string(),
+            Token::LParen => "`(`".to_string(),
+            Token::RParen => "`)`".to_string(),
+            Token::LBracket => "`[`".to_string(),
+            Token::RBracket => "`]`".to_string(),
+            Token::Comma => "`,`".to_string(),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek_char()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Token, Range<usize>)>, ParseError> {
+        let mut tokens = Vec::new();
+
+        while let Some(ch) = self.peek_char() {
+            let start = self.pos;
+
+            if ch.is_whitespace() {
+                self.bump();
+                continue;
+            }
+
+            let token = match ch {
+                '(' => {
+                    self.bump();
+                    Token::LParen
+                }
+                ')' => {
+                    self.bump();
+                    Token::RParen
+                }
+                '[' => {
+                    self.bump();
+                    Token::LBracket
+                }
+                ']' => {
+                    self.bump();
+                    Token::RBracket
+                }
+                ',' => {
+                    self.bump();
+                    Token::Comma
+                }
+                '=' => {
+                    self.bump();
+                    // Accept both `=` and `==`
+                    if self.peek_char() == Some('=') {
+                        self.bump();
+                    }
+                    Token::Eq
+                }
+                '!' => {
+                    self.bump();
+                    if self.bump() != Some('=') {
+                        return Err(ParseError::new("expected `!=`", start..self.pos));
+                    }
+                    Token::Ne
+                }
+                '<' | '>' => {
+                    self.bump();
+                    let or_equal = self.peek_char() == Some('=');
+                    if or_equal {
+                        self.bump();
+                    }
+                    match (ch, or_equal) {
+                        ('<', false) => Token::Lt,
+                        ('<', true) => Token::Le,
+                        ('>', false) => Token::Gt,
+                        _ => Token::Ge,
+                    }
+                }
+                '"' => self.lex_string()?,
+                ch if ch.is_ascii_digit() => {
+                    while self.peek_char().map_or(false, |ch| ch.is_ascii_digit()) {
+                        self.bump();
+                    }
+                    let digits = &self.input[start..self.pos];
+                    let value = digits.parse().map_err(|_| {
+                        ParseError::new("integer literal is too large", start..self.pos)
+                    })?;
+                    Token::Int(value)
+                }
+                ch if is_ident_start(ch) => {
+                    while self.peek_char().map_or(false, is_ident_continue) {
+                        self.bump();
+                    }
+                    Token::Ident(self.input[start..self.pos].to_string())
+                }
+                ch => {
+                    return Err(ParseError::new(
+                        format!("unexpected character `{}`", ch),
+                        start..start + ch.len_utf8(),
+                    ))
+                }
+            };
+
+            tokens.push((token, start..self.pos));
+        }
+
+        Ok(tokens)
+    }
+
+    fn lex_string(&mut self) -> Result<Token, ParseError> {
+        let start = self.pos;
+        self.bump(); // Opening quote
+
+        let mut value = String::new();
+        loop {
+            let escape_start = self.pos;
+            match self.bump() {
+                None => {
+                    return Err(ParseError::new(
+                        "unterminated string literal",
+                        start..self.pos,
+                    ))
+                }
+                Some('"') => break,
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('u') => self.lex_unicode_escape(escape_start)?,
+                        Some(other) => {
+                            return Err(ParseError::new(
+                                format!("unknown escape sequence `\\{}`", other),
+                                escape_start..self.pos,
+                            ))
+                        }
+                        None => {
+                            return Err(ParseError::new(
+                                "unterminated string literal",
+                                start..self.pos,
+                            ))
+                        }
+                    };
+                    value.push(escaped);
+                }
+                Some(ch) => value.push(ch),
+            }
+        }
+
+        Ok(Token::Str(value))
+    }
+
+    /// Lex the `{XXXX}` part of a `\u{XXXX}` escape
+    fn lex_unicode_escape(&mut self, escape_start: usize) -> Result<char, ParseError> {
+        if self.bump() != Some('{') {
+            return Err(ParseError::new(
+                "expected `{` after `\\u`",
+                escape_start..self.pos,
+            ));
+        }
+
+        let digits_start = self.pos;
+        while self.peek_char().map_or(false, |ch| ch.is_ascii_hexdigit()) {
+            self.bump();
+        }
+        let digits = &self.input[digits_start..self.pos];
+
+        if self.bump() != Some('}') {
+            return Err(ParseError::new(
+                "expected `}` to close unicode escape",
+                escape_start..self.pos,
+            ));
+        }
+
+        u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| ParseError::new("invalid unicode escape", escape_start..self.pos))
+    }
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+fn is_ident_continue(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '.'
+}
+
+/// Most parentheses and `not`s a filter can be nested in. Each level recurses, so without a
+/// limit a long enough `((((..` would overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a, F> {
+    tokens: Vec<(Token, Range<usize>)>,
+    pos: usize,
+    input_len: usize,
+    /// Parentheses and `not`s the parser is currently in
+    depth: usize,
+    /// Finds fields by name, `F::from_name` unless the fields come from a schema
+    lookup: &'a dyn Fn(&str) -> Option<F>,
+}
+
+impl<F: RecordField> Parser<'_, F> {
+    fn peek(&self) -> Option<&(Token, Range<usize>)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(Token, Range<usize>), ParseError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => Err(ParseError::new(
+                format!("expected {}, found end of input", expected),
+                self.input_len..self.input_len,
+            )),
+        }
+    }
+
+    /// Go one level deeper for the `(` or `not` at `span`
+    fn enter(&mut self, span: Range<usize>) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ParseError::new(
+                format!("filter is nested more than {} levels deep", MAX_DEPTH),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some((Token::Ident(ident), _)) if ident == keyword)
+    }
+
+    /// Whether the next token names a list field, which makes an `any`/`all` before it a
+    /// quantifier rather than a field
+    fn peek_list_field(&self) -> bool {
+        match self.peek() {
+            Some((Token::Ident(name), _)) => (self.lookup)(name).map_or(false, F::is_list),
+            _ => false,
+        }
+    }
+
+    // Chains of `and`/`or` become a single n-ary node rather than a deep binary tree
+
+    fn parse_or(&mut self) -> Result<JoinFilters<F>, ParseError> {
+        let mut children = vec![self.parse_and()?];
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            children.push(self.parse_and()?);
+        }
+
+        if children.len() == 1 {
+            Ok(children.pop().unwrap())
+        } else {
+            Ok(JoinFilters::Any(children))
+        }
+    }
+
+    fn parse_and(&mut self) -> Result<JoinFilters<F>, ParseError> {
+        let mut children = vec![self.parse_not()?];
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            children.push(self.parse_not()?);
+        }
+
+        if children.len() == 1 {
+            Ok(children.pop().unwrap())
+        } else {
+            Ok(JoinFilters::All(children))
+        }
+    }
+
+    fn parse_not(&mut self) -> Result<JoinFilters<F>, ParseError> {
+        if self.peek_keyword("not") {
+            let (_, span) = self.next("`not`")?;
+            self.enter(span)?;
+            let inner = self.parse_not()?;
+            self.depth -= 1;
+            return Ok(JoinFilters::Not(Box::new(inner)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<JoinFilters<F>, ParseError> {
+        match self.next("a filter")? {
+            (Token::LParen, open_span) => {
+                self.enter(open_span.clone())?;
+                let inner = self.parse_or()?;
+                match self.peek() {
+                    Some((Token::RParen, _)) => {
+                        self.pos += 1;
+                        self.depth -= 1;
+                        Ok(inner)
+                    }
+                    Some((token, span)) => Err(ParseError::new(
+                        format!("expected `)`, found {}", token.describe()),
+                        span.clone(),
+                    )),
+                    None => Err(ParseError::new("unclosed `(`", open_span)),
+                }
+            }
+            (Token::Ident(quantifier), _)
+                if (quantifier == "any" || quantifier == "all") && self.peek_list_field() =>
+            {
+                let (Token::Ident(name), _) = self.next("a list field")? else {
+                    unreachable!()
+                };
+                let field = (self.lookup)(&name).unwrap();
+
+                let (kind, value) = self.parse_str_predicate()?;
+                let filter = Filter::from_parts(field, kind, value);
+                if quantifier == "any" {
+                    Ok(JoinFilters::AnyElement(filter))
+                } else {
+                    Ok(JoinFilters::AllElements(filter))
+                }
+            }
+            (Token::Ident(name), span) => {
+                let field = (self.lookup)(&name).ok_or_else(|| {
+                    ParseError::new(format!("unknown field `{}`", name), span.clone())
+                })?;
+                if field.is_list() {
+                    return Err(ParseError::new(
+                        format!(
+                            "`{}` is a list, filter it with `any {}` or `all {}`",
+                            name, name, name
+                        ),
+                        span,
+                    ));
+                }
+                let (kind, value) = if self.peek_keyword("is") {
+                    self.parse_null_check()?
+                } else if field.is_numeric() {
+                    self.parse_int_predicate()?
+                } else {
+                    self.parse_str_predicate()?
+                };
+
+                Ok(JoinFilters::Filter(Filter::from_parts(field, kind, value)))
+            }
+            (token, span) => Err(ParseError::new(
+                format!("expected a filter, found {}", token.describe()),
+                span,
+            )),
+        }
+    }
+
+    /// Parse `is null` or `is not null`
+    fn parse_null_check(&mut self) -> Result<(FilterKind, FilterValue), ParseError> {
+        self.pos += 1; // `is`
+        let kind = if self.peek_keyword("not") {
+            self.pos += 1;
+            FilterKind::IsNotNull
+        } else {
+            FilterKind::IsNull
+        };
+
+        match self.next("`null`")? {
+            (Token::Ident(ident), _) if ident == "null" => Ok((kind, FilterValue::Null)),
+            (token, span) => Err(ParseError::new(
+                format!("expected `null`, found {}", token.describe()),
+                span,
+            )),
+        }
+    }
+
+    fn parse_str_predicate(&mut self) -> Result<(FilterKind, FilterValue), ParseError> {
+        let kind = match self.next("an operator")? {
+            (Token::Eq, _) => FilterKind::StrEquals,
+            (Token::Ident(op), span) => match op.as_str() {
+                "contains" => FilterKind::StrContains,
+                "equals" => FilterKind::StrEquals,
+                "starts_with" => FilterKind::StrStartsWith,
+                "ends_with" => FilterKind::StrEndsWith,
+                "contains_ignore_case" => FilterKind::StrContainsIgnoreCase,
+                "equals_ignore_case" => FilterKind::StrEqualsIgnoreCase,
+                "starts_with_ignore_case" => FilterKind::StrStartsWithIgnoreCase,
+                "ends_with_ignore_case" => FilterKind::StrEndsWithIgnoreCase,
+                "matches" => FilterKind::StrMatches,
+                "in" => return Ok((FilterKind::StrIn, self.parse_str_set()?)),
+                _ => {
+                    return Err(ParseError::new(
+                        format!("unknown string operator `{}`", op),
+                        span,
+                    ))
+                }
+            },
+            (token, span) => {
+                return Err(ParseError::new(
+                    format!("expected a string operator, found {}", token.describe()),
+                    span,
+                ))
+            }
+        };
+
+        let (value, value_span) = self.parse_str()?;
+
+        if let FilterKind::StrMatches = kind {
+            let regex = RegexDfa::new(&value)
+                .map_err(|err| ParseError::new(format!("invalid regex: {}", err), value_span))?;
+            return Ok((kind, FilterValue::Regex(Arc::new(regex))));
+        }
+
+        Ok((kind, FilterValue::Str(value)))
+    }
+
+    /// Parse a `["a", "b", ...]` list
+    fn parse_str_set(&mut self) -> Result<FilterValue, ParseError> {
+        match self.next("`[`")? {
+            (Token::LBracket, _) => {}
+            (token, span) => {
+                return Err(ParseError::new(
+                    format!("expected `[`, found {}", token.describe()),
+                    span,
+                ))
+            }
+        }
+
+        let mut values = HashSet::new();
+        loop {
+            if let Some((Token::RBracket, _)) = self.peek() {
+                self.pos += 1;
+                break;
+            }
+
+            values.insert(self.parse_str()?.0);
+
+            match self.next("`,` or `]`")? {
+                (Token::Comma, _) => {}
+                (Token::RBracket, _) => break,
+                (token, span) => {
+                    return Err(ParseError::new(
+                        format!("expected `,` or `]`, found {}", token.describe()),
+                        span,
+                    ))
+                }
+            }
+        }
+
+        Ok(FilterValue::StrSet(values))
+    }
+
+    fn parse_str(&mut self) -> Result<(String, Range<usize>), ParseError> {
+        match self.next("a string literal")? {
+            (Token::Str(value), span) => Ok((value, span)),
+            (token, span) => Err(ParseError::new(
+                format!("expected a string literal, found {}", token.describe()),
+                span,
+            )),
+        }
+    }
+
+    fn parse_int_predicate(&mut self) -> Result<(FilterKind, FilterValue), ParseError> {
+        let kind = match self.next("an operator")? {
+            (Token::Eq, _) => FilterKind::IntEquals,
+            (Token::Ne, _) => FilterKind::IntNotEquals,
+            (Token::Lt, _) => FilterKind::IntLessThan,
+            (Token::Le, _) => FilterKind::IntLessOrEqual,
+            (Token::Gt, _) => FilterKind::IntGreaterThan,
+            (Token::Ge, _) => FilterKind::IntGreaterOrEqual,
+            (Token::Ident(op), _) if op == "between" => {
+                let min = self.parse_int()?;
+                match self.next("`and`")? {
+                    (Token::Ident(ident), _) if ident == "and" => {}
+                    (token, span) => {
+                        return Err(ParseError::new(
+                            format!("expected `and`, found {}", token.describe()),
+                            span,
+                        ))
+                    }
+                }
+                let max = self.parse_int()?;
+                return Ok((FilterKind::IntBetween, FilterValue::IntRange(min, max)));
+            }
+            (token, span) => {
+                return Err(ParseError::new(
+                    format!("expected a numeric operator, found {}", token.describe()),
+                    span,
+                ))
+            }
+        };
+
+        Ok((kind, FilterValue::Int(self.parse_int()?)))
+    }
+
+    fn parse_int(&mut self) -> Result<u64, ParseError> {
+        match self.next("an integer literal")? {
+            (Token::Int(value), _) => Ok(value),
+            (token, span) => Err(ParseError::new(
+                format!("expected an integer literal, found {}", token.describe()),
+                span,
+            )),
+        }
+    }
+}
+
+/// Parse a textual filter query into a [`JoinFilters`] tree.
+pub fn parse_filter(input: &str) -> Result<JoinFilters, ParseError> {
+    parse_record_filter::<Field>(input)
+}
+
+/// Parse a textual filter query over the fields of any record type
+pub fn parse_record_filter<F: RecordField>(input: &str) -> Result<JoinFilters<F>, ParseError> {
+    parse_with_lookup(input, &F::from_name)
+}
+
+/// Parse a textual filter query over documents, with their fields looked up in `schema`
+pub fn parse_record_filter_with(
+    schema: &DocumentSchema,
+    input: &str,
+) -> Result<JoinFilters<DocumentField>, ParseError> {
+    parse_with_lookup(input, &|name| schema.field(name))
+}
+
+fn parse_with_lookup<F: RecordField>(
+    input: &str,
+    lookup: &dyn Fn(&str) -> Option<F>,
+) -> Result<JoinFilters<F>, ParseError> {
+    let tokens = Lexer::new(input).tokenize()?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        input_len: input.len(),
+        depth: 0,
+        lookup,
+    };
+
+    let filters = parser.parse_or()?;
+
+    if let Some((token, span)) = parser.peek() {
+        return Err(ParseError::new(
+            format!(
+                "expected `and`, `or` or end of input, found {}",
+                token.describe()
+            ),
+            span.clone(),
+        ));
+    }
+
+    Ok(filters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(field: Field, kind: FilterKind, value: FilterValue) -> JoinFilters {
+        JoinFilters::Filter(Filter::new(field, kind, value).unwrap())
+    }
+
+    fn email_is(value: &str) -> JoinFilters {
+        filter(
+            Field::Email,
+            FilterKind::StrEquals,
+            FilterValue::Str(value.into()),
+        )
+    }
+
+    fn not(inner: JoinFilters) -> JoinFilters {
+        JoinFilters::Not(Box::new(inner))
+    }
+
+    fn parse(input: &str) -> JoinFilters {
+        parse_filter(input).unwrap_or_else(|err| panic!("{}", err.display_with_source(input)))
+    }
+
+    fn parse_error(input: &str) -> (String, Range<usize>) {
+        let err = parse_filter(input).unwrap_err();
+        (err.message, err.span)
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_than_or() {
+        assert_eq!(
+            parse(r#"email = "a" or email = "b" and not email = "c""#),
+            JoinFilters::Any(vec![
+                email_is("a"),
+                JoinFilters::All(vec![email_is("b"), not(email_is("c"))]),
+            ])
+        );
+        assert_eq!(
+            parse(r#"not email = "a" and email = "b""#),
+            JoinFilters::All(vec![not(email_is("a")), email_is("b")])
+        );
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            parse(r#"(email = "a" or email = "b") and email = "c""#),
+            JoinFilters::All(vec![
+                JoinFilters::Any(vec![email_is("a"), email_is("b")]),
+                email_is("c"),
+            ])
+        );
+        assert_eq!(
+            parse(r#"not (email = "a" or email = "b")"#),
+            not(JoinFilters::Any(vec![email_is("a"), email_is("b")]))
+        );
+        assert_eq!(parse(r#"((email = "a"))"#), email_is("a"));
+    }
+
+    #[test]
+    fn chains_parse_into_one_node() {
+        assert_eq!(
+            parse(r#"email = "a" and email = "b" and email = "c""#),
+            JoinFilters::All(vec![email_is("a"), email_is("b"), email_is("c")])
+        );
+        assert_eq!(
+            parse(r#"email = "a" or email = "b" or email = "c""#),
+            JoinFilters::Any(vec![email_is("a"), email_is("b"), email_is("c")])
+        );
+        assert_eq!(
+            parse(r#"email = "a" and email = "b" or email = "c" and email = "d""#),
+            JoinFilters::Any(vec![
+                JoinFilters::All(vec![email_is("a"), email_is("b")]),
+                JoinFilters::All(vec![email_is("c"), email_is("d")]),
+            ])
+        );
+        assert_eq!(parse(r#"not not email = "a""#), not(not(email_is("a"))));
+    }
+
+    #[test]
+    fn string_operators() {
+        let str_value = |value: &str| FilterValue::Str(value.into());
+        for (op, kind) in [
+            ("contains", FilterKind::StrContains),
+            ("equals", FilterKind::StrEquals),
+            ("=", FilterKind::StrEquals),
+            ("==", FilterKind::StrEquals),
+            ("starts_with", FilterKind::StrStartsWith),
+            ("ends_with", FilterKind::StrEndsWith),
+        ] {
+            assert_eq!(
+                parse(&format!(r#"title {} "Dr""#, op)),
+                filter(Field::Title, kind, str_value("Dr")),
+                "{}",
+                op
+            );
+        }
+
+        // Ignore case needles are folded when the filter is built
+        for (op, kind) in [
+            ("contains_ignore_case", FilterKind::StrContainsIgnoreCase),
+            ("equals_ignore_case", FilterKind::StrEqualsIgnoreCase),
+            (
+                "starts_with_ignore_case",
+                FilterKind::StrStartsWithIgnoreCase,
+            ),
+            ("ends_with_ignore_case", FilterKind::StrEndsWithIgnoreCase),
+        ] {
+            let parsed = parse(&format!(r#"title {} "Dr""#, op));
+            assert_eq!(
+                parsed,
+                filter(Field::Title, kind, str_value("dr")),
+                "{}",
+                op
+            );
+        }
+
+        assert_eq!(
+            parse(r#"email matches "^[a-z]+@""#),
+            filter(
+                Field::Email,
+                FilterKind::StrMatches,
+                FilterValue::regex("^[a-z]+@").unwrap()
+            )
+        );
+        assert_eq!(
+            parse(r#"location.city in ["Paris", "London",]"#),
+            filter(
+                Field::LocationCity,
+                FilterKind::StrIn,
+                FilterValue::str_set(["Paris", "London"])
+            )
+        );
+        assert_eq!(
+            parse(r#"location_city in []"#),
+            filter(
+                Field::LocationCity,
+                FilterKind::StrIn,
+                FilterValue::str_set(Vec::<String>::new())
+            )
+        );
+    }
+
+    #[test]
+    fn int_operators() {
+        for (op, kind) in [
+            ("=", FilterKind::IntEquals),
+            ("!=", FilterKind::IntNotEquals),
+            ("<", FilterKind::IntLessThan),
+            ("<=", FilterKind::IntLessOrEqual),
+            (">", FilterKind::IntGreaterThan),
+            (">=", FilterKind::IntGreaterOrEqual),
+        ] {
+            assert_eq!(
+                parse(&format!("birthdate {} 946684800", op)),
+                filter(Field::Birthdate, kind, FilterValue::Int(946684800)),
+                "{}",
+                op
+            );
+        }
+        assert_eq!(
+            parse("location_postcode between 1000 and 2000"),
+            filter(
+                Field::LocationPostcode,
+                FilterKind::IntBetween,
+                FilterValue::IntRange(1000, 2000)
+            )
+        );
+        assert_eq!(
+            parse(&format!("birthdate = {}", u64::MAX)),
+            filter(
+                Field::Birthdate,
+                FilterKind::IntEquals,
+                FilterValue::Int(u64::MAX)
+            )
+        );
+    }
+
+    #[test]
+    fn null_checks_and_quantifiers() {
+        assert_eq!(
+            parse("nickname is null"),
+            filter(Field::Nickname, FilterKind::IsNull, FilterValue::Null)
+        );
+        assert_eq!(
+            parse("last_login is not null"),
+            filter(Field::LastLogin, FilterKind::IsNotNull, FilterValue::Null)
+        );
+
+        let vip = Filter::new(
+            Field::Tags,
+            FilterKind::StrStartsWith,
+            FilterValue::Str("vip".into()),
+        )
+        .unwrap();
+        assert_eq!(
+            parse(r#"any tags starts_with "vip""#),
+            JoinFilters::AnyElement(vip.clone())
+        );
+        assert_eq!(
+            parse(r#"all tags starts_with "vip""#),
+            JoinFilters::AllElements(vip)
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            parse(r#"email = "q\"b\\s\n\t\r\0\u{e9}\u{1F600}""#),
+            email_is("q\"b\\s\n\t\r\0\u{e9}\u{1F600}")
+        );
+        // Non-ASCII text doesn't need escaping
+        assert_eq!(parse(r#"email = "é😀""#), email_is("é😀"));
+
+        assert_eq!(
+            parse_error(r#"email = "a\qb""#),
+            ("unknown escape sequence `\\q`".to_string(), 10..12)
+        );
+        assert_eq!(
+            parse_error(r#"email = "\u00e9""#),
+            ("expected `{` after `\\u`".to_string(), 9..12)
+        );
+        assert_eq!(
+            parse_error(r#"email = "\u{d800}""#),
+            ("invalid unicode escape".to_string(), 9..17)
+        );
+        assert_eq!(
+            parse_error(r#"email = "abc"#),
+            ("unterminated string literal".to_string(), 8..12)
+        );
+    }
+
+    #[test]
+    fn error_spans() {
+        assert_eq!(
+            parse_error(r#"(email = "a""#),
+            ("unclosed `(`".to_string(), 0..1)
+        );
+        assert_eq!(
+            parse_error(r#"(email = "a" email"#),
+            ("expected `)`, found `email`".to_string(), 13..18)
+        );
+        assert_eq!(
+            parse_error(r#"email = "a" and nope = "b""#),
+            ("unknown field `nope`".to_string(), 16..20)
+        );
+        assert_eq!(
+            parse_error(r#"birthdate = "1990""#),
+            (
+                "expected an integer literal, found string literal".to_string(),
+                12..18
+            )
+        );
+        assert_eq!(
+            parse_error("email = 1"),
+            (
+                "expected a string literal, found integer literal".to_string(),
+                8..9
+            )
+        );
+        assert_eq!(
+            parse_error("birthdate contains 1"),
+            (
+                "expected a numeric operator, found `contains`".to_string(),
+                10..18
+            )
+        );
+        assert_eq!(
+            parse_error(r#"email like "a""#),
+            ("unknown string operator `like`".to_string(), 6..10)
+        );
+        assert_eq!(
+            parse_error(r#"email = "a" )"#),
+            (
+                "expected `and`, `or` or end of input, found `)`".to_string(),
+                12..13
+            )
+        );
+        assert_eq!(
+            parse_error("email ="),
+            (
+                "expected a string literal, found end of input".to_string(),
+                7..7
+            )
+        );
+        assert_eq!(
+            parse_error("birthdate = 18446744073709551616"),
+            ("integer literal is too large".to_string(), 12..32)
+        );
+        assert_eq!(
+            parse_error(r#"tags = "a""#),
+            (
+                "`tags` is a list, filter it with `any tags` or `all tags`".to_string(),
+                0..4
+            )
+        );
+        assert_eq!(parse_error(r#"email matches "(""#).1, 14..17);
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested =
+            |depth: usize| format!("{}email = \"a\"{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(parse(&nested(MAX_DEPTH)), email_is("a"));
+        assert_eq!(
+            parse_error(&nested(MAX_DEPTH + 1)),
+            (
+                format!("filter is nested more than {} levels deep", MAX_DEPTH),
+                MAX_DEPTH..MAX_DEPTH + 1
+            )
+        );
+
+        let nots = |depth: usize| format!("{}email = \"a\"", "not ".repeat(depth));
+        assert!(parse_filter(&nots(MAX_DEPTH)).is_ok());
+        let start = 4 * MAX_DEPTH;
+        assert_eq!(parse_error(&nots(MAX_DEPTH + 1)).1, start..start + 3);
+
+        // Deep enough to overflow the stack without the limit
+        assert!(parse_filter(&"(".repeat(1_000_000)).is_err());
+        assert!(parse_filter(&nots(1_000_000)).is_err());
+
+        // Siblings don't add up
+        let siblings = vec![nested(MAX_DEPTH); 4].join(" and ");
+        assert!(parse_filter(&siblings).is_ok());
+        let mixed = format!("{}{}", "not (".repeat(MAX_DEPTH / 2), nested(0));
+        let mixed = format!("{}{}", mixed, ")".repeat(MAX_DEPTH / 2));
+        assert!(parse_filter(&mixed).is_ok());
+    }
+
+    #[test]
+    fn documents_are_parsed_with_their_schema() {
+        use shared::document::{Document, DocumentValue};
+
+        let document = |city: &str| Document {
+            root: DocumentValue::object([(
+                "location".to_string(),
+                DocumentValue::object([("city".to_string(), DocumentValue::Str(city.into()))]),
+            )]),
+        };
+        let schema = DocumentSchema::infer(&[document("Paris")]);
+        let empty = DocumentSchema::infer(&[]);
+
+        let filters = parse_record_filter_with(&schema, r#"location.city = "Paris""#).unwrap();
+        let JoinFilters::Filter(filter) = filters else {
+            panic!("{:?}", filters);
+        };
+        assert_eq!(filter.field().name(), "location.city");
+        assert_eq!(
+            parse_record_filter_with(&empty, r#"location.city = "Paris""#)
+                .unwrap_err()
+                .message,
+            "unknown field `location.city`"
+        );
+    }
+}
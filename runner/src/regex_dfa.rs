@@ -0,0 +1,258 @@
+//! Regex patterns compiled into a byte level DFA.
+//!
+//! The DFA is built once when the filter is created, and both the interpreter and the JIT
+//! walk the same transition table, so they always agree on what matches.
+
+use std::{collections::HashMap, fmt};
+
+use regex_automata::{
+    dfa::{dense, Automaton},
+    util::primitives::StateID,
+    Input,
+};
+
+/// Why a pattern couldn't be compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError(String);
+
+impl RegexError {
+    fn from_build_error(err: dense::BuildError) -> Self {
+        // The top level build error is vague, the useful details are in its sources
+        let mut message = err.to_string();
+        let mut source = std::error::Error::source(&err);
+        while let Some(err) = source {
+            message += &format!(": {}", err);
+            source = err.source();
+        }
+        RegexError(message)
+    }
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+#[derive(Debug, Clone)]
+pub struct DfaState {
+    /// Index of the next state for every possible input byte
+    pub transitions: Box<[usize; 256]>,
+    /// Reaching this state means the pattern matched somewhere in the input
+    pub is_match: bool,
+    /// This state can never lead to a match
+    pub is_dead: bool,
+    /// The pattern matches if the input ends while in this state (e.g. patterns ending in `$`)
+    pub matches_at_end: bool,
+}
+
+#[derive(Clone)]
+pub struct RegexDfa {
+    pattern: String,
+    /// The first state is the start state
+    states: Vec<DfaState>,
+}
+
+impl RegexDfa {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        let dfa = dense::Builder::new()
+            .configure(
+                dense::Config::new()
+                    .minimize(true)
+                    .dfa_size_limit(Some(10 << 20))
+                    .determinize_size_limit(Some(10 << 20)),
+            )
+            .build(pattern)
+            .map_err(RegexError::from_build_error)?;
+
+        let start_id = dfa
+            .start_state_forward(&Input::new(""))
+            .expect("unanchored start state without look-behind can't fail");
+
+        // Renumber the reachable states into a dense 0..n range, in the order they're found
+        let mut ids = HashMap::from([(start_id, 0)]);
+        let mut order = vec![start_id];
+        let mut states = Vec::new();
+
+        while states.len() < order.len() {
+            let id = order[states.len()];
+            let mut transitions = Box::new([0; 256]);
+
+            for byte in 0..=255u8 {
+                let next: StateID = dfa.next_state(id, byte);
+                transitions[byte as usize] = *ids.entry(next).or_insert_with(|| {
+                    order.push(next);
+                    order.len() - 1
+                });
+            }
+
+            states.push(DfaState {
+                transitions,
+                is_match: dfa.is_match_state(id),
+                is_dead: dfa.is_dead_state(id),
+                matches_at_end: dfa.is_match_state(dfa.next_eoi_state(id)),
+            });
+        }
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            states,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn states(&self) -> &[DfaState] {
+        &self.states
+    }
+
+    /// Whether the pattern matches anywhere in `haystack`
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        let mut state = &self.states[0];
+        for &byte in haystack {
+            if state.is_match {
+                return true;
+            }
+            if state.is_dead {
+                return false;
+            }
+            state = &self.states[state.transitions[byte as usize]];
+        }
+
+        state.is_match || state.matches_at_end
+    }
+}
+
+impl fmt::Debug for RegexDfa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegexDfa")
+            .field("pattern", &self.pattern)
+            .field("states", &self.states.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::*;
+
+    /// Check the DFA against the regex crate on every pattern and haystack
+    fn assert_agrees(patterns: &[&str], haystacks: &[&str]) {
+        for pattern in patterns {
+            let dfa = RegexDfa::new(pattern).unwrap();
+            let regex = Regex::new(pattern).unwrap();
+            for haystack in haystacks {
+                assert_eq!(
+                    dfa.is_match(haystack.as_bytes()),
+                    regex.is_match(haystack),
+                    "{:?} on {:?}",
+                    pattern,
+                    haystack
+                );
+            }
+        }
+    }
+
+    const HAYSTACKS: &[&str] = &[
+        "",
+        "a",
+        "z",
+        "az",
+        "abc",
+        "xabc",
+        "abcx",
+        "a\nz",
+        "foo",
+        "a foo b",
+        "food",
+        "foo_bar",
+        "é",
+        "école",
+        "ÉCOLE",
+        "αβγ",
+        "Straße",
+        "STRASSE",
+        "colour",
+        "color",
+        "555-1234",
+        "call 555-1234",
+        "😀",
+        "a😀z",
+        "bob@example.com",
+        "bob@example.com.au",
+    ];
+
+    #[test]
+    fn anchors() {
+        assert_agrees(
+            &[
+                "^abc", "abc$", "^abc$", "^$", "^a.*z$", "^", "$", "a$|^z", "(?m)^z",
+            ],
+            HAYSTACKS,
+        );
+    }
+
+    #[test]
+    fn empty_patterns() {
+        assert_agrees(&["", "()", "(?:)", "a*", "x?"], HAYSTACKS);
+    }
+
+    #[test]
+    fn word_boundaries() {
+        // Unicode word boundaries can't be built into a DFA, ASCII ones can
+        assert!(RegexDfa::new(r"\bfoo\b").is_err());
+        assert_agrees(
+            &[r"(?-u:\b)foo(?-u:\b)", r"(?-u:\B)oo", r"(?-u:\b)"],
+            HAYSTACKS,
+        );
+    }
+
+    #[test]
+    fn unicode_classes() {
+        assert_agrees(
+            &[
+                r"^.$",
+                r"^\w+$",
+                r"\p{Greek}",
+                r"^\p{Lu}",
+                r"[[:digit:]]{3}-\d{4}",
+                r"\d",
+                r"é",
+                r"(?i)école",
+                r"(?i)straße",
+                r"[^a-z]",
+                r"^\S+@\S+\.com$",
+            ],
+            HAYSTACKS,
+        );
+    }
+
+    #[test]
+    fn matches_ending_at_end_of_input() {
+        assert_agrees(
+            &[
+                "z$", "colou?r", "c$|x", r"\.com$", "o+", "😀", r"a\z", "1234$",
+            ],
+            HAYSTACKS,
+        );
+        // The match is only found once the DFA sees the end of the input
+        let dfa = RegexDfa::new("colou?r").unwrap();
+        assert!(dfa.is_match(b"color"));
+        assert!(dfa.is_match(b"colour"));
+        assert!(!dfa.is_match(b"colou"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in ["(", "[a-", "a{2,1}", r"\p{Nope}"] {
+            assert!(Regex::new(pattern).is_err());
+            assert!(RegexDfa::new(pattern).is_err(), "{:?}", pattern);
+        }
+    }
+}
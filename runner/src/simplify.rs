@@ -0,0 +1,457 @@
+//! Simplification pass over [`JoinFilters`], run before both interpreting and building IR.
+//!
+//! The output only uses n-ary `All`/`Any` nodes (never `And`/`Or`), with nested nodes of the
+//! same kind flattened, duplicate children removed and the usual boolean laws applied.
+//! Constant subtrees are folded, using `All([])` for "always true" and `Any([])` for
+//! "always false". Filters on list fields always end up in an `AnyElement`/`AllElements` node.
+
+use std::collections::HashSet;
+
+use shared::schema::RecordField;
+
+use crate::{Filter, FilterKind, FilterValue, JoinFilters};
+
+fn as_constant<F>(filters: &JoinFilters<F>) -> Option<bool> {
+    match filters {
+        JoinFilters::All(children) if children.is_empty() => Some(true),
+        JoinFilters::Any(children) if children.is_empty() => Some(false),
+        _ => None,
+    }
+}
+
+fn constant<F>(value: bool) -> JoinFilters<F> {
+    if value {
+        JoinFilters::All(Vec::new())
+    } else {
+        JoinFilters::Any(Vec::new())
+    }
+}
+
+pub fn simplify<F: RecordField>(filters: &JoinFilters<F>) -> JoinFilters<F> {
+    match filters {
+        JoinFilters::Filter(filter) if filter.field.is_list() => simplify_elements(filter, false),
+        JoinFilters::Filter(filter) => match filter_constant(filter) {
+            // Missing values match nothing, so a filter that matches every value only checks
+            // that there is one
+            Some(true) if filter.field.is_nullable() && !filter.kind.is_null_check() => {
+                JoinFilters::Filter(Filter {
+                    field: filter.field,
+                    kind: FilterKind::IsNotNull,
+                    value: FilterValue::Null,
+                })
+            }
+            Some(value) => constant(value),
+            None => filters.clone(),
+        },
+        JoinFilters::Not(inner) => match simplify(inner) {
+            JoinFilters::Not(inner) => *inner,
+            inner => match as_constant(&inner) {
+                Some(value) => constant(!value),
+                None => JoinFilters::Not(Box::new(inner)),
+            },
+        },
+        JoinFilters::And(left, right) => simplify_chain([&**left, &**right], true),
+        JoinFilters::Or(left, right) => simplify_chain([&**left, &**right], false),
+        JoinFilters::All(children) => simplify_chain(children, true),
+        JoinFilters::Any(children) => simplify_chain(children, false),
+        JoinFilters::AnyElement(filter) => simplify_elements(filter, false),
+        JoinFilters::AllElements(filter) => simplify_elements(filter, true),
+    }
+}
+
+/// Simplify an `AllElements` (`is_all`) or `AnyElement` node. A filter that's the same for
+/// every element only makes the node constant when it doesn't matter whether the list is empty.
+fn simplify_elements<F: RecordField>(filter: &Filter<F>, is_all: bool) -> JoinFilters<F> {
+    if !filter.field.is_list() {
+        return simplify(&JoinFilters::Filter(filter.clone()));
+    }
+
+    match filter_constant(filter) {
+        Some(value) if value == is_all => constant(value),
+        _ if is_all => JoinFilters::AllElements(filter.clone()),
+        _ => JoinFilters::AnyElement(filter.clone()),
+    }
+}
+
+/// Simplify an `All` (`is_all`) or `Any` node
+fn simplify_chain<'a, F: RecordField>(
+    children: impl IntoIterator<Item = &'a JoinFilters<F>>,
+    is_all: bool,
+) -> JoinFilters<F> {
+    // For `All`, `true` children are the identity and `false` children short circuit. The
+    // other way around for `Any`.
+    let identity = is_all;
+
+    let mut flattened = Vec::new();
+    let mut seen = HashSet::new();
+    let mut push = |child: JoinFilters<F>, flattened: &mut Vec<JoinFilters<F>>| {
+        // Idempotence (x and x = x)
+        if seen.insert(child.clone()) {
+            flattened.push(child);
+        }
+    };
+
+    for child in children {
+        let child = simplify(child);
+        match as_constant(&child) {
+            Some(value) if value == identity => continue,
+            Some(_) => return constant(!identity),
+            None => {}
+        }
+
+        match child {
+            // Associativity, children were already simplified so they're flat themselves
+            JoinFilters::All(grandchildren) if is_all => {
+                for grandchild in grandchildren {
+                    push(grandchild, &mut flattened);
+                }
+            }
+            JoinFilters::Any(grandchildren) if !is_all => {
+                for grandchild in grandchildren {
+                    push(grandchild, &mut flattened);
+                }
+            }
+            child => push(child, &mut flattened),
+        }
+    }
+
+    let siblings = flattened.iter().cloned().collect::<HashSet<_>>();
+
+    // Complementation (x and not x = false)
+    let has_complement = flattened.iter().any(|child| match child {
+        JoinFilters::Not(inner) => siblings.contains(&**inner),
+        _ => false,
+    });
+    if has_complement {
+        return constant(!identity);
+    }
+
+    // Absorption (x and (x or y) = x)
+    flattened.retain(|child| {
+        let absorbed = match child {
+            JoinFilters::Any(grandchildren) if is_all => grandchildren.iter(),
+            JoinFilters::All(grandchildren) if !is_all => grandchildren.iter(),
+            _ => return true,
+        }
+        .any(|grandchild| siblings.contains(grandchild));
+        !absorbed
+    });
+
+    match flattened.len() {
+        0 => constant(identity),
+        1 => flattened.pop().unwrap(),
+        _ if is_all => JoinFilters::All(flattened),
+        _ => JoinFilters::Any(flattened),
+    }
+}
+
+/// Whether a single filter gives the same result for every value of the field
+fn filter_constant<F: RecordField>(filter: &Filter<F>) -> Option<bool> {
+    match (filter.kind, &filter.value) {
+        // Only nullable fields can be missing
+        (FilterKind::IsNull, _) => (!filter.field.is_nullable()).then_some(false),
+        (FilterKind::IsNotNull, _) => (!filter.field.is_nullable()).then_some(true),
+        (
+            FilterKind::StrContains
+            | FilterKind::StrStartsWith
+            | FilterKind::StrEndsWith
+            | FilterKind::StrContainsIgnoreCase
+            | FilterKind::StrStartsWithIgnoreCase
+            | FilterKind::StrEndsWithIgnoreCase,
+            FilterValue::Str(value),
+        ) if value.is_empty() => Some(true),
+        (FilterKind::StrIn, FilterValue::StrSet(values)) if values.is_empty() => Some(false),
+        (kind, &FilterValue::Int(value)) => {
+            let max = filter.field.field_type().int_max();
+            match kind {
+                FilterKind::IntEquals if value > max => Some(false),
+                FilterKind::IntNotEquals if value > max => Some(true),
+                FilterKind::IntLessThan if value == 0 => Some(false),
+                FilterKind::IntLessThan if value > max => Some(true),
+                FilterKind::IntLessOrEqual if value >= max => Some(true),
+                FilterKind::IntGreaterThan if value >= max => Some(false),
+                FilterKind::IntGreaterOrEqual if value == 0 => Some(true),
+                FilterKind::IntGreaterOrEqual if value > max => Some(false),
+                _ => None,
+            }
+        }
+        (FilterKind::IntBetween, &FilterValue::IntRange(min, max)) => {
+            let field_max = filter.field.field_type().int_max();
+            if min > max || min > field_max {
+                Some(false)
+            } else if min == 0 && max >= field_max {
+                Some(true)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreted::run_join_filters, query::parse_filter, read_data, Field};
+
+    fn query(input: &str) -> JoinFilters {
+        parse_filter(input).unwrap()
+    }
+
+    fn email_is(value: &str) -> JoinFilters {
+        query(&format!("email = {:?}", value))
+    }
+
+    fn not(inner: JoinFilters) -> JoinFilters {
+        JoinFilters::Not(Box::new(inner))
+    }
+
+    fn and(left: JoinFilters, right: JoinFilters) -> JoinFilters {
+        JoinFilters::And(Box::new(left), Box::new(right))
+    }
+
+    fn or(left: JoinFilters, right: JoinFilters) -> JoinFilters {
+        JoinFilters::Or(Box::new(left), Box::new(right))
+    }
+
+    const TRUE: JoinFilters = JoinFilters::All(Vec::new());
+    const FALSE: JoinFilters = JoinFilters::Any(Vec::new());
+
+    #[test]
+    fn nested_chains_are_flattened() {
+        let (a, b, c) = (email_is("a"), email_is("b"), email_is("c"));
+        assert_eq!(
+            simplify(&and(a.clone(), and(b.clone(), c.clone()))),
+            JoinFilters::All(vec![a.clone(), b.clone(), c.clone()])
+        );
+        assert_eq!(
+            simplify(&or(JoinFilters::Any(vec![a.clone(), b.clone()]), c.clone())),
+            JoinFilters::Any(vec![a.clone(), b.clone(), c.clone()])
+        );
+        // Different kinds of chains stay nested
+        assert_eq!(
+            simplify(&and(a.clone(), or(b.clone(), c.clone()))),
+            JoinFilters::All(vec![a.clone(), JoinFilters::Any(vec![b.clone(), c])])
+        );
+        // A single child replaces its chain
+        assert_eq!(
+            simplify(&JoinFilters::All(vec![JoinFilters::Any(vec![
+                a.clone(),
+                b.clone()
+            ])])),
+            JoinFilters::Any(vec![a, b])
+        );
+    }
+
+    #[test]
+    fn duplicates_are_removed() {
+        let (a, b) = (email_is("a"), email_is("b"));
+        assert_eq!(
+            simplify(&JoinFilters::All(vec![a.clone(), b.clone(), a.clone()])),
+            JoinFilters::All(vec![a.clone(), b.clone()])
+        );
+        assert_eq!(simplify(&or(a.clone(), a.clone())), a);
+        // Duplicates from flattened chains too
+        assert_eq!(
+            simplify(&and(and(a.clone(), b.clone()), and(b.clone(), a.clone()))),
+            JoinFilters::All(vec![a, b])
+        );
+    }
+
+    #[test]
+    fn complements_are_constant() {
+        let (a, b) = (email_is("a"), email_is("b"));
+        assert_eq!(
+            simplify(&JoinFilters::All(vec![
+                a.clone(),
+                b.clone(),
+                not(a.clone())
+            ])),
+            FALSE
+        );
+        assert_eq!(simplify(&or(not(a.clone()), a.clone())), TRUE);
+        assert_eq!(simplify(&not(not(a.clone()))), a);
+        assert_eq!(simplify(&not(not(not(a.clone())))), not(a));
+    }
+
+    #[test]
+    fn absorbed_children_are_removed() {
+        let (a, b, c) = (email_is("a"), email_is("b"), email_is("c"));
+        assert_eq!(simplify(&and(a.clone(), or(a.clone(), b.clone()))), a);
+        assert_eq!(simplify(&or(and(b.clone(), a.clone()), a.clone())), a);
+        assert_eq!(
+            simplify(&JoinFilters::All(vec![
+                a.clone(),
+                c.clone(),
+                JoinFilters::Any(vec![b.clone(), c.clone()]),
+            ])),
+            JoinFilters::All(vec![a, c])
+        );
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        let a = email_is("a");
+        assert_eq!(simplify(&TRUE), TRUE);
+        assert_eq!(simplify(&FALSE), FALSE);
+        assert_eq!(simplify(&not(TRUE)), FALSE);
+        assert_eq!(simplify(&not(FALSE)), TRUE);
+        assert_eq!(simplify(&and(TRUE, a.clone())), a);
+        assert_eq!(simplify(&and(FALSE, a.clone())), FALSE);
+        assert_eq!(simplify(&or(FALSE, a.clone())), a);
+        assert_eq!(simplify(&or(a.clone(), TRUE)), TRUE);
+        assert_eq!(simplify(&JoinFilters::All(vec![TRUE, TRUE])), TRUE);
+        assert_eq!(simplify(&JoinFilters::Any(vec![FALSE, not(TRUE)])), FALSE);
+
+        for (input, expected) in [
+            (r#"email contains """#, TRUE),
+            (r#"email ends_with_ignore_case """#, TRUE),
+            (r#"email in []"#, FALSE),
+            ("birthdate < 0", FALSE),
+            ("birthdate >= 0", TRUE),
+            ("birthdate between 5 and 1", FALSE),
+            ("email is null", FALSE),
+            ("email is not null", TRUE),
+            // Past the largest `u32`
+            ("location_postcode = 4294967296", FALSE),
+            ("location_postcode != 4294967296", TRUE),
+            ("location_postcode <= 4294967295", TRUE),
+            ("location_postcode between 0 and 4294967295", TRUE),
+        ] {
+            assert_eq!(simplify(&query(input)), expected, "{}", input);
+        }
+
+        // Still depends on the value
+        let input = "location_postcode < 4294967295";
+        assert_eq!(simplify(&query(input)), query(input));
+    }
+
+    #[test]
+    fn nullable_filters_matching_every_value_check_for_one() {
+        assert_eq!(
+            simplify(&query(r#"nickname starts_with """#)),
+            query("nickname is not null")
+        );
+        assert_eq!(
+            simplify(&query("last_login >= 0")),
+            query("last_login is not null")
+        );
+        assert_eq!(simplify(&query("last_login < 0")), FALSE);
+        for input in ["nickname is null", "nickname is not null"] {
+            assert_eq!(simplify(&query(input)), query(input));
+        }
+    }
+
+    #[test]
+    fn list_filters_apply_to_elements() {
+        let filter = |kind, value| Filter::new(Field::Tags, kind, value).unwrap();
+        let vip = filter(FilterKind::StrEquals, FilterValue::Str("vip".into()));
+
+        // A plain filter on a list is an `AnyElement`
+        assert_eq!(
+            simplify(&JoinFilters::Filter(vip.clone())),
+            JoinFilters::AnyElement(vip.clone())
+        );
+        // And quantifiers on other fields are plain filters
+        let email = Filter::new(
+            Field::Email,
+            FilterKind::StrEquals,
+            FilterValue::Str("a".into()),
+        );
+        assert_eq!(
+            simplify(&JoinFilters::AllElements(email.unwrap())),
+            email_is("a")
+        );
+
+        // Only constant when an empty list gives the same result
+        let every = filter(FilterKind::StrContains, FilterValue::Str(String::new()));
+        let none = filter(
+            FilterKind::StrIn,
+            FilterValue::str_set(Vec::<String>::new()),
+        );
+        assert_eq!(simplify(&JoinFilters::AllElements(every.clone())), TRUE);
+        assert_eq!(
+            simplify(&JoinFilters::AnyElement(every.clone())),
+            JoinFilters::AnyElement(every)
+        );
+        assert_eq!(simplify(&JoinFilters::AnyElement(none.clone())), FALSE);
+        assert_eq!(
+            simplify(&JoinFilters::AllElements(none.clone())),
+            JoinFilters::AllElements(none)
+        );
+    }
+
+    /// The bundled users, with some of them given nullable values and list elements so both
+    /// sides of those filters are covered
+    fn users() -> Vec<shared::User> {
+        let mut users = read_data();
+        for (i, user) in users.iter_mut().enumerate() {
+            if i % 3 == 0 {
+                user.nickname = Some(user.first_name.clone());
+                user.last_login = Some(user.birthdate);
+            }
+            if i % 4 == 0 {
+                user.tags = vec!["vip".to_string(), user.location.city.clone()];
+            } else if i % 4 == 1 {
+                user.tags = vec!["vip".to_string()];
+            }
+        }
+        users
+    }
+
+    #[test]
+    fn simplified_filters_match_the_same_users() {
+        let users = users();
+        let mut filters = [
+            r#"email contains "a" and (email contains "a" or gender = "male")"#,
+            r#"(gender = "male" or gender = "female") and not gender = "male""#,
+            r#"not (not title = "mr" or not title = "mr") and birthdate > 0"#,
+            r#"gender = "male" and (location_city in [] or not location_city in [])"#,
+            r#"(email contains "" or nickname starts_with "a") and last_login >= 0"#,
+            r#"nickname contains "" or nickname is null"#,
+            r#"not nickname ends_with "" and not last_login is null"#,
+            r#"any tags = "vip" and not all tags = "vip""#,
+            r#"all tags contains "" or any tags in []"#,
+            r#"all tags in [] and not any tags contains """#,
+            r#"location_postcode != 4294967296 and location_postcode between 7 and 3"#,
+            r#"(title = "mr" and gender = "male") or (gender = "male" and title = "mr")"#,
+        ]
+        .map(query)
+        .to_vec();
+        filters.extend([
+            crate::build_complex_filter(),
+            and(TRUE, not(FALSE)),
+            or(FALSE, JoinFilters::Any(vec![JoinFilters::All(vec![])])),
+            JoinFilters::Filter(
+                Filter::new(
+                    Field::Tags,
+                    FilterKind::StrEquals,
+                    FilterValue::Str("vip".into()),
+                )
+                .unwrap(),
+            ),
+        ]);
+
+        let mut split_users = 0;
+        for filter in &filters {
+            let simplified = simplify(filter);
+            let mut matched = 0;
+            for user in &users {
+                let expected = run_join_filters(user, filter);
+                assert_eq!(
+                    run_join_filters(user, &simplified),
+                    expected,
+                    "{:?} simplified to {:?}",
+                    filter,
+                    simplified
+                );
+                matched += usize::from(expected);
+            }
+            if 0 < matched && matched < users.len() {
+                split_users += 1;
+            }
+        }
+        // Most filters have to match some users but not others to check anything
+        assert!(split_users > filters.len() / 2, "{}", split_users);
+    }
+}
@@ -0,0 +1,520 @@
+//! The interpreter and the JIT have to agree on every filter, checked over `data.json`.
+
+use runner::{
+    aggregate::{Aggregate, AggregateQuery, AggregateRow},
+    interpreted,
+    jit::{build_module, build_module_with_options, build_plan_module},
+    order::SortKey,
+    plan::{Plan, PlanError, PlanOutput},
+    query, read_data, Field, Filter, FilterKind, FilterOptions, FilterValue, JoinFilters,
+};
+use shared::User;
+
+/// The bundled users, with some of them given list elements (every user in `data.json` has
+/// empty lists) so both sides of list filters are covered
+fn users() -> Vec<User> {
+    let mut users = read_data();
+    for (i, user) in users.iter_mut().enumerate() {
+        match i % 4 {
+            0 => user.tags = vec!["vip".to_string(), user.location.city.clone()],
+            1 => user.tags = vec!["vip".to_string()],
+            2 => user.tags = vec![user.gender.clone()],
+            _ => {}
+        }
+        match i % 3 {
+            0 => user.secondary_emails = vec![format!("{}@corp.com", user.username)],
+            1 => {
+                user.secondary_emails =
+                    vec![format!("{}@corp.com", user.username), user.email.clone()]
+            }
+            _ => {}
+        }
+    }
+    users
+}
+
+fn query(input: &str) -> JoinFilters {
+    query::parse_filter(input).unwrap()
+}
+
+fn not(inner: JoinFilters) -> JoinFilters {
+    JoinFilters::Not(Box::new(inner))
+}
+
+fn and(left: JoinFilters, right: JoinFilters) -> JoinFilters {
+    JoinFilters::And(Box::new(left), Box::new(right))
+}
+
+fn or(left: JoinFilters, right: JoinFilters) -> JoinFilters {
+    JoinFilters::Or(Box::new(left), Box::new(right))
+}
+
+/// Filters that match some users, none, and all of them
+fn sample_filters() -> Vec<JoinFilters> {
+    vec![
+        query(r#"email contains "an""#),
+        query(r#"gender = "female" and birthdate < 500000000"#),
+        query(r#"any tags = "vip" or location_state starts_with "new""#),
+        query(r#"email = "nobody""#),
+        not(query(r#"email = "nobody""#)),
+    ]
+}
+
+/// Positions of `rows` in `users`, which they have to be borrowed from
+fn positions(users: &[User], rows: &[&User]) -> Vec<usize> {
+    rows.iter()
+        .map(|&row| (row as *const User as usize - users.as_ptr() as usize) / size_of::<User>())
+        .collect()
+}
+
+fn assert_indices_agree(users: &[User], filters: &JoinFilters) {
+    let jit_fn = unsafe { build_module(filters) };
+    assert_eq!(
+        unsafe { jit_fn.execute_indices(users) },
+        interpreted::filter_indices_with_filters(users, filters),
+        "{:?}",
+        filters
+    );
+}
+
+#[test]
+fn not_agrees_around_and_inside_every_node() {
+    let users = users();
+    let email = query(r#"email contains "an""#);
+    let male = query(r#"gender = "male""#);
+    let old = query("birthdate < 500000000");
+    let nodes = [
+        email.clone(),
+        and(email.clone(), male.clone()),
+        or(male.clone(), old.clone()),
+        JoinFilters::All(vec![email.clone(), male.clone(), old.clone()]),
+        JoinFilters::Any(vec![email.clone(), male.clone(), old.clone()]),
+        query(r#"any tags = "vip""#),
+        query(r#"all tags = "vip""#),
+        not(not(email.clone())),
+    ];
+
+    for node in nodes {
+        // Around the node
+        assert_indices_agree(&users, &not(node.clone()));
+        // And inside each kind of node, next to something that isn't negated
+        assert_indices_agree(&users, &and(not(node.clone()), old.clone()));
+        assert_indices_agree(&users, &or(male.clone(), not(node.clone())));
+        assert_indices_agree(
+            &users,
+            &JoinFilters::All(vec![not(node.clone()), email.clone()]),
+        );
+        assert_indices_agree(
+            &users,
+            &JoinFilters::Any(vec![old.clone(), not(node.clone())]),
+        );
+        assert_indices_agree(&users, &not(not(node)));
+    }
+}
+
+#[test]
+fn missing_values_only_match_is_null() {
+    // Every other user is missing both nullable fields, the rest have the values missing ones
+    // would read as if they weren't checked
+    let mut users = read_data();
+    for (i, user) in users.iter_mut().enumerate() {
+        if i % 2 == 0 {
+            user.nickname = Some(String::new());
+            user.last_login = Some(0);
+        } else {
+            user.nickname = None;
+            user.last_login = None;
+        }
+    }
+    let present = (0..users.len()).step_by(2).collect::<Vec<_>>();
+    let missing = (1..users.len()).step_by(2).collect::<Vec<_>>();
+
+    let str_kinds = [
+        (FilterKind::StrContains, FilterValue::Str(String::new())),
+        (FilterKind::StrEquals, FilterValue::Str(String::new())),
+        (FilterKind::StrStartsWith, FilterValue::Str(String::new())),
+        (FilterKind::StrEndsWith, FilterValue::Str(String::new())),
+        (
+            FilterKind::StrContainsIgnoreCase,
+            FilterValue::Str(String::new()),
+        ),
+        (
+            FilterKind::StrEqualsIgnoreCase,
+            FilterValue::Str(String::new()),
+        ),
+        (
+            FilterKind::StrStartsWithIgnoreCase,
+            FilterValue::Str(String::new()),
+        ),
+        (
+            FilterKind::StrEndsWithIgnoreCase,
+            FilterValue::Str(String::new()),
+        ),
+        (FilterKind::StrMatches, FilterValue::regex("^$").unwrap()),
+        (FilterKind::StrIn, FilterValue::str_set([""])),
+    ];
+    let int_kinds = [
+        (FilterKind::IntEquals, FilterValue::Int(0)),
+        (FilterKind::IntNotEquals, FilterValue::Int(1)),
+        (FilterKind::IntLessThan, FilterValue::Int(1)),
+        (FilterKind::IntLessOrEqual, FilterValue::Int(0)),
+        (FilterKind::IntGreaterOrEqual, FilterValue::Int(0)),
+        (FilterKind::IntBetween, FilterValue::IntRange(0, 1)),
+    ];
+    let mut cases = Vec::new();
+    for (kind, value) in str_kinds {
+        cases.push((Field::Nickname, kind, value, present.clone()));
+    }
+    for (kind, value) in int_kinds {
+        cases.push((Field::LastLogin, kind, value, present.clone()));
+    }
+    // No value is greater than 0, so nothing matches either way
+    cases.push((
+        Field::LastLogin,
+        FilterKind::IntGreaterThan,
+        FilterValue::Int(0),
+        Vec::new(),
+    ));
+    for field in [Field::Nickname, Field::LastLogin] {
+        cases.push((
+            field,
+            FilterKind::IsNull,
+            FilterValue::Null,
+            missing.clone(),
+        ));
+        cases.push((
+            field,
+            FilterKind::IsNotNull,
+            FilterValue::Null,
+            present.clone(),
+        ));
+    }
+
+    for (field, kind, value, expected) in cases {
+        let filters = JoinFilters::Filter(Filter::new(field, kind, value).unwrap());
+        assert_eq!(
+            interpreted::filter_indices_with_filters(&users, &filters),
+            expected,
+            "{:?}",
+            filters
+        );
+        assert_indices_agree(&users, &filters);
+        // Negating a filter on a missing value matches it, like any other non-match
+        assert_indices_agree(&users, &not(filters));
+    }
+}
+
+#[test]
+fn element_filters_agree_on_every_list_field() {
+    let users = users();
+    // Some users have empty lists, which no `any` and every `all` filter matches
+    let lists = [("tags", "vip"), ("secondary_emails", "@corp.com")];
+    for (field, needle) in lists {
+        for quantifier in ["any", "all"] {
+            for (kind, value) in [
+                ("=", "vip"),
+                ("ends_with", needle),
+                ("contains", "e"),
+                ("contains", "no element has this"),
+                ("matches", "^[a-z]+$"),
+                ("in", r#"["vip", "male"]"#),
+            ] {
+                let value = match kind {
+                    "in" => value.to_string(),
+                    _ => format!("{:?}", value),
+                };
+                let filters = query(&format!("{} {} {} {}", quantifier, field, kind, value));
+                assert_indices_agree(&users, &filters);
+                assert_indices_agree(&users, &not(filters));
+            }
+        }
+    }
+
+    // Both sides of the empty lists
+    let empty_tags = (0..users.len()).filter(|i| i % 4 == 3).collect::<Vec<_>>();
+    assert_eq!(
+        interpreted::filter_indices_with_filters(&users, &query(r#"all tags = "nothing""#)),
+        empty_tags
+    );
+    assert_indices_agree(&users, &query(r#"all tags = "nothing""#));
+    assert_indices_agree(&users, &query(r#"any secondary_emails contains """#));
+}
+
+#[test]
+fn list_fields_cant_be_projected_or_sorted_by() {
+    let users = users();
+    let filters = query(r#"gender = "female""#);
+    let projection = [Field::Email, Field::Tags];
+    let order_by = [SortKey::ascending(Field::SecondaryEmails)];
+
+    let projected = FilterOptions {
+        projection: &projection,
+        ..Default::default()
+    };
+    assert_eq!(
+        interpreted::filter_projected_with_options(&users, &filters, &projected).unwrap_err(),
+        PlanError::ListField(Field::Tags)
+    );
+    assert_eq!(
+        unsafe { build_module_with_options(&filters, &projected) }.err(),
+        Some(PlanError::ListField(Field::Tags))
+    );
+
+    let ordered = FilterOptions {
+        order_by: &order_by,
+        ..Default::default()
+    };
+    assert_eq!(
+        interpreted::filter_ordered_with_options(&users, &filters, &ordered).unwrap_err(),
+        PlanError::ListField(Field::SecondaryEmails)
+    );
+    assert_eq!(
+        unsafe { build_module_with_options(&filters, &ordered) }.err(),
+        Some(PlanError::ListField(Field::SecondaryEmails))
+    );
+}
+
+#[test]
+fn bitmap_selects_the_matching_indices() {
+    let users = users();
+    let filters = [
+        query(r#"email contains "an""#),
+        query(r#"gender = "male" and birthdate < 500000000"#),
+        query(r#"any tags = "vip""#),
+        // Nothing, and everything
+        query(r#"email = "nobody""#),
+        not(query(r#"email = "nobody""#)),
+    ];
+    for filters in filters {
+        let jit_fn = unsafe { build_module(&filters) };
+        let bitmap = unsafe { jit_fn.execute_bitmap(&users) };
+        let indices = unsafe { jit_fn.execute_indices(&users) };
+
+        assert_eq!(bitmap.len(), users.len());
+        assert_eq!(bitmap.iter_selected().collect::<Vec<_>>(), indices);
+        assert_eq!(bitmap.count(), indices.len());
+        assert_eq!(
+            bitmap,
+            interpreted::filter_bitmap_with_filters(&users, &filters),
+            "{:?}",
+            filters
+        );
+    }
+}
+
+#[test]
+fn refs_agree_with_indices() {
+    let users = users();
+    for filters in sample_filters() {
+        let jit_fn = unsafe { build_module(&filters) };
+        let refs = unsafe { jit_fn.execute_refs(&users) };
+        let indices = unsafe { jit_fn.execute_indices(&users) };
+
+        assert_eq!(positions(&users, &refs), indices, "{:?}", filters);
+        assert_eq!(
+            positions(
+                &users,
+                &interpreted::filter_refs_with_filters(&users, &filters)
+            ),
+            indices,
+            "{:?}",
+            filters
+        );
+    }
+}
+
+#[test]
+fn projected_rows_agree() {
+    let mut users = users();
+    for (i, user) in users.iter_mut().enumerate().filter(|(i, _)| i % 5 == 0) {
+        user.nickname = Some(format!("user {}", i));
+        user.last_login = Some(i as u64);
+    }
+    let projection = [
+        Field::Email,
+        Field::Birthdate,
+        Field::LocationCity,
+        Field::Nickname,
+        Field::LastLogin,
+    ];
+    let options = FilterOptions {
+        projection: &projection,
+        ..Default::default()
+    };
+
+    for filters in sample_filters() {
+        let jit_fn = unsafe { build_module_with_options(&filters, &options) }.unwrap();
+        let projected = unsafe { jit_fn.execute_projected(&users) };
+        assert_eq!(
+            projected,
+            interpreted::filter_projected_with_options(&users, &filters, &options).unwrap(),
+            "{:?}",
+            filters
+        );
+        assert_eq!(
+            projected.len(),
+            unsafe { jit_fn.count(&users) },
+            "{:?}",
+            filters
+        );
+    }
+}
+
+#[test]
+fn aggregates_agree() {
+    let users = users();
+    let values = vec![
+        Aggregate::Count,
+        Aggregate::Min(Field::Birthdate),
+        Aggregate::Max(Field::Birthdate),
+    ];
+    let queries = [
+        AggregateQuery::new(None, values.clone()).unwrap(),
+        AggregateQuery::new(Some(Field::LocationState), values.clone()).unwrap(),
+        AggregateQuery::new(Some(Field::Gender), vec![Aggregate::Count]).unwrap(),
+    ];
+
+    for aggregate_query in &queries {
+        let options = FilterOptions {
+            aggregate: Some(aggregate_query),
+            ..Default::default()
+        };
+        for filters in sample_filters() {
+            let jit_fn = unsafe { build_module_with_options(&filters, &options) }.unwrap();
+            assert_eq!(
+                unsafe { jit_fn.execute_aggregate(&users) },
+                interpreted::filter_aggregate_with_options(&users, &filters, &options),
+                "{:?} {:?}",
+                aggregate_query,
+                filters
+            );
+        }
+    }
+
+    // Min and max don't have a value when nothing matches, and there are no groups at all
+    let nothing = query(r#"email = "nobody""#);
+    let expected = [AggregateRow {
+        group: None,
+        values: vec![Some(0), None, None],
+    }];
+    for (aggregate_query, expected) in queries.iter().zip([&expected[..], &[], &[]]) {
+        let options = FilterOptions {
+            aggregate: Some(aggregate_query),
+            ..Default::default()
+        };
+        let jit_fn = unsafe { build_module_with_options(&nothing, &options) }.unwrap();
+        assert_eq!(unsafe { jit_fn.execute_aggregate(&users) }, expected);
+        assert_eq!(
+            interpreted::filter_aggregate_with_options(&users, &nothing, &options),
+            expected
+        );
+    }
+}
+
+#[test]
+fn plans_agree() {
+    let users = users();
+    let by_birthdate = vec![
+        SortKey::descending(Field::Birthdate),
+        SortKey::ascending(Field::Email),
+    ];
+    let count_by_state =
+        AggregateQuery::new(Some(Field::LocationState), vec![Aggregate::Count]).unwrap();
+
+    for filters in sample_filters() {
+        let plans = [
+            Plan::scan().filter(filters.clone()),
+            Plan::scan().filter(filters.clone()).limit(10),
+            Plan::scan()
+                .filter(filters.clone())
+                .sort(by_birthdate.clone())
+                .limit(10),
+            Plan::scan()
+                .sort(by_birthdate.clone())
+                .limit(100)
+                .filter(filters.clone())
+                .project(vec![Field::Email, Field::Birthdate]),
+            Plan::scan()
+                .filter(filters.clone())
+                .project(vec![Field::Username, Field::LocationState])
+                .sort(vec![SortKey::ascending(Field::Username)]),
+            Plan::scan()
+                .filter(filters.clone())
+                .aggregate(count_by_state.clone()),
+        ];
+
+        for plan in plans {
+            let options = FilterOptions::default();
+            let plan_fn = unsafe { build_plan_module(&plan, &options) }.unwrap();
+            let jit = unsafe { plan_fn.execute(&users) };
+            let interpreted = interpreted::execute_plan(&users, &plan, &options).unwrap();
+            match (jit, interpreted) {
+                (PlanOutput::Rows(jit), PlanOutput::Rows(interpreted)) => assert_eq!(
+                    positions(&users, &jit),
+                    positions(&users, &interpreted),
+                    "{:?}",
+                    plan
+                ),
+                (PlanOutput::Projected(jit), PlanOutput::Projected(interpreted)) => {
+                    assert_eq!(jit, interpreted, "{:?}", plan)
+                }
+                (PlanOutput::Aggregated(jit), PlanOutput::Aggregated(interpreted)) => {
+                    assert_eq!(jit, interpreted, "{:?}", plan)
+                }
+                (jit, interpreted) => panic!(
+                    "{:?} gave different outputs: {:?} and {:?}",
+                    plan, jit, interpreted
+                ),
+            }
+        }
+    }
+}
+
+#[test]
+fn quantifiers_agree() {
+    let users = users();
+    for filters in sample_filters() {
+        let jit_fn = unsafe { build_module(&filters) };
+        let indices = interpreted::filter_indices_with_filters(&users, &filters);
+
+        assert_eq!(
+            unsafe { jit_fn.any(&users) },
+            interpreted::filter_any_with_filters(&users, &filters),
+            "{:?}",
+            filters
+        );
+        assert_eq!(
+            unsafe { jit_fn.all(&users) },
+            interpreted::filter_all_with_filters(&users, &filters),
+            "{:?}",
+            filters
+        );
+        assert_eq!(
+            unsafe { jit_fn.count(&users) },
+            interpreted::filter_count_with_filters(&users, &filters),
+            "{:?}",
+            filters
+        );
+        assert_eq!(unsafe { jit_fn.count(&users) }, indices.len());
+
+        // Including `None` for the filter nothing matches
+        let first = unsafe { jit_fn.find_first(&users) };
+        let interpreted_first = interpreted::filter_find_first_with_filters(&users, &filters);
+        assert_eq!(
+            positions(&users, first.as_slice()),
+            positions(&users, interpreted_first.as_slice()),
+            "{:?}",
+            filters
+        );
+        assert_eq!(
+            positions(&users, first.as_slice()),
+            indices.first().copied().as_slice()
+        );
+
+        // Nothing to check, so `any` is false and `all` is true whatever the filter
+        assert!(!unsafe { jit_fn.any(&[]) });
+        assert!(unsafe { jit_fn.all(&[]) });
+        assert_eq!(unsafe { jit_fn.count(&[]) }, 0);
+        assert!(unsafe { jit_fn.find_first(&[]) }.is_none());
+    }
+}
@@ -0,0 +1,30 @@
+//! Accumulators for aggregate queries, filled in by compiled code and read back by the runner.
+//!
+//! Every group (or the whole input, without a group by) gets one row of `u64` slots. Slot 0 is
+//! always the number of matching users, the meaning of the other slots is up to the query.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+pub struct AggregateState<'a> {
+    /// Starting value of every slot in a row
+    pub initial: Vec<u64>,
+    /// The slots for queries without a group by
+    pub totals: Vec<u64>,
+    pub groups: BTreeMap<&'a str, Vec<u64>>,
+}
+
+impl<'a> AggregateState<'a> {
+    pub fn new(initial: Vec<u64>) -> Self {
+        Self {
+            totals: initial.clone(),
+            initial,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// The slots of a group, starting from the initial values if it's new
+    pub fn group_slots(&mut self, key: &'a str) -> &mut [u64] {
+        let initial = &self.initial;
+        self.groups.entry(key).or_insert_with(|| initial.clone())
+    }
+}
@@ -0,0 +1,536 @@
+//! Records without a fixed layout, for datasets that don't have a struct.
+//!
+//! A [`Document`] is a tree of JSON-like values, and its fields are the paths of keys to the
+//! string and unsigned integer values in it (`location.city`). Which paths there are, and
+//! their types, aren't known until the documents are loaded, so they're taken from the
+//! documents with [`DocumentSchema::infer`]. Fields are looked up by name in the schema with
+//! [`DocumentSchema::field`] rather than [`RecordField::from_name`], which only knows the
+//! fields of record types with a fixed layout, so queries and filter files over documents are
+//! read with the schema they're for.
+//!
+//! Every document field is nullable. A document without a value at the path, or with a value
+//! of another type there, doesn't have the field, so `is null` matches it and every other
+//! filter doesn't. Keys with a `.` in them can't be addressed.
+//!
+//! There's no getter per field for compiled code to call. It looks the path up one key at a
+//! time with the `document_lookup` export instead, and then checks and reads the value it found
+//! with the getters of the field's type (`document_value_is_str` and `document_value_str` for
+//! strings).
+
+#[cfg(feature = "std")]
+use alloc::{boxed::Box, collections::BTreeSet};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::schema::{FieldType, Record, RecordField};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum DocumentValue {
+    Null,
+    Bool(bool),
+    Int(u64),
+    /// Numbers that aren't unsigned integers, which no filter reads
+    Float(f64),
+    Str(String),
+    List(Vec<DocumentValue>),
+    /// Sorted by key, see [`DocumentValue::object`]
+    Object(Vec<(String, DocumentValue)>),
+}
+
+impl DocumentValue {
+    /// Sorts the entries so keys can be binary searched, and keeps the last value of repeated
+    /// keys like JSON parsers usually do
+    pub fn object(entries: impl IntoIterator<Item = (String, DocumentValue)>) -> Self {
+        let mut entries = entries.into_iter().collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // The sort is stable, so the last of each run of keys is the last value given
+        entries.reverse();
+        entries.dedup_by(|(a, _), (b, _)| a == b);
+        entries.reverse();
+        DocumentValue::Object(entries)
+    }
+
+    /// `None` if this isn't an object, or doesn't have the key
+    pub fn get(&self, key: &str) -> Option<&DocumentValue> {
+        let DocumentValue::Object(entries) = self else {
+            return None;
+        };
+        let index = entries
+            .binary_search_by(|(entry_key, _)| entry_key.as_str().cmp(key))
+            .ok()?;
+        Some(&entries[index].1)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DocumentValue::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<u64> {
+        match self {
+            DocumentValue::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The type of the fields that can read this value, if any can
+    fn field_type(&self) -> Option<FieldType> {
+        match self {
+            DocumentValue::Str(_) => Some(FieldType::Str),
+            DocumentValue::Int(_) => Some(FieldType::U64),
+            _ => None,
+        }
+    }
+}
+
+/// Compiled code passes a reference to the document as a reference to its root value
+#[derive(Clone)]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[repr(transparent)]
+pub struct Document {
+    pub root: DocumentValue,
+}
+
+impl Document {
+    /// The value at the end of `path`, if every key on the way is there
+    pub fn lookup(&self, path: &[&str]) -> Option<&DocumentValue> {
+        path.iter()
+            .try_fold(&self.root, |value, key| value.get(key))
+    }
+
+    /// The field's value, if it has one of the field's type
+    fn field_value(&self, field: DocumentField) -> Option<&DocumentValue> {
+        self.lookup(&field.0.keys)
+            .filter(|value| value.field_type() == Some(field.0.ty))
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct FieldPath {
+    name: &'static str,
+    /// Slices of `name`
+    keys: Vec<&'static str>,
+    ty: FieldType,
+}
+
+/// A path to string or integer values in documents, from a [`DocumentSchema`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DocumentField(&'static FieldPath);
+
+impl fmt::Debug for DocumentField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name)
+    }
+}
+
+/// The fields found in a set of documents
+pub struct DocumentSchema {
+    fields: Vec<DocumentField>,
+}
+
+impl DocumentSchema {
+    /// Every path to a string or integer value in `documents`, in the order they're first
+    /// seen. A path with values of different types takes the type of the first one, and the
+    /// others read as missing. Keys with a `.` in them are skipped, along with everything
+    /// under them, as a field name couldn't tell them apart from a path through nested
+    /// objects.
+    ///
+    /// Fields are `'static` like the variants of a field enum, so their paths are interned
+    /// for the rest of the process. Inferring another schema only allocates the paths (and
+    /// types) no schema had before.
+    #[cfg(feature = "std")]
+    pub fn infer(documents: &[Document]) -> Self {
+        let mut seen = BTreeSet::new();
+        let mut fields = Vec::new();
+        let mut keys = Vec::new();
+        for document in documents {
+            collect_fields(&document.root, &mut keys, &mut seen, &mut fields);
+        }
+        DocumentSchema { fields }
+    }
+
+    pub fn fields(&self) -> &[DocumentField] {
+        &self.fields
+    }
+
+    /// Look up a field by the name used in queries, its path with dots between the keys
+    pub fn field(&self, name: &str) -> Option<DocumentField> {
+        self.fields
+            .iter()
+            .copied()
+            .find(|field| field.0.name == name)
+    }
+}
+
+#[cfg(feature = "std")]
+fn collect_fields<'a>(
+    value: &'a DocumentValue,
+    keys: &mut Vec<&'a str>,
+    seen: &mut BTreeSet<String>,
+    fields: &mut Vec<DocumentField>,
+) {
+    if let DocumentValue::Object(entries) = value {
+        for (key, value) in entries {
+            if key.contains('.') {
+                continue;
+            }
+            keys.push(key);
+            collect_fields(value, keys, seen, fields);
+            keys.pop();
+        }
+        return;
+    }
+
+    let Some(ty) = value.field_type() else {
+        return;
+    };
+    let name = keys.join(".");
+    if keys.is_empty() || seen.contains(&name) {
+        return;
+    }
+    seen.insert(name.clone());
+    fields.push(intern_field(name, ty));
+}
+
+/// Every field path found by any schema, by name, with one path per type found at it
+#[cfg(feature = "std")]
+static FIELD_PATHS: std::sync::Mutex<
+    alloc::collections::BTreeMap<&'static str, Vec<&'static FieldPath>>,
+> = std::sync::Mutex::new(alloc::collections::BTreeMap::new());
+
+/// The field for the path `name` with values of type `ty`, the same one every time
+#[cfg(feature = "std")]
+fn intern_field(name: String, ty: FieldType) -> DocumentField {
+    let mut paths = FIELD_PATHS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let name = match paths.get_key_value(name.as_str()) {
+        Some((&name, typed_paths)) => {
+            if let Some(path) = typed_paths.iter().find(|path| path.ty == ty) {
+                return DocumentField(path);
+            }
+            name
+        }
+        None => Box::leak(name.into_boxed_str()),
+    };
+
+    let path: &'static FieldPath = Box::leak(Box::new(FieldPath {
+        name,
+        keys: name.split('.').collect(),
+        ty,
+    }));
+    paths.entry(name).or_default().push(path);
+    DocumentField(path)
+}
+
+impl RecordField for DocumentField {
+    type Record = Document;
+
+    /// Documents don't have fields of their own, they're in the [`DocumentSchema`] of each set
+    /// of documents
+    const ALL: &'static [Self] = &[];
+
+    fn name(self) -> &'static str {
+        self.0.name
+    }
+
+    fn field_type(self) -> FieldType {
+        self.0.ty
+    }
+
+    fn getter(self) -> &'static str {
+        match self.0.ty {
+            FieldType::Str => "document_value_str",
+            _ => "document_value_int",
+        }
+    }
+
+    fn presence_getter(self) -> Option<&'static str> {
+        match self.0.ty {
+            FieldType::Str => Some("document_value_is_str"),
+            _ => Some("document_value_is_int"),
+        }
+    }
+
+    fn path(self) -> Option<&'static [&'static str]> {
+        Some(&self.0.keys)
+    }
+}
+
+impl Record for Document {
+    type Field = DocumentField;
+
+    const SYMBOL_PREFIX: &'static str = "document_";
+
+    fn str_field(&self, field: DocumentField) -> &str {
+        assert!(!field.is_numeric(), "{:?} is not a string field", field);
+        self.field_value(field)
+            .and_then(DocumentValue::as_str)
+            .unwrap_or("")
+    }
+
+    fn int_field(&self, field: DocumentField) -> u64 {
+        assert!(field.is_numeric(), "{:?} is not a numeric field", field);
+        self.field_value(field)
+            .and_then(DocumentValue::as_int)
+            .unwrap_or(0)
+    }
+
+    fn has_value(&self, field: DocumentField) -> bool {
+        self.field_value(field).is_some()
+    }
+}
+
+#[cfg(feature = "std")]
+mod serde_impls {
+    use alloc::{string::String, vec::Vec};
+    use core::fmt;
+
+    use serde::{
+        de::{MapAccess, SeqAccess, Visitor},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    use super::{Document, DocumentField, DocumentValue};
+    use crate::schema::RecordField;
+
+    /// Fields are saved by name. There's no `Deserialize`, as they can only be looked up in
+    /// the schema of the documents they're for, see [`DocumentSchema::field`].
+    ///
+    /// [`DocumentSchema::field`]: super::DocumentSchema::field
+    impl Serialize for DocumentField {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.name())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Document {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(Document {
+                root: DocumentValue::deserialize(deserializer)?,
+            })
+        }
+    }
+
+    impl<'de> Deserialize<'de> for DocumentValue {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(DocumentValueVisitor)
+        }
+    }
+
+    struct DocumentValueVisitor;
+
+    impl<'de> Visitor<'de> for DocumentValueVisitor {
+        type Value = DocumentValue;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a json value")
+        }
+
+        fn visit_unit<E>(self) -> Result<DocumentValue, E> {
+            Ok(DocumentValue::Null)
+        }
+
+        fn visit_none<E>(self) -> Result<DocumentValue, E> {
+            Ok(DocumentValue::Null)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<DocumentValue, D::Error> {
+            DocumentValue::deserialize(deserializer)
+        }
+
+        fn visit_bool<E>(self, b: bool) -> Result<DocumentValue, E> {
+            Ok(DocumentValue::Bool(b))
+        }
+
+        fn visit_u64<E>(self, n: u64) -> Result<DocumentValue, E> {
+            Ok(DocumentValue::Int(n))
+        }
+
+        fn visit_i64<E>(self, n: i64) -> Result<DocumentValue, E> {
+            Ok(match u64::try_from(n) {
+                Ok(n) => DocumentValue::Int(n),
+                Err(_) => DocumentValue::Float(n as f64),
+            })
+        }
+
+        fn visit_f64<E>(self, n: f64) -> Result<DocumentValue, E> {
+            Ok(DocumentValue::Float(n))
+        }
+
+        fn visit_str<E>(self, s: &str) -> Result<DocumentValue, E> {
+            Ok(DocumentValue::Str(s.into()))
+        }
+
+        fn visit_string<E>(self, s: String) -> Result<DocumentValue, E> {
+            Ok(DocumentValue::Str(s))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<DocumentValue, A::Error> {
+            let mut values = Vec::new();
+            while let Some(value) = seq.next_element()? {
+                values.push(value);
+            }
+            Ok(DocumentValue::List(values))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<DocumentValue, A::Error> {
+            let mut entries = Vec::new();
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(DocumentValue::object(entries))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec};
+
+    use super::*;
+
+    fn str(s: &str) -> DocumentValue {
+        DocumentValue::Str(s.to_string())
+    }
+
+    fn object<const N: usize>(entries: [(&str, DocumentValue); N]) -> DocumentValue {
+        DocumentValue::object(entries.map(|(key, value)| (key.to_string(), value)))
+    }
+
+    fn document<const N: usize>(entries: [(&str, DocumentValue); N]) -> Document {
+        Document {
+            root: object(entries),
+        }
+    }
+
+    #[test]
+    fn lookup_follows_nested_keys() {
+        let document = document([
+            ("name", str("Ada")),
+            ("address", object([("city", str("London"))])),
+            ("tags", DocumentValue::List(vec![str("a")])),
+        ]);
+
+        assert_eq!(
+            document.lookup(&["name"]).and_then(DocumentValue::as_str),
+            Some("Ada")
+        );
+        assert!(document.lookup(&[]).is_some());
+        assert_eq!(
+            document
+                .lookup(&["address", "city"])
+                .and_then(DocumentValue::as_str),
+            Some("London")
+        );
+        assert!(document.lookup(&["address", "street"]).is_none());
+        assert!(document.lookup(&["missing", "city"]).is_none());
+        // Only objects have keys
+        assert!(document.lookup(&["name", "first"]).is_none());
+        assert!(document.lookup(&["tags", "0"]).is_none());
+    }
+
+    #[test]
+    fn object_keeps_the_last_value_of_repeated_keys() {
+        let value = object([("a", str("first")), ("b", str("b")), ("a", str("last"))]);
+        assert_eq!(value.get("a").and_then(DocumentValue::as_str), Some("last"));
+        assert_eq!(value.get("b").and_then(DocumentValue::as_str), Some("b"));
+    }
+
+    #[cfg(feature = "std")]
+    fn fields(schema: &DocumentSchema) -> Vec<(&'static str, FieldType)> {
+        schema
+            .fields()
+            .iter()
+            .map(|&field| (field.name(), field.field_type()))
+            .collect()
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn infer_finds_string_and_int_paths_in_order() {
+        let schema = DocumentSchema::infer(&[
+            document([
+                ("name", str("Ada")),
+                ("age", DocumentValue::Int(36)),
+                ("score", DocumentValue::Float(1.5)),
+                ("active", DocumentValue::Bool(true)),
+                ("nickname", DocumentValue::Null),
+                ("tags", DocumentValue::List(vec![str("a")])),
+            ]),
+            document([("address", object([("city", str("London"))]))]),
+        ]);
+
+        assert_eq!(
+            fields(&schema),
+            [
+                ("age", FieldType::U64),
+                ("name", FieldType::Str),
+                ("address.city", FieldType::Str),
+            ]
+        );
+        let city = schema.field("address.city").unwrap();
+        assert_eq!(city.path(), Some(&["address", "city"][..]));
+        assert!(schema.field("score").is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn infer_keeps_the_first_type_of_a_path() {
+        let documents = [
+            document([("id", DocumentValue::Int(7))]),
+            document([("id", str("seven"))]),
+        ];
+        let schema = DocumentSchema::infer(&documents);
+        assert_eq!(fields(&schema), [("id", FieldType::U64)]);
+
+        // Values of any other type read as missing
+        let id = schema.field("id").unwrap();
+        assert!(documents[0].has_value(id));
+        assert_eq!(documents[0].int_field(id), 7);
+        assert!(!documents[1].has_value(id));
+        assert_eq!(documents[1].int_field(id), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn infer_skips_dotted_keys() {
+        let schema = DocumentSchema::infer(&[document([
+            ("a.b", str("dotted")),
+            ("c.d", object([("e", str("under a dotted key"))])),
+            ("a", object([("b", str("nested"))])),
+        ])]);
+        assert_eq!(fields(&schema), [("a.b", FieldType::Str)]);
+
+        let field = schema.field("a.b").unwrap();
+        assert_eq!(field.path(), Some(&["a", "b"][..]));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn infer_interns_field_paths() {
+        let documents = [document([
+            ("interned", str("a")),
+            ("interned_int", DocumentValue::Int(1)),
+        ])];
+        let first = DocumentSchema::infer(&documents);
+        let second = DocumentSchema::infer(&documents);
+        for (a, b) in first.fields().iter().zip(second.fields()) {
+            assert!(core::ptr::eq(a.0, b.0));
+        }
+
+        // The same name with another type is another field, sharing the name
+        let other = DocumentSchema::infer(&[document([("interned", DocumentValue::Int(1))])]);
+        let str_field = first.field("interned").unwrap();
+        let int_field = other.field("interned").unwrap();
+        assert_ne!(str_field, int_field);
+        assert!(core::ptr::eq(str_field.name(), int_field.name()));
+        assert_eq!(int_field.field_type(), FieldType::U64);
+    }
+}
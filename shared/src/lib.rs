@@ -2,18 +2,49 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 extern crate alloc;
+// Lets the code `record_derive` generates refer to this crate as `::shared` here too
+extern crate self as shared;
 
-use alloc::string::String;
+pub mod aggregate;
+pub mod case_fold;
+pub mod document;
+pub mod plan;
+pub mod schema;
+pub mod substring_search;
+pub mod top_k;
+
+use alloc::{string::String, vec::Vec};
 #[cfg(feature = "std")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+use record_derive::JitRecord;
+
+// Flattened into `User`, so it has to be defined before it
+#[derive(Clone, JitRecord)]
 #[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
+#[jit_record(nested)]
+pub struct Location {
+    pub street: String,
+    pub city: String,
+    pub state: String,
+    pub postcode: u32,
+}
+
+// Users came first, so their symbols don't have a prefix
+#[derive(Clone, JitRecord)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
+#[jit_record(field = UserField, symbol_prefix = "")]
+#[jit_record_field_attr(cfg_attr(
+    feature = "std",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+))]
 pub struct User {
     pub email: String,
     pub gender: String,
     pub phone_number: String,
     pub birthdate: u64,
+    #[jit_record(flatten)]
     pub location: Location,
     pub username: String,
     pub password: String,
@@ -21,13 +52,181 @@ pub struct User {
     pub last_name: String,
     pub title: String,
     pub picture: String,
+    /// Missing for most users, including every user in `data.json`
+    pub nickname: Option<String>,
+    /// Unix timestamp, missing for users that never logged in
+    pub last_login: Option<u64>,
+    /// Empty for every user in `data.json`
+    #[cfg_attr(feature = "std", serde(default))]
+    pub tags: Vec<String>,
+    /// Other addresses than `email`, empty for every user in `data.json`
+    #[cfg_attr(feature = "std", serde(default))]
+    pub secondary_emails: Vec<String>,
 }
 
-#[derive(Clone)]
-#[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
-pub struct Location {
-    pub street: String,
-    pub city: String,
-    pub state: String,
-    pub postcode: u32,
+/// A single field of a projected record, borrowed from the record it was read from
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum ProjectedValue<'a> {
+    Str(&'a str),
+    Int(u64),
+    /// A nullable field without a value
+    Null,
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec, vec::Vec};
+
+    use record_derive::JitRecord;
+
+    use crate::schema::{FieldType, Record, RecordField};
+    use crate::{User, UserField};
+
+    #[derive(Clone, JitRecord)]
+    #[jit_record(nested)]
+    struct Station {
+        name: String,
+        elevation: u64,
+        #[jit_record(skip)]
+        _calibration: Vec<u8>,
+    }
+
+    #[derive(Clone, JitRecord)]
+    struct Reading {
+        sensor: String,
+        value: u32,
+        at: Option<u64>,
+        labels: Vec<String>,
+        #[jit_record(flatten)]
+        station: Station,
+        #[jit_record(skip)]
+        _raw: Vec<u8>,
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            sensor: "t1".into(),
+            value: 21,
+            at: None,
+            labels: vec!["indoor".into(), "north".into()],
+            station: Station {
+                name: "roof".into(),
+                elevation: 40,
+                _calibration: Vec::new(),
+            },
+            _raw: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn fields_are_flattened_in_declaration_order() {
+        let names = ReadingField::ALL
+            .iter()
+            .map(|field| field.name())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "sensor",
+                "value",
+                "at",
+                "labels",
+                "station_name",
+                "station_elevation"
+            ]
+        );
+        assert_eq!(
+            ReadingField::ALL
+                .iter()
+                .map(|field| field.field_type())
+                .collect::<Vec<_>>(),
+            [
+                FieldType::Str,
+                FieldType::U32,
+                FieldType::U64,
+                FieldType::StrList,
+                FieldType::Str,
+                FieldType::U64
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_fields_can_be_named_with_a_dot() {
+        assert_eq!(
+            ReadingField::from_name("station_name"),
+            Some(ReadingField::StationName)
+        );
+        assert_eq!(
+            ReadingField::from_name("station.name"),
+            Some(ReadingField::StationName)
+        );
+        assert_eq!(ReadingField::from_name("station"), None);
+        assert_eq!(ReadingField::from_name("_raw"), None);
+        assert_eq!(ReadingField::from_name("station._calibration"), None);
+        assert_eq!(
+            UserField::from_name("location.city"),
+            Some(UserField::LocationCity)
+        );
+    }
+
+    #[test]
+    fn getter_names() {
+        assert_eq!(ReadingField::Value.getter(), "reading_get_field_value");
+        assert_eq!(
+            ReadingField::StationElevation.getter(),
+            "reading_get_field_station_elevation"
+        );
+        assert_eq!(
+            ReadingField::Labels.getter(),
+            "reading_get_field_labels_len"
+        );
+        assert_eq!(
+            ReadingField::Labels.element_getter(),
+            Some("reading_get_field_labels_element")
+        );
+        assert_eq!(ReadingField::Sensor.element_getter(), None);
+        assert_eq!(
+            ReadingField::At.presence_getter(),
+            Some("reading_has_field_at")
+        );
+        assert_eq!(ReadingField::Value.presence_getter(), None);
+        assert_eq!(Reading::SYMBOL_PREFIX, "reading_");
+        assert_eq!(User::SYMBOL_PREFIX, "");
+        assert_eq!(
+            UserField::LocationPostcode.getter(),
+            "user_get_field_location_postcode"
+        );
+    }
+
+    #[test]
+    fn fields_are_read_from_the_record() {
+        let reading = reading();
+        assert_eq!(reading.str_field(ReadingField::Sensor), "t1");
+        assert_eq!(reading.str_field(ReadingField::StationName), "roof");
+        assert_eq!(reading.int_field(ReadingField::Value), 21);
+        assert_eq!(reading.int_field(ReadingField::StationElevation), 40);
+        assert_eq!(
+            reading.str_list_field(ReadingField::Labels),
+            ["indoor", "north"]
+        );
+
+        // A missing value reads as 0
+        assert!(!reading.has_value(ReadingField::At));
+        assert_eq!(reading.int_field(ReadingField::At), 0);
+        assert!(reading.has_value(ReadingField::Sensor));
+        let reading = Reading {
+            at: Some(1_700_000_000),
+            ..reading
+        };
+        assert!(reading.has_value(ReadingField::At));
+        assert_eq!(reading.int_field(ReadingField::At), 1_700_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Value is not a string field")]
+    fn reading_a_field_as_the_wrong_type_panics() {
+        reading().str_field(ReadingField::Value);
+    }
 }
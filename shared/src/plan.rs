@@ -0,0 +1,33 @@
+//! Everything a compiled query plan writes to while it runs, filled in by compiled code and
+//! read back by the runner.
+//!
+//! Operators refer to their piece of the state by index, in the order they appear in the plan.
+
+use alloc::vec::Vec;
+
+use crate::{aggregate::AggregateState, top_k::TopK, ProjectedValue, User};
+
+pub struct PlanState<'a, R = User> {
+    /// Rows each `Limit` still lets through
+    pub limits: Vec<usize>,
+    /// Rows collected by each `Sort`, which the operators after it run over
+    pub sorted: Vec<TopK<'a, R>>,
+    /// Output of plans that end in records
+    pub rows: Vec<&'a R>,
+    /// Output of plans that end in a projection
+    pub projected: Vec<ProjectedValue<'a>>,
+    /// Output of plans that end in an aggregate
+    pub aggregate: AggregateState<'a>,
+}
+
+impl<'a, R> PlanState<'a, R> {
+    /// Take one row out of limit `index`, or `false` if it has nothing left
+    pub fn take_limit(&mut self, index: usize) -> bool {
+        let remaining = &mut self.limits[index];
+        if *remaining == 0 {
+            return false;
+        }
+        *remaining -= 1;
+        true
+    }
+}
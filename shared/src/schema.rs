@@ -0,0 +1,128 @@
+//! Schemas of the record types queries can run over.
+//!
+//! A record type implements [`Record`], and lists its fields as a [`RecordField`] type (usually
+//! a fieldless enum). The runner only ever reads records through these traits, and the
+//! compiled code through the getters and runners the `functions` crate exports for the record,
+//! so the filter model, interpreter and JIT work the same for every record type.
+//!
+//! Nullable fields (`Option<String>`, `Option<u64>`, ...) have a getter for whether the value
+//! is there on top of the one for the value itself. A missing value matches no filter except
+//! `IsNull`, so `not` of a filter matches the records where the field is missing.
+//!
+//! List fields (`Vec<String>`) have getters for their length and for each element, and are
+//! filtered through a quantifier over their elements.
+//!
+//! Fields of records without a fixed layout are paths of keys rather than struct fields, see
+//! [`document`](crate::document).
+
+use alloc::string::String;
+use core::{fmt::Debug, hash::Hash};
+
+/// How a field is stored, numeric fields are read as `u64` whatever their width
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldType {
+    Str,
+    U32,
+    U64,
+    /// `Vec<String>`, filters on it apply to its elements
+    StrList,
+}
+
+impl FieldType {
+    pub fn is_numeric(self) -> bool {
+        matches!(self, FieldType::U32 | FieldType::U64)
+    }
+
+    pub fn is_list(self) -> bool {
+        self == FieldType::StrList
+    }
+
+    /// Largest value a numeric field of this type can hold
+    pub fn int_max(self) -> u64 {
+        match self {
+            FieldType::U32 => u32::MAX as u64,
+            FieldType::U64 | FieldType::Str | FieldType::StrList => u64::MAX,
+        }
+    }
+}
+
+pub trait RecordField: Debug + Copy + Eq + Hash + 'static {
+    type Record: Record<Field = Self>;
+
+    /// Every field of the record
+    const ALL: &'static [Self];
+
+    /// Name used in queries
+    fn name(self) -> &'static str;
+
+    fn field_type(self) -> FieldType;
+
+    /// Name of the getter the `functions` crate exports for the field, which takes a
+    /// reference to the record and returns a `&str`, `u32` or `u64` by the field's type. A
+    /// missing value reads as an empty string or 0. For list fields it returns the number of
+    /// elements as a `u64`, see [`element_getter`](Self::element_getter) for the elements.
+    fn getter(self) -> &'static str;
+
+    /// Name of the getter the `functions` crate exports for one element of a list field, which
+    /// takes a reference to the record and an index as a `u64`, and returns a `&str`. `None`
+    /// for fields that aren't lists.
+    fn element_getter(self) -> Option<&'static str> {
+        None
+    }
+
+    /// Name of the getter the `functions` crate exports for whether a nullable field has a
+    /// value, which takes a reference to the record and returns a `bool`. `None` for fields
+    /// that always have a value.
+    fn presence_getter(self) -> Option<&'static str> {
+        None
+    }
+
+    /// Keys the field is looked up by in records without a fixed layout, like
+    /// [`Document`](crate::document::Document)s. Compiled code then looks the path up one key
+    /// at a time with the record's `lookup` export, and the getters take the value found there
+    /// (or null if there's none) instead of the record.
+    fn path(self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    fn is_numeric(self) -> bool {
+        self.field_type().is_numeric()
+    }
+
+    fn is_nullable(self) -> bool {
+        self.presence_getter().is_some()
+    }
+
+    fn is_list(self) -> bool {
+        self.field_type().is_list()
+    }
+
+    /// Look up a field by the name used in queries
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|field| field.name() == name)
+    }
+}
+
+pub trait Record: Clone + 'static {
+    type Field: RecordField<Record = Self>;
+
+    /// Prefix of the signatures and runners the `functions` crate exports for this record
+    /// type, see `record_functions!` there
+    const SYMBOL_PREFIX: &'static str;
+
+    /// Panics if `field` isn't a string field. A missing value reads as an empty string.
+    fn str_field(&self, field: Self::Field) -> &str;
+
+    /// Panics if `field` isn't a numeric field. A missing value reads as 0.
+    fn int_field(&self, field: Self::Field) -> u64;
+
+    /// Whether the field has a value, always true for fields that aren't nullable
+    fn has_value(&self, _field: Self::Field) -> bool {
+        true
+    }
+
+    /// Panics if `field` isn't a list field
+    fn str_list_field(&self, field: Self::Field) -> &[String] {
+        panic!("{:?} is not a list field", field)
+    }
+}
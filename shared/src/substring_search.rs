@@ -0,0 +1,102 @@
+//! Boyer-Moore-Horspool substring search with a skip table that's built once per needle.
+//!
+//! `str::contains` sets up its searcher again on every call, which adds up when the same
+//! constant needle is searched for in every user. The JIT builds the table with
+//! [`skip_table`] at compile time and embeds it, so only [`contains_with_table`] runs per user.
+
+/// For every byte, how far the search window can move forward when the window's last byte is
+/// that byte.
+pub type SkipTable = [u32; 256];
+
+/// Build the skip table for `needle`.
+pub fn skip_table(needle: &[u8]) -> SkipTable {
+    let mut table = [needle.len() as u32; 256];
+    if let Some((_, rest)) = needle.split_last() {
+        for (index, &byte) in rest.iter().enumerate() {
+            table[byte as usize] = (needle.len() - 1 - index) as u32;
+        }
+    }
+    table
+}
+
+/// Whether `needle` appears anywhere in `haystack`, `table` must be built from `needle`.
+pub fn contains_with_table(haystack: &[u8], needle: &[u8], table: &SkipTable) -> bool {
+    let Some((&last, rest)) = needle.split_last() else {
+        return true;
+    };
+
+    let mut start = 0;
+    while start + needle.len() <= haystack.len() {
+        // Check the last byte first, as it's also the one the skip is based on
+        let window_last = haystack[start + rest.len()];
+        if window_last == last && &haystack[start..start + rest.len()] == rest {
+            return true;
+        }
+        start += table[window_last as usize] as usize;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(haystack: &str, needle: &str) -> bool {
+        let table = skip_table(needle.as_bytes());
+        contains_with_table(haystack.as_bytes(), needle.as_bytes(), &table)
+    }
+
+    #[test]
+    fn matches_str_contains() {
+        let cases = [
+            ("", ""),
+            ("abc", ""),
+            ("", "a"),
+            ("ab", "abc"),
+            ("abc", "abc"),
+            ("abcd", "d"),
+            ("abcd", "cd"),
+            ("abcd", "e"),
+            ("aaab", "aab"),
+            ("aabaab", "aab"),
+            ("abababac", "ababac"),
+            ("abababab", "ababac"),
+            ("a.b@example.com", "@example.com"),
+            ("a.b@example.co", "@example.com"),
+            ("Straße", "ß"),
+            ("Straße", "\u{df}e"),
+            ("Strasse", "ß"),
+            ("مرحبا", "حب"),
+            ("مرحبا", "بح"),
+            ("x\u{10400}y", "\u{10400}"),
+            // Shares its last byte with `é`, but not the one before it
+            ("\u{e9}", "\u{129}"),
+        ];
+        for (haystack, needle) in cases {
+            assert_eq!(
+                contains(haystack, needle),
+                haystack.contains(needle),
+                "{:?} in {:?}",
+                needle,
+                haystack
+            );
+        }
+    }
+
+    #[test]
+    fn matches_str_contains_at_every_position() {
+        let haystack = "the quick brown fox jumps over the lazy dog, ünïcödé";
+        for start in 0..haystack.len() {
+            for end in start..=haystack.len() {
+                let Some(needle) = haystack.get(start..end) else {
+                    continue;
+                };
+                assert!(contains(haystack, needle), "{:?}", needle);
+                // And with a byte after it, which is only there for some needles
+                let missing = [needle, "!"].concat();
+                assert_eq!(contains(haystack, &missing), haystack.contains(&*missing));
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Bounded collection of the first `limit` rows in some order, without keeping every match.
+//!
+//! Rows are kept in a max-heap ordered by the `less` function they're pushed with, so the
+//! current worst row is always at the top and can be replaced in `O(log limit)`.
+
+use alloc::vec::Vec;
+
+pub struct TopK<'a, T> {
+    pub limit: usize,
+    pub rows: Vec<&'a T>,
+}
+
+impl<'a, T> TopK<'a, T> {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.rows.len() >= self.limit
+    }
+
+    /// Add a row if there's still room, for when the order doesn't matter
+    pub fn push_unordered(&mut self, row: &'a T) {
+        if !self.is_full() {
+            self.rows.push(row);
+        }
+    }
+
+    /// Add a row, dropping the worst row if there's no room left
+    pub fn push(&mut self, row: &'a T, less: impl Fn(&T, &T) -> bool) {
+        if !self.is_full() {
+            self.rows.push(row);
+            self.sift_up(self.rows.len() - 1, &less);
+        } else if self.limit > 0 && less(row, self.rows[0]) {
+            self.rows[0] = row;
+            self.sift_down(0, self.rows.len(), &less);
+        }
+    }
+
+    /// Sort the heap in place, leaving the rows in ascending order
+    pub fn sort(&mut self, less: impl Fn(&T, &T) -> bool) {
+        for end in (1..self.rows.len()).rev() {
+            self.rows.swap(0, end);
+            self.sift_down(0, end, &less);
+        }
+    }
+
+    fn sift_up(&mut self, mut index: usize, less: &impl Fn(&T, &T) -> bool) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if !less(self.rows[parent], self.rows[index]) {
+                break;
+            }
+            self.rows.swap(parent, index);
+            index = parent;
+        }
+    }
+
+    /// Only the first `len` rows are treated as part of the heap
+    fn sift_down(&mut self, mut index: usize, len: usize, less: &impl Fn(&T, &T) -> bool) {
+        loop {
+            let mut largest = index;
+            for child in [index * 2 + 1, index * 2 + 2] {
+                if child < len && less(self.rows[largest], self.rows[child]) {
+                    largest = child;
+                }
+            }
+            if largest == index {
+                break;
+            }
+            self.rows.swap(index, largest);
+            index = largest;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::ptr;
+
+    use super::*;
+
+    /// By key, then by position in the slice like the runner's comparisons
+    fn less(a: &u32, b: &u32) -> bool {
+        (a, a as *const u32) < (b, b as *const u32)
+    }
+
+    fn top_k(rows: &[u32], limit: usize) -> Vec<&u32> {
+        let mut top_k = TopK::new(limit);
+        for row in rows {
+            top_k.push(row, less);
+        }
+        top_k.sort(less);
+        top_k.rows
+    }
+
+    fn sort_then_truncate(rows: &[u32], limit: usize) -> Vec<&u32> {
+        let mut sorted = rows.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| (*a, *a as *const u32).cmp(&(*b, *b as *const u32)));
+        sorted.truncate(limit);
+        sorted
+    }
+
+    /// Whether both hold the same rows (not just equal keys) in the same order
+    fn same_rows(a: &[&u32], b: &[&u32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| ptr::eq(*a, *b))
+    }
+
+    /// Deterministic pseudo random keys from a small range, so there are duplicates
+    fn keys(len: usize, range: u32, seed: u32) -> Vec<u32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) % range
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limit_zero_keeps_nothing() {
+        let rows = [3, 1, 2];
+        assert!(top_k(&rows, 0).is_empty());
+
+        let mut top_k = TopK::new(0);
+        assert!(top_k.is_full());
+        for row in &rows {
+            top_k.push_unordered(row);
+        }
+        assert!(top_k.rows.is_empty());
+    }
+
+    #[test]
+    fn limit_larger_than_input_keeps_every_row() {
+        let rows = [5, 3, 9, 1, 3];
+        let sorted = top_k(&rows, 100);
+        assert_eq!(sorted, [&1, &3, &3, &5, &9]);
+        assert!(same_rows(&sorted, &sort_then_truncate(&rows, 100)));
+        assert!(top_k(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn equal_keys_keep_the_first_rows() {
+        let rows = [7; 20];
+        let sorted = top_k(&rows, 5);
+        let first = rows[..5].iter().collect::<Vec<_>>();
+        assert!(same_rows(&sorted, &first));
+
+        // Ties between the kept and dropped rows go to the earlier row
+        let rows = [2, 1, 2, 0, 2, 1, 2];
+        let sorted = top_k(&rows, 4);
+        assert!(same_rows(
+            &sorted,
+            &[&rows[3], &rows[1], &rows[5], &rows[0]]
+        ));
+    }
+
+    #[test]
+    fn agrees_with_sort_then_truncate() {
+        for seed in 0..20 {
+            for (len, range) in [(1, 1), (10, 3), (100, 10), (257, 1000), (500, 2)] {
+                let rows = keys(len, range, seed);
+                for limit in [1, 2, 3, 7, len / 2, len - 1, len, len + 1] {
+                    assert!(
+                        same_rows(&top_k(&rows, limit), &sort_then_truncate(&rows, limit)),
+                        "seed {} len {} range {} limit {}",
+                        seed,
+                        len,
+                        range,
+                        limit
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unordered_pushes_keep_the_first_rows() {
+        let rows = [4, 2, 8, 6];
+        let mut top_k = TopK::new(2);
+        for row in &rows {
+            top_k.push_unordered(row);
+        }
+        assert!(top_k.is_full());
+        assert!(same_rows(&top_k.rows, &[&rows[0], &rows[1]]));
+    }
+}